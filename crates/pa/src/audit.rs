@@ -0,0 +1,56 @@
+use std::fmt::Write as _;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use camino::Utf8Path;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::current_timestamp;
+
+/// One line of the `audit_log`: what was rendered, never the rendered text itself.
+#[derive(Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    prompt: &'a str,
+    version: &'a str,
+    source: &'a str,
+    output_sha256: String,
+}
+
+/// Append a JSON line recording that `prompt`, defined in `source`, rendered `output`.
+pub(crate) fn append_audit_record(
+    log: &Utf8Path,
+    prompt: &str,
+    source: &Utf8Path,
+    output: &str,
+) -> Result<()> {
+    let record = AuditRecord {
+        timestamp: current_timestamp(),
+        prompt,
+        version: env!("CARGO_PKG_VERSION"),
+        source: source.as_str(),
+        output_sha256: Sha256::digest(output.as_bytes()).iter().fold(
+            String::with_capacity(64),
+            |mut hex, byte| {
+                let _ = write!(hex, "{byte:02x}");
+                hex
+            },
+        ),
+    };
+    let mut line = serde_json::to_string(&record)?;
+    line.push('\n');
+
+    if let Some(parent) = log.parent().filter(|parent| !parent.as_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create audit log directory {parent}"))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .with_context(|| format!("failed to open audit log {log}"))?;
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("failed to append to audit log {log}"))
+}
@@ -1,22 +1,49 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ffi::OsString;
 use std::fs;
-use std::io::{self, Write};
-use std::process;
-use std::time::SystemTime;
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+use std::process::{self, ExitCode};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, Result, anyhow, bail};
 use camino::{Utf8Path, Utf8PathBuf};
-use clap::{Args, CommandFactory, Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use clap_complete::{Shell, generate};
 use directories::BaseDirs;
+use indexmap::IndexMap;
+#[cfg(feature = "keyring")]
+use prompt_assembler::KeyringStore;
 use prompt_assembler::{
-    ConfigIssue, LoadConfigError, PromptAssembler, PromptKind, PromptPart, PromptProfile,
-    PromptSpec, PromptVariable, StructuredData,
+    Config, ConfigIssue, DataFormat, FragmentTiming, LayoutOptions, LoadConfigError, LoadOptions,
+    MissingFragmentPolicy, OutputEscape, PromptAssembler, PromptKind, PromptPart, PromptProfile,
+    PromptSpec, PromptVariable, RenderError, RenderOptions, RenderOutput, SecretRef,
+    StructuredData, TemplateDependency, load_structured_data,
 };
 use serde::Serialize;
+use thiserror::Error;
 use time::{OffsetDateTime, format_description::well_known::Rfc3339};
 
+mod audit;
+mod pager;
+#[cfg(all(unix, feature = "serve"))]
+mod serve;
+mod sink;
+mod updater;
+mod wizard;
+
+use sink::OutputArgs;
+
 const SCHEMA_VERSION: u8 = 1;
 const DEFAULT_CONFIG: &[u8] = include_bytes!("../../../assets/default_config.toml");
+const EXAMPLE_CONFIG: &str = r#"[prompt.example]
+description = "Example prompt created by pa init"
+prompts = ["example.md"]
+"#;
+const EXAMPLE_FRAGMENT: &str = "Hello {0}!\n";
+const AUTO_INIT_ENV: &str = "PA_AUTO_INIT";
+const DEFAULT_PROMPT_ENV: &str = "PA_DEFAULT_PROMPT";
+const DEFAULT_POST_PROCESS_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[derive(Parser, Debug)]
 #[command(
@@ -34,174 +61,1590 @@ struct Cli {
     prompt: Option<String>,
     #[arg(value_name = "ARG", trailing_var_arg = true)]
     prompt_args: Vec<String>,
+    #[command(flatten)]
+    run: RunArgs,
+    /// Run the prompt's `post_process` command on the rendered output
+    #[arg(long)]
+    allow_post_process: bool,
+    /// Print the config directory pa reads from and exit
+    #[arg(long, exclusive = true)]
+    print_config_path: bool,
+    #[command(flatten)]
+    global: GlobalArgs,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+struct GlobalArgs {
+    /// Use cached copies of remote fragments and never touch the network
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Skip the config directory entirely; `parts` then resolves files from the current directory
+    #[arg(long, global = true)]
+    no_config: bool,
+    /// Report failures on stderr as a JSON object with an exit code per error kind
+    #[arg(long, global = true)]
+    json_errors: bool,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+struct RunArgs {
+    /// Wrap the rendered output in a JSON envelope
+    #[arg(long)]
+    json: bool,
+    #[command(flatten)]
+    fragments: FragmentHandling,
+    #[command(flatten)]
+    args: ArgHandling,
+    /// Append a comment naming the prompt and render time, as `--annotate[=html|hash]`
+    #[arg(
+        long,
+        value_name = "STYLE",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "html"
+    )]
+    annotate: Option<CommentStyle>,
+    /// Escape the final output; defaults to the prompt's `escape` setting, else none
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "json")]
+    escape: Option<EscapeMode>,
+    /// Print the output as text, or as a JSON array of chat messages split at role-tagged
+    /// sequence entries
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        default_value = "text",
+        conflicts_with_all = ["json", "escape", "annotate", "each"]
+    )]
+    format: RenderFormat,
+    /// Print how long each fragment took to read and render to stderr
+    #[arg(long)]
+    trace_render: bool,
+    /// Strip leading whitespace shared by every output line
+    #[arg(long)]
+    dedent: bool,
+    /// Strip one trailing newline from the output, or all trailing whitespace with `--trim=all`
+    #[arg(
+        long,
+        value_name = "MODE",
+        value_enum,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "newline"
+    )]
+    trim: Option<TrimMode>,
+    #[command(flatten)]
+    batch: BatchArgs,
+    #[command(flatten)]
+    context: ContextArgs,
+    #[command(flatten)]
+    sinks: OutputArgs,
+}
+
+/// Extra template context and inspecting it.
+#[derive(Args, Debug, Clone, Default)]
+struct ContextArgs {
+    /// Expose the OS keyring entry for service/account as the context entry KEY (repeatable)
+    #[arg(long = "secret", value_name = "KEY=SERVICE/ACCOUNT")]
+    secrets: Vec<SecretRef>,
+    /// Start the template context from one of the prompt's `presets`; data overrides it
+    #[arg(long, value_name = "NAME")]
+    preset: Option<String>,
+    /// Ask on the terminal for each declared var the data does not supply, then render
+    #[arg(long)]
+    interactive: bool,
+    /// Print the template context as JSON instead of rendering; secrets are redacted
+    #[arg(long, conflicts_with = "each")]
+    dump_context: bool,
+}
+
+/// Rendering a template once per element of its data.
+#[derive(Args, Debug, Clone, Default)]
+struct BatchArgs {
+    /// Render a template once per element of a JSON array data file, each element as the context
+    #[arg(long)]
+    each: bool,
+    /// Text placed between the renders produced by `--each`
+    #[arg(long, value_name = "SEP", default_value = "\n", requires = "each")]
+    each_separator: String,
+}
+
+/// How sequence fragments are read and joined.
+#[derive(Args, Debug, Clone, Default)]
+struct FragmentHandling {
+    /// Join fragments byte-for-byte without adding newlines between them
+    #[arg(long)]
+    binary: bool,
+    /// Replace invalid UTF-8 in fragments with U+FFFD instead of failing
+    #[arg(long)]
+    lossy: bool,
+    /// How to treat sequence fragments that do not exist
+    #[arg(long, value_enum, value_name = "POLICY", default_value_t = OnMissing::Error)]
+    on_missing: OnMissing,
+    /// Leave out the `global_prepend` and `global_append` fragments
+    #[arg(long)]
+    no_global: bool,
+}
+
+/// How the prompt's own positional arguments are interpreted.
+#[derive(Args, Debug, Clone, Default)]
+struct ArgHandling {
+    /// Split the last argument on DELIM into several positional arguments
+    #[arg(long, value_name = "DELIM", alias = "prompt-arg-separator")]
+    split_args: Option<String>,
+    /// Pass a first argument ending in .json, .toml, .yaml, or .yml to a sequence prompt as plain text
+    #[arg(long)]
+    literal_args: bool,
+    /// Fill the `{NAME}` placeholder in sequence fragments with VALUE (repeatable)
+    #[arg(long = "set", value_name = "NAME=VALUE")]
+    set: Vec<String>,
+    /// Set KEY to VALUE in a template's context, over the data file, or fill `{KEY}` in a
+    /// sequence (repeatable)
+    #[arg(long = "var", value_name = "KEY=VALUE")]
+    var: Vec<String>,
+    /// Parse stdin as JSON and expose it to a template prompt as the context entry KEY
+    #[arg(long, value_name = "KEY")]
+    stdin_json_key: Option<String>,
+    /// Warn about missing arguments and required vars instead of failing, and render anyway
+    #[arg(long)]
+    force: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OnMissing {
+    /// Leave the fragment out with a warning
+    Skip,
+    /// Render the fragment as empty
+    Empty,
+    /// Fail the render
+    #[default]
+    Error,
+}
+
+impl From<OnMissing> for MissingFragmentPolicy {
+    fn from(value: OnMissing) -> Self {
+        match value {
+            OnMissing::Skip => MissingFragmentPolicy::Skip,
+            OnMissing::Empty => MissingFragmentPolicy::Empty,
+            OnMissing::Error => MissingFragmentPolicy::Error,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum RenderFormat {
+    /// The assembled prompt as plain text
+    #[default]
+    Text,
+    /// A JSON array of `{role, content}` objects
+    Messages,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum EscapeMode {
+    /// Print the output as rendered
+    None,
+    /// Print the output as a JSON string literal
+    Json,
+    /// Print the output as a single-quoted shell word
+    Shell,
+}
+
+impl From<EscapeMode> for OutputEscape {
+    fn from(value: EscapeMode) -> Self {
+        match value {
+            EscapeMode::None => OutputEscape::None,
+            EscapeMode::Json => OutputEscape::Json,
+            EscapeMode::Shell => OutputEscape::Shell,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum TrimMode {
+    /// A single trailing `\n` or `\r\n`
+    Newline,
+    /// Every trailing whitespace character
+    All,
+}
+
+impl TrimMode {
+    fn apply(self, output: &mut String) {
+        let keep = match self {
+            TrimMode::Newline => output
+                .strip_suffix('\n')
+                .map_or(output.as_str(), |rest| {
+                    rest.strip_suffix('\r').unwrap_or(rest)
+                })
+                .len(),
+            TrimMode::All => output.trim_end().len(),
+        };
+        output.truncate(keep);
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum CommentStyle {
+    /// `<!-- ... -->`
+    Html,
+    /// `# ...`
+    Hash,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Human,
+    Json,
+    Jsonl,
+    Toml,
+    Compact,
+}
+
+#[derive(Args, Debug, Clone)]
+struct FormatArgs {
+    /// Output format
+    #[arg(long, value_enum, default_value_t = OutputFormat::Human)]
+    format: OutputFormat,
+    /// Deprecated alias for `--format json`
+    #[arg(long, conflicts_with = "format")]
+    json: bool,
+}
+
+impl FormatArgs {
+    fn format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.format
+        }
+    }
 }
 
 #[derive(Args, Debug, Clone)]
 struct ListArgs {
+    #[command(flatten)]
+    output: FormatArgs,
+    /// Only list prompts named PREFIX or nested under `PREFIX.`
+    #[arg(long, value_name = "PREFIX")]
+    group: Option<String>,
+    /// Indent dotted prompt names under their group
     #[arg(long)]
-    json: bool,
+    tree: bool,
+    /// List each prompt's vars with their type, whether required, and description
+    #[arg(long, alias = "vars", conflicts_with = "tree")]
+    detailed: bool,
+    /// Report prompts skipped by `when_os`/`when_env` on stderr
+    #[arg(long, short)]
+    verbose: bool,
+    #[command(flatten)]
+    paths: PathArgs,
 }
 
 #[derive(Args, Debug, Clone)]
 struct ShowArgs {
     #[arg(value_name = "PROMPT")]
     name: String,
+    #[command(flatten)]
+    output: FormatArgs,
+    /// Also render the template with DATA (JSON, TOML, or YAML) and show the result
+    #[arg(long, value_name = "DATA")]
+    preview: Option<String>,
+    #[command(flatten)]
+    paths: PathArgs,
+    /// Print directly instead of paging long output through `$PAGER`
     #[arg(long)]
-    json: bool,
+    no_pager: bool,
+}
+
+/// How file paths are written in structured output.
+#[derive(Args, Debug, Clone)]
+struct PathArgs {
+    /// Write source and profile paths as absolute paths
+    #[arg(long)]
+    absolute_paths: bool,
+}
+
+impl PathArgs {
+    fn apply(&self, mut prompt: JsonPrompt) -> JsonPrompt {
+        if !self.absolute_paths {
+            return prompt;
+        }
+        prompt.source_path = absolute_path(&prompt.source_path);
+        if let Some(profile) = &mut prompt.profile {
+            for part in profile.parts.iter_mut().chain(profile.template.as_mut()) {
+                part.path = absolute_path(&part.path);
+            }
+        }
+        prompt
+    }
 }
 
 #[derive(Args, Debug, Clone)]
 struct ValidateArgs {
+    #[command(flatten)]
+    output: FormatArgs,
+    /// Only report diagnostics for this prompt (repeatable)
+    #[arg(long = "prompt", value_name = "NAME")]
+    prompts: Vec<String>,
+    /// Only report diagnostics for prompts carrying this tag (repeatable)
+    #[arg(long = "tag", value_name = "TAG")]
+    tags: Vec<String>,
+}
+
+#[derive(Args, Debug, Clone)]
+struct OrphansArgs {
+    #[command(flatten)]
+    output: FormatArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+struct DepsArgs {
+    /// Template prompt to inspect
+    name: String,
+    #[command(flatten)]
+    output: FormatArgs,
+}
+
+#[derive(Args, Debug, Clone)]
+struct NewArgs {
+    /// Name of the prompt to create
+    #[arg(value_name = "PROMPT")]
+    name: String,
+    /// Kind of prompt to scaffold
+    #[arg(long, value_enum, default_value_t = NewKind::Sequence)]
+    kind: NewKind,
+    /// Write the definition to `conf.d/<PROMPT>.toml` instead of appending to config.toml
     #[arg(long)]
-    json: bool,
+    conf_d: bool,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum NewKind {
+    /// A sequence prompt with one `<PROMPT>.md` fragment
+    Sequence,
+    /// A template prompt with one `<PROMPT>.j2` template
+    Template,
+}
+
+#[derive(Args, Debug, Clone)]
+struct RenderArgs {
+    /// Prompt to render
+    #[arg(value_name = "PROMPT")]
+    name: String,
+    /// Data file for a template prompt; arguments are never taken as data
+    #[arg(long, value_name = "FILE")]
+    data: Option<String>,
+    /// Argument for the prompt's placeholders, in order (repeatable)
+    #[arg(long, value_name = "VALUE", allow_hyphen_values = true)]
+    arg: Vec<String>,
+    #[command(flatten)]
+    run: RunArgs,
+    /// Run the prompt's `post_process` command on the rendered output
+    #[arg(long)]
+    allow_post_process: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+struct ServeArgs {
+    /// Socket to listen on; defaults to `pa.sock` in the config directory
+    #[arg(long, value_name = "PATH")]
+    socket: Option<Utf8PathBuf>,
+    /// Reload the configuration before a request when its files changed
+    #[arg(long)]
+    watch_config: bool,
+}
+
+#[derive(Args, Debug, Clone)]
+struct TagsArgs {
+    #[command(flatten)]
+    output: FormatArgs,
+}
+
+impl ValidateArgs {
+    fn is_scoped(&self) -> bool {
+        !self.prompts.is_empty() || !self.tags.is_empty()
+    }
+
+    /// Keep issues that are not tied to a prompt, plus those for prompts in scope.
+    fn retain_in_scope(
+        &self,
+        issues: &mut Vec<ConfigIssue>,
+        tags_of: impl Fn(&str) -> Option<Vec<String>>,
+    ) {
+        if !self.is_scoped() {
+            return;
+        }
+        issues.retain(|issue| {
+            let Some(name) = issue.prompt.as_deref() else {
+                return true;
+            };
+            self.prompts.iter().any(|prompt| prompt == name)
+                || tags_of(name).is_some_and(|tags| tags.iter().any(|tag| self.tags.contains(tag)))
+        });
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+struct InitArgs {
+    /// Also create an empty conf.d directory
+    #[arg(long)]
+    conf_d: bool,
+    /// Also create an example prompt and fragment
+    #[arg(long)]
+    example: bool,
 }
 
 #[derive(Args, Debug, Clone)]
 struct SelfUpdateArgs {
-    #[arg(long, value_name = "TAG")]
+    #[arg(long, value_name = "TAG", conflicts_with = "rollback")]
     version: Option<String>,
+    /// Reinstall the version that was running before the last self-update
+    #[arg(long)]
+    rollback: bool,
+    /// Give up after SECS seconds (0 waits indefinitely)
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    timeout: u64,
+    /// Download the release built for this target triple instead of the running one
+    #[arg(long, value_name = "TRIPLE")]
+    target: Option<String>,
+    /// Install the first release asset whose name matches this glob
+    #[arg(long, value_name = "GLOB")]
+    asset_pattern: Option<String>,
 }
 
 #[derive(Subcommand, Debug)]
 enum Commands {
+    /// Create the configuration directory and default files
+    Init(InitArgs),
     /// List available prompts
     List(ListArgs),
     /// Show prompt metadata
     Show(ShowArgs),
     /// Validate configuration files
     Validate(ValidateArgs),
+    /// List library files that no prompt references
+    Orphans(OrphansArgs),
+    /// List every tag in the library with how many prompts use it
+    Tags(TagsArgs),
+    /// Print the tree of templates a template prompt includes and imports
+    Deps(DepsArgs),
+    /// Render a prompt with an explicit data file and arguments
+    Render(Box<RenderArgs>),
+    /// Add a prompt to the configuration and create its empty fragment or template
+    New(NewArgs),
+    /// Render prompts for other tools over a Unix socket until stopped
+    Serve(ServeArgs),
     /// Update pa to the latest released version
     SelfUpdate(SelfUpdateArgs),
     /// Generate shell completions
-    Completions { shell: String },
-    /// Concatenate raw prompt parts without placeholder substitution
-    Parts {
-        #[arg(value_name = "FILE", num_args = 1..)]
-        files: Vec<String>,
+    Completions {
+        shell: String,
+        /// Look up prompt names with `pa list` at completion time instead of embedding them
+        #[arg(long)]
+        dynamic: bool,
+    },
+    /// Open the config directory, or a prompt's directory, in the file manager
+    Open {
+        /// Prompt whose resolved `prompt_path` to open instead of the config directory
+        #[arg(value_name = "PROMPT")]
+        target: Option<String>,
+        /// Print the directory instead of opening it
+        #[arg(long)]
+        print: bool,
     },
+    /// Concatenate raw prompt parts without placeholder substitution
+    Parts(PartsArgs),
 }
 
-fn main() -> Result<()> {
+#[derive(Args, Debug, Clone)]
+struct PartsArgs {
+    #[arg(value_name = "FILE", num_args = 1..)]
+    files: Vec<String>,
+    /// Emit each resolved part and the combined output as JSON
+    #[arg(long)]
+    json: bool,
+    /// Read up to N parts in parallel; output keeps the order given
+    #[arg(long, value_name = "N", default_value_t = NonZeroUsize::MIN)]
+    concurrency: NonZeroUsize,
+    /// Escape the combined output
+    #[arg(long, value_enum, value_name = "MODE", conflicts_with = "json")]
+    escape: Option<EscapeMode>,
+    /// Resolve relative parts against DIR instead of the current directory
+    #[arg(long, value_name = "DIR")]
+    root: Option<Utf8PathBuf>,
+    #[command(flatten)]
+    sinks: OutputArgs,
+}
+
+fn main() -> ExitCode {
+    let mut cli = Cli::parse();
+    let argv: Vec<OsString> = std::env::args_os().collect();
+    unname_prompt_after_separator(&mut cli, &argv);
+    let json_errors = cli.global.json_errors;
+    match run_cli(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) if json_errors => {
+            let kind = ErrorKind::classify(&err);
+            let envelope = ErrorEnvelope {
+                error: JsonError {
+                    kind: kind.as_str(),
+                    message: format!("{err:#}"),
+                },
+            };
+            match serde_json::to_string(&envelope) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprintln!("Error: {err:?}"),
+            }
+            ExitCode::from(kind.exit_code())
+        }
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run_cli(cli: Cli) -> Result<()> {
     let Cli {
         command,
         prompt,
         prompt_args,
-    } = Cli::parse();
+        run,
+        allow_post_process,
+        print_config_path,
+        global: GlobalArgs {
+            offline, no_config, ..
+        },
+    } = cli;
+
+    if print_config_path {
+        println!("{}", discover_config_dir()?);
+        return Ok(());
+    }
+
+    if no_config {
+        let Some(Commands::Parts(args)) = command else {
+            bail!("--no-config is only supported by `pa parts`");
+        };
+        let assembler = PromptAssembler::from_config(Config::new(current_dir()?));
+        return run_parts(&assembler, &args);
+    }
 
     let config_dir = discover_config_dir()?;
-    ensure_config_initialized(config_dir.as_ref())?;
 
+    let needs_config = !matches!(command, Some(Commands::Init(_) | Commands::SelfUpdate(_)));
+    if needs_config {
+        if auto_init_enabled() {
+            ensure_config_initialized(config_dir.as_ref())?;
+        } else {
+            ensure_config_exists(config_dir.as_ref())?;
+        }
+    }
+
+    let config_dir = config_dir.as_ref();
     match command {
-        Some(Commands::List(args)) => {
-            handle_list(config_dir.as_ref(), &args)?;
+        Some(Commands::Init(args)) => handle_init(config_dir, &args),
+        Some(Commands::SelfUpdate(args)) => updater::handle_self_update(config_dir, &args),
+        Some(Commands::List(args)) => handle_list(config_dir, offline, &args),
+        Some(Commands::Show(args)) => handle_show(config_dir, offline, &args),
+        Some(Commands::Validate(args)) => handle_validate(config_dir, offline, &args),
+        Some(Commands::Orphans(args)) => {
+            run_orphans(&load_runtime_assembler(config_dir, offline)?, &args)
         }
-        Some(Commands::Show(args)) => {
-            handle_show(config_dir.as_ref(), &args)?;
+        Some(Commands::Tags(args)) => {
+            run_tags(&load_runtime_assembler(config_dir, offline)?, &args)
         }
-        Some(Commands::Validate(args)) => {
-            handle_validate(config_dir.as_ref(), &args)?;
+        Some(Commands::Deps(args)) => {
+            run_deps(&load_runtime_assembler(config_dir, offline)?, &args)
         }
-        Some(Commands::SelfUpdate(args)) => {
-            handle_self_update(&args)?;
+        Some(Commands::Render(args)) => handle_render(config_dir, offline, *args),
+        Some(Commands::New(args)) => handle_new(
+            config_dir,
+            &load_runtime_assembler(config_dir, offline)?,
+            &args,
+        ),
+        Some(Commands::Serve(args)) => run_serve(config_dir, offline, &args),
+        Some(Commands::Completions { shell, dynamic }) => {
+            handle_completions(config_dir, offline, &shell, dynamic)
         }
-        Some(Commands::Completions { shell }) => {
-            let assembler = load_runtime_assembler(config_dir.as_ref())?;
-            ensure_prompts_available(&assembler)?;
-            let shell = parse_shell(&shell)?;
-            generate_completions(shell, &assembler)?;
+        Some(Commands::Open { target, print }) => {
+            handle_open(config_dir, offline, target.as_deref(), print)
         }
-        Some(Commands::Parts { files }) => {
-            let assembler = load_runtime_assembler(config_dir.as_ref())?;
-            run_parts(&assembler, &files)?;
+        Some(Commands::Parts(args)) => {
+            run_parts(&load_runtime_assembler(config_dir, offline)?, &args)
         }
         None => {
-            let assembler = load_runtime_assembler(config_dir.as_ref())?;
+            let assembler = load_runtime_assembler(config_dir, offline)?;
             ensure_prompts_available(&assembler)?;
-            let prompt = prompt.ok_or_else(|| anyhow!("prompt name is required"))?;
-            run_prompt(&assembler, &prompt, prompt_args)?;
+            let (prompt, prompt_args) = select_prompt(&assembler, prompt, prompt_args)?;
+            let input = PromptInput::Guess(prompt_args);
+            run_prompt(&assembler, &prompt, input, &run, allow_post_process)
+        }
+    }
+}
+
+fn handle_render(config_dir: &Utf8Path, offline: bool, args: RenderArgs) -> Result<()> {
+    let assembler = load_runtime_assembler(config_dir, offline)?;
+    let RenderArgs {
+        name,
+        data,
+        arg,
+        run,
+        allow_post_process,
+    } = args;
+    let input = PromptInput::Explicit { data, args: arg };
+    run_prompt(&assembler, &name, input, &run, allow_post_process)
+}
+
+fn handle_completions(
+    config_dir: &Utf8Path,
+    offline: bool,
+    shell: &str,
+    dynamic: bool,
+) -> Result<()> {
+    let shell = parse_shell(shell)?;
+    if dynamic {
+        return generate_dynamic_completions(shell);
+    }
+    let assembler = load_runtime_assembler(config_dir, offline)?;
+    ensure_prompts_available(&assembler)?;
+    generate_completions(shell, &assembler)
+}
+
+fn handle_open(
+    config_dir: &Utf8Path,
+    offline: bool,
+    target: Option<&str>,
+    print: bool,
+) -> Result<()> {
+    let dir = match target {
+        Some(name) => {
+            let assembler = load_runtime_assembler(config_dir, offline)?;
+            if assembler.prompt_spec(name).is_none() {
+                return Err(UnknownPrompt::new(&assembler, name).into());
+            }
+            assembler.prompt_directory(name)?
         }
+        None => config_dir.to_owned(),
+    };
+    open_directory(&dir, print)
+}
+
+/// Pick the prompt to run. A named prompt runs as given, so an unknown name is reported with
+/// suggestions; without a name, `PA_DEFAULT_PROMPT` (else the `default_prompt` setting) runs
+/// with every word as its arguments.
+fn select_prompt(
+    assembler: &PromptAssembler,
+    prompt: Option<String>,
+    args: Vec<String>,
+) -> Result<(String, Vec<String>)> {
+    if let Some(name) = prompt {
+        return Ok((name, args));
+    }
+    let default = std::env::var(DEFAULT_PROMPT_ENV)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| assembler.settings().default_prompt.clone());
+    match default {
+        Some(default) => Ok((default, args)),
+        None => bail!(
+            "prompt name is required; set {DEFAULT_PROMPT_ENV} or default_prompt to run without one"
+        ),
+    }
+}
+
+/// `pa -- WORDS...` names no prompt, but clap still fills `prompt` from the first word after
+/// the separator. Move that word back in front of the arguments.
+fn unname_prompt_after_separator(cli: &mut Cli, argv: &[OsString]) {
+    if cli.command.is_some() {
+        return;
     }
+    let Some(separator) = argv.iter().skip(1).position(|arg| arg == "--") else {
+        return;
+    };
+    let first = argv.get(separator + 2).and_then(|arg| arg.to_str());
+    if cli.prompt.is_some() && cli.prompt.as_deref() == first {
+        let name = cli.prompt.take().unwrap_or_default();
+        cli.prompt_args.insert(0, name);
+    }
+}
+
+/// How the words after a prompt name become its data file and arguments.
+enum PromptInput {
+    /// `pa <prompt> ARGS...`: a first argument that looks like a data file is the data.
+    Guess(Vec<String>),
+    /// `pa render`: the data file comes only from `--data`, and every `--arg` is an argument.
+    Explicit {
+        data: Option<String>,
+        args: Vec<String>,
+    },
+}
 
+/// One prompt invocation: the prompt being run, its spec, and the flags it was run with.
+#[derive(Clone, Copy)]
+struct Invocation<'a> {
+    assembler: &'a PromptAssembler,
+    prompt: &'a str,
+    spec: &'a PromptSpec,
+    run: &'a RunArgs,
+}
+
+impl Invocation<'_> {
+    fn is_template(&self) -> bool {
+        matches!(
+            self.spec.kind,
+            PromptKind::Template { .. } | PromptKind::InlineTemplate { .. }
+        )
+    }
+}
+
+fn run_prompt(
+    assembler: &PromptAssembler,
+    prompt: &str,
+    input: PromptInput,
+    run: &RunArgs,
+    allow_post_process: bool,
+) -> Result<()> {
+    let spec = assembler
+        .prompt_spec(prompt)
+        .ok_or_else(|| UnknownPrompt::new(assembler, prompt))?;
+    let invocation = Invocation {
+        assembler,
+        prompt,
+        spec,
+        run,
+    };
+    check_run_flags(&invocation, allow_post_process)?;
+
+    // The wizard reads its answers from stdin, so stdin is not taken as input.
+    let interactive = run.context.interactive && invocation.is_template() && wizard::available();
+    let mut stdin_arg = if interactive {
+        None
+    } else {
+        read_stdin_if_available()?
+    };
+    let options = render_options(&invocation, &mut stdin_arg)?;
+
+    let rendered = if invocation.is_template() {
+        let Some(rendered) =
+            render_template_input(&invocation, input, stdin_arg, options, interactive)?
+        else {
+            return Ok(());
+        };
+        rendered
+    } else {
+        render_positional_input(&invocation, input, stdin_arg, &options)?
+    };
+    deliver_rendered(&invocation, rendered)
+}
+
+/// Reject flags the prompt cannot honor before anything is read or rendered.
+fn check_run_flags(invocation: &Invocation<'_>, allow_post_process: bool) -> Result<()> {
+    let Invocation {
+        prompt, spec, run, ..
+    } = *invocation;
+    if spec.metadata.post_process.is_some() && !allow_post_process {
+        bail!(
+            "prompt '{prompt}' pipes its output through a post_process command; \
+             pass --allow-post-process to run it"
+        );
+    }
+
+    let is_template = invocation.is_template();
+    if run.batch.each && !is_template {
+        bail!("--each only applies to template prompts");
+    }
+    if !is_template && !run.context.secrets.is_empty() {
+        bail!("--secret only applies to template prompts");
+    }
+    if !is_template && run.context.dump_context {
+        bail!("--dump-context only applies to template prompts");
+    }
+    if !is_template && run.context.preset.is_some() {
+        bail!("--preset only applies to template prompts");
+    }
+    if !is_template && run.args.stdin_json_key.is_some() {
+        bail!("--stdin-json-key only applies to template prompts");
+    }
+    if run.format == RenderFormat::Messages && spec.metadata.post_process.is_some() {
+        bail!(
+            "--format messages cannot be used with prompt '{prompt}', which has a post_process command"
+        );
+    }
+    Ok(())
+}
+
+/// Build the render options from the run flags, moving piped stdin into the template context
+/// when `--stdin-json-key` or the prompt's `data_format` claims it.
+fn render_options(
+    invocation: &Invocation<'_>,
+    stdin_arg: &mut Option<String>,
+) -> Result<RenderOptions> {
+    let Invocation {
+        prompt, spec, run, ..
+    } = *invocation;
+    let is_template = invocation.is_template();
+    let mut options = RenderOptions {
+        layout: LayoutOptions {
+            exact: run.fragments.binary,
+            dedent: run.dedent,
+            no_global: run.fragments.no_global,
+        },
+        lossy: run.fragments.lossy,
+        on_missing: run.fragments.on_missing.into(),
+        vars: parse_pairs("--set", "NAME=VALUE", &run.args.set)?,
+        context: serde_json::Map::new(),
+        force: run.args.force,
+        trace: run.trace_render,
+        redact: BTreeSet::new(),
+        preset: run.context.preset.clone(),
+    };
+    insert_secrets(&mut options, &run.context.secrets)?;
+    if let Some(key) = &run.args.stdin_json_key {
+        let input = stdin_arg
+            .take()
+            .ok_or_else(|| anyhow!("--stdin-json-key {key} expects JSON on stdin"))?;
+        let value: serde_json::Value = serde_json::from_str(&input)
+            .with_context(|| format!("stdin for --stdin-json-key {key} is not valid JSON"))?;
+        options.context.insert(key.clone(), value);
+    } else if let Some(format) = spec.metadata.data_format
+        && is_template
+        && let Some(input) = stdin_arg.take()
+    {
+        // A prompt that declares its data format reads piped input as that data.
+        match format.parse_content(&input, "stdin")? {
+            serde_json::Value::Object(map) => options.context.extend(map),
+            _ => bail!(
+                "stdin for prompt '{prompt}' must hold a {} table",
+                format.as_str().to_uppercase()
+            ),
+        }
+    }
+    let cli_vars = parse_pairs("--var", "KEY=VALUE", &run.args.var)?;
+    if is_template {
+        options.context.extend(
+            cli_vars
+                .into_iter()
+                .map(|(key, value)| (key, serde_json::Value::String(value))),
+        );
+    } else {
+        options.vars.extend(cli_vars);
+    }
+    Ok(options)
+}
+
+/// Render a sequence or inline prompt, whose words and stdin are all positional arguments.
+fn render_positional_input(
+    invocation: &Invocation<'_>,
+    input: PromptInput,
+    stdin_arg: Option<String>,
+    options: &RenderOptions,
+) -> Result<RenderOutput> {
+    let Invocation {
+        assembler,
+        prompt,
+        run,
+        ..
+    } = *invocation;
+    let (mut positional_args, guess) = match input {
+        PromptInput::Guess(args) => (args, true),
+        PromptInput::Explicit { data: Some(_), .. } => {
+            bail!("prompt '{prompt}' does not accept structured data")
+        }
+        PromptInput::Explicit { args, .. } => (args, false),
+    };
+    split_trailing_arg(&mut positional_args, run.args.split_args.as_deref())?;
+    if let Some(input) = stdin_arg {
+        positional_args.insert(0, input);
+    }
+
+    if guess
+        && !run.args.literal_args
+        && positional_args
+            .first()
+            .is_some_and(|first| looks_like_data_file(first))
+    {
+        bail!(
+            "prompt '{prompt}' does not accept structured data; \
+             pass --literal-args to use the argument as text"
+        );
+    }
+    assembler.render_prompt_output(prompt, &positional_args, None, options)
+}
+
+/// Render a template prompt, taking its data file from `--data` or a leading argument and
+/// asking for missing vars when interactive. Returns `None` once `--dump-context` has printed
+/// the context instead.
+fn render_template_input(
+    invocation: &Invocation<'_>,
+    input: PromptInput,
+    stdin_arg: Option<String>,
+    mut options: RenderOptions,
+    interactive: bool,
+) -> Result<Option<RenderOutput>> {
+    let Invocation {
+        assembler,
+        prompt,
+        spec,
+        run,
+    } = *invocation;
+    let (data, mut remaining) = match input {
+        PromptInput::Explicit { data, args } => {
+            let data = data
+                .map(|raw| parse_data_argument(&raw, spec.metadata.data_format))
+                .transpose()?;
+            (data, args)
+        }
+        PromptInput::Guess(args) => {
+            let mut iter = args.into_iter().peekable();
+            // Context from stdin, a declared `data` file, or a preset stands in for the
+            // data argument, which becomes optional.
+            let data = if (options.context.is_empty()
+                && spec.metadata.data.is_none()
+                && options.preset.is_none()
+                && !interactive)
+                || iter.peek().is_some_and(|first| {
+                    looks_like_data_file(first)
+                        || (spec.metadata.data_format.is_some() && Utf8Path::new(first).is_file())
+                }) {
+                let data_arg = iter.next().ok_or_else(|| {
+                    anyhow!("prompt '{prompt}' requires a data file (JSON, TOML, or YAML)")
+                })?;
+                Some(parse_data_argument(&data_arg, spec.metadata.data_format)?)
+            } else {
+                None
+            };
+            (data, iter.collect())
+        }
+    };
+    split_trailing_arg(&mut remaining, run.args.split_args.as_deref())?;
+    if let Some(input) = stdin_arg {
+        remaining.insert(0, input);
+    }
+    if interactive && !assembler.input_requirements(prompt)?.is_empty() {
+        let missing = assembler.missing_inputs(prompt, &remaining, data.clone(), &options)?;
+        let answers =
+            wizard::ask_for_vars(&missing, &mut io::stdin().lock(), &mut io::stderr().lock())?;
+        options.context.extend(answers);
+    }
+    if run.context.dump_context {
+        let context = assembler.dump_context(prompt, &remaining, data, &options)?;
+        println!("{}", serde_json::to_string_pretty(&context)?);
+        return Ok(None);
+    }
+    let rendered = match data {
+        Some(data) if run.batch.each => {
+            render_each(assembler, prompt, &remaining, &data, &options, &run.batch)?
+        }
+        None if run.batch.each => bail!("--each needs an array data file"),
+        data => assembler.render_prompt_output(prompt, &remaining, data, &options)?,
+    };
+    Ok(Some(rendered))
+}
+
+/// Report warnings and traces, then post-process, trim, annotate, escape, and deliver the
+/// output, recording it in the audit log once delivered.
+fn deliver_rendered(invocation: &Invocation<'_>, rendered: RenderOutput) -> Result<()> {
+    let Invocation {
+        assembler,
+        prompt,
+        spec,
+        run,
+    } = *invocation;
+    for warning in &rendered.warnings {
+        eprintln!("warning: {warning}");
+    }
+    if run.trace_render {
+        print_render_trace(&rendered.timings);
+    }
+    if run.format == RenderFormat::Messages {
+        let messages: Vec<JsonMessage> = rendered
+            .messages
+            .iter()
+            .map(|message| JsonMessage {
+                role: message.role.as_str(),
+                content: &message.content,
+            })
+            .collect();
+        let mut output = serde_json::to_string_pretty(&messages)?;
+        output.push('\n');
+        run.sinks.deliver(&output)?;
+        return record_audit(assembler, prompt, spec, &output, run.sinks.dry_run);
+    }
+    let mut output = rendered.output;
+    if let Some(command) = &spec.metadata.post_process {
+        let timeout = spec
+            .metadata
+            .post_process_timeout
+            .map_or(DEFAULT_POST_PROCESS_TIMEOUT, Duration::from_secs);
+        if run.sinks.dry_run {
+            eprintln!(
+                "dry run: would pipe the output through post_process command `{command}` (timeout {}s)",
+                timeout.as_secs()
+            );
+        } else {
+            output = post_process(prompt, command, &output, timeout)?;
+        }
+    }
+    if let Some(mode) = run.trim {
+        mode.apply(&mut output);
+    }
+    if let Some(style) = run.annotate {
+        append_footer(&mut output, prompt, style);
+    }
+
+    if run.json {
+        print_render_json(assembler, prompt, &output, &run.sinks)?;
+    } else {
+        let escape = run
+            .escape
+            .map(OutputEscape::from)
+            .or(spec.metadata.escape)
+            .unwrap_or_default();
+        write_escaped(&output, escape, &run.sinks)?;
+    }
+    // Recorded only once the output has been delivered, so a failed write leaves no record.
+    record_audit(assembler, prompt, spec, &output, run.sinks.dry_run)
+}
+
+/// Append an audit record for `output` when the config sets `audit_log`; under `--dry-run`
+/// only report that it would.
+fn record_audit(
+    assembler: &PromptAssembler,
+    prompt: &str,
+    spec: &PromptSpec,
+    output: &str,
+    dry_run: bool,
+) -> Result<()> {
+    let Some(log) = &assembler.settings().audit_log else {
+        return Ok(());
+    };
+    if dry_run {
+        eprintln!("dry run: would append an audit record to {log}");
+        return Ok(());
+    }
+    audit::append_audit_record(log, prompt, &spec.metadata.source.path, output)
+}
+
+#[cfg(all(unix, feature = "serve"))]
+fn run_serve(config_dir: &Utf8Path, offline: bool, args: &ServeArgs) -> Result<()> {
+    let options = load_options(offline);
+    let socket = args
+        .socket
+        .clone()
+        .unwrap_or_else(|| config_dir.join("pa.sock"));
+    serve::run_serve(config_dir, &options, &socket, args.watch_config)
+}
+
+#[cfg(not(all(unix, feature = "serve")))]
+fn run_serve(_config_dir: &Utf8Path, _offline: bool, _args: &ServeArgs) -> Result<()> {
+    bail!("pa serve needs pa built with the `serve` feature on a Unix platform")
+}
+
+#[cfg(feature = "keyring")]
+fn insert_secrets(options: &mut RenderOptions, secrets: &[SecretRef]) -> Result<()> {
+    for secret in secrets {
+        options.insert_secret(secret, &KeyringStore)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "keyring"))]
+fn insert_secrets(_options: &mut RenderOptions, secrets: &[SecretRef]) -> Result<()> {
+    if let Some(secret) = secrets.first() {
+        bail!(
+            "cannot read secret '{}': pa was built without the `keyring` feature",
+            secret.key
+        );
+    }
     Ok(())
 }
 
-fn run_prompt(assembler: &PromptAssembler, prompt: &str, args: Vec<String>) -> Result<()> {
-    let kind = assembler
-        .prompt_kind(prompt)
-        .ok_or_else(|| anyhow!("unknown prompt: {prompt}"))?;
-
-    let stdin_arg = read_stdin_if_available()?;
+/// Render a template once per element of the array in `data`, using each element as the
+/// context root, and join the results with the `--each-separator`.
+fn render_each(
+    assembler: &PromptAssembler,
+    prompt: &str,
+    args: &[String],
+    data: &StructuredData,
+    options: &RenderOptions,
+    batch: &BatchArgs,
+) -> Result<RenderOutput> {
+    let path = data.path();
+    let serde_json::Value::Array(elements) = load_structured_data(data)? else {
+        bail!("--each expects {path} to contain an array");
+    };
+
+    let mut combined = RenderOutput::default();
+    for (index, element) in elements.into_iter().enumerate() {
+        let serde_json::Value::Object(mut context) = element else {
+            bail!("element {index} of {path} is not a table");
+        };
+        context.extend(options.context.clone());
+        let element_options = RenderOptions {
+            context,
+            ..options.clone()
+        };
+        let rendered = assembler
+            .render_prompt_output(prompt, args, None, &element_options)
+            .with_context(|| format!("failed to render element {index} of {path}"))?;
+        if index > 0 {
+            combined.output.push_str(&batch.each_separator);
+        }
+        combined.output.push_str(&rendered.output);
+        combined.warnings.extend(rendered.warnings);
+        combined.timings.extend(rendered.timings);
+    }
+    Ok(combined)
+}
+
+/// Print one line per traced fragment, slowest first, followed by the total.
+fn print_render_trace(timings: &[FragmentTiming]) {
+    let mut sorted: Vec<&FragmentTiming> = timings.iter().collect();
+    sorted.sort_by_key(|timing| std::cmp::Reverse(timing.read + timing.render));
+    let width = sorted
+        .iter()
+        .map(|timing| timing.fragment.len())
+        .max()
+        .unwrap_or(0)
+        .max("fragment".len());
+    eprintln!("{:<width$}  {:>10}  {:>10}", "fragment", "read", "render");
+    let mut total = Duration::ZERO;
+    for timing in sorted {
+        total += timing.read + timing.render;
+        eprintln!(
+            "{:<width$}  {:>10}  {:>10}",
+            timing.fragment,
+            format_duration(timing.read),
+            format_duration(timing.render),
+        );
+    }
+    eprintln!("total: {}", format_duration(total));
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Feed `input` to `command` on stdin and return what it writes to stdout.
+fn post_process(prompt: &str, command: &str, input: &str, timeout: Duration) -> Result<String> {
+    // Split like a POSIX shell so quoted arguments survive, but run the program directly:
+    // pipes, redirections, and variables are not interpreted.
+    let words = shlex::split(command).ok_or_else(|| {
+        anyhow!("prompt '{prompt}' has a post_process command with unbalanced quotes")
+    })?;
+    let (program, args) = words
+        .split_first()
+        .ok_or_else(|| anyhow!("prompt '{prompt}' has an empty post_process command"))?;
+    let mut child = process::Command::new(program)
+        .args(args)
+        .stdin(process::Stdio::piped())
+        .stdout(process::Stdio::piped())
+        .spawn()
+        .with_context(|| format!("failed to run post_process command '{command}'"))?;
+
+    // Write from another thread so a command that streams output cannot fill its stdout pipe
+    // while we are still blocked feeding stdin.
+    let mut stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow!("failed to open stdin for '{command}'"))?;
+    let input = input.to_owned();
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let mut stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow!("failed to open stdout for '{command}'"))?;
+    let reader = std::thread::spawn(move || {
+        let mut buffer = Vec::new();
+        stdout.read_to_end(&mut buffer).map(|_| buffer)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child
+            .try_wait()
+            .with_context(|| format!("failed to run post_process command '{command}'"))?
+        {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!(
+                "post_process command '{command}' for prompt '{prompt}' timed out after {}s",
+                timeout.as_secs()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    // A command that exits without reading all of its input breaks the pipe; its exit status
+    // is what decides success.
+    let _ = writer.join();
+    let stdout = reader
+        .join()
+        .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        .with_context(|| format!("failed to read output of post_process command '{command}'"))?;
+    if !status.success() {
+        bail!("post_process command '{command}' for prompt '{prompt}' failed with {status}");
+    }
+    String::from_utf8(stdout)
+        .map_err(|_| anyhow!("post_process command '{command}' produced invalid UTF-8"))
+}
+
+fn append_footer(output: &mut String, prompt: &str, style: CommentStyle) {
+    if !output.is_empty() && !output.ends_with('\n') {
+        output.push('\n');
+    }
+    let note = format!(
+        "generated by pa from prompt '{prompt}' at {}",
+        current_timestamp()
+    );
+    let footer = match style {
+        CommentStyle::Html => format!("<!-- {note} -->\n"),
+        CommentStyle::Hash => format!("# {note}\n"),
+    };
+    output.push_str(&footer);
+}
+
+fn split_trailing_arg(args: &mut Vec<String>, delimiter: Option<&str>) -> Result<()> {
+    let Some(delimiter) = delimiter else {
+        return Ok(());
+    };
+    if delimiter.is_empty() {
+        bail!("--split-args delimiter cannot be empty");
+    }
+    if let Some(last) = args.pop() {
+        args.extend(last.split(delimiter).map(str::to_owned));
+    }
+    Ok(())
+}
+
+/// Split each `NAME=VALUE` occurrence of `flag` on its first `=`.
+fn parse_pairs(flag: &str, shape: &str, raw: &[String]) -> Result<BTreeMap<String, String>> {
+    raw.iter()
+        .map(|pair| {
+            let (name, value) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid {flag} '{pair}': expected {shape}"))?;
+            Ok((name.to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Deliver `output` to `sinks`, escaped as a single literal on its own line unless `escape`
+/// is none.
+fn write_escaped(output: &str, escape: OutputEscape, sinks: &OutputArgs) -> Result<()> {
+    if escape == OutputEscape::None {
+        return sinks.deliver(output);
+    }
+    let mut escaped = escape.apply(output);
+    escaped.push('\n');
+    sinks.deliver(&escaped)
+}
+
+fn print_render_json(
+    assembler: &PromptAssembler,
+    prompt: &str,
+    output: &str,
+    sinks: &OutputArgs,
+) -> Result<()> {
+    let sources = assembler
+        .prompt_sources(prompt)?
+        .into_iter()
+        .map(Utf8PathBuf::into_string)
+        .collect();
+
+    let payload = RenderEnvelope {
+        schema_version: SCHEMA_VERSION,
+        generated_at: current_timestamp(),
+        name: prompt.to_owned(),
+        output: output.to_owned(),
+        sources,
+    };
+
+    let mut rendered = serde_json::to_string_pretty(&payload)?;
+    rendered.push('\n');
+    sinks.deliver(&rendered)
+}
+
+fn run_parts(assembler: &PromptAssembler, args: &PartsArgs) -> Result<()> {
+    let cwd = current_dir()?;
+    let working_dir = match &args.root {
+        Some(root) => {
+            let root = cwd.join(root);
+            if !root.is_dir() {
+                bail!("parts root {root} is not a directory");
+            }
+            root
+        }
+        None => cwd,
+    };
+    let parts =
+        assembler.read_parts_concurrently(working_dir.as_ref(), &args.files, args.concurrency)?;
+    if !args.json {
+        let output: String = parts.into_iter().map(|part| part.content).collect();
+        return write_escaped(
+            &output,
+            args.escape.map_or(OutputEscape::None, Into::into),
+            &args.sinks,
+        );
+    }
+
+    let payload = PartsEnvelope {
+        schema_version: SCHEMA_VERSION,
+        generated_at: current_timestamp(),
+        content: parts.iter().map(|part| part.content.as_str()).collect(),
+        parts: parts.into_iter().map(JsonPromptPart::from).collect(),
+    };
+    let mut rendered = serde_json::to_string_pretty(&payload)?;
+    rendered.push('\n');
+    args.sinks.deliver(&rendered)
+}
+
+/// Reveal `dir` with the platform's opener, or print it when asked to, when the `open`
+/// feature is off, or when no opener is available.
+fn open_directory(dir: &Utf8Path, print: bool) -> Result<()> {
+    if !dir.is_dir() {
+        bail!("directory {dir} does not exist");
+    }
+    if !print && launch_opener(dir) {
+        return Ok(());
+    }
+    println!("{dir}");
+    Ok(())
+}
+
+#[cfg(feature = "open")]
+fn launch_opener(dir: &Utf8Path) -> bool {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else if cfg!(windows) {
+        "explorer"
+    } else if cfg!(unix) {
+        "xdg-open"
+    } else {
+        return false;
+    };
+    match process::Command::new(opener).arg(dir.as_str()).spawn() {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("warning: could not run {opener}: {err}");
+            false
+        }
+    }
+}
+
+#[cfg(not(feature = "open"))]
+fn launch_opener(_dir: &Utf8Path) -> bool {
+    false
+}
+
+fn current_dir() -> Result<Utf8PathBuf> {
+    let cwd = std::env::current_dir().context("failed to determine current directory")?;
+    Utf8PathBuf::from_path_buf(cwd).map_err(|_| anyhow!("current directory is not valid UTF-8"))
+}
+
+#[derive(Debug, Error)]
+#[error("unknown prompt: {name}{hint}")]
+struct UnknownPrompt {
+    name: String,
+    hint: String,
+}
+
+impl UnknownPrompt {
+    fn new(assembler: &PromptAssembler, name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            hint: did_you_mean(&assembler.suggest_prompts(name)),
+        }
+    }
+}
+
+/// The hint appended to an unknown prompt error, or nothing when there are no suggestions.
+fn did_you_mean(suggestions: &[&str]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [only] => format!(" (did you mean '{only}'?)"),
+        many => {
+            let quoted: Vec<String> = many.iter().map(|name| format!("'{name}'")).collect();
+            format!(" (did you mean: {}?)", quoted.join(", "))
+        }
+    }
+}
+
+/// Broad classes of failure reported by `--json-errors`, each with its own exit code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ErrorKind {
+    Other,
+    Config,
+    UnknownPrompt,
+    Render,
+    Io,
+}
+
+impl ErrorKind {
+    fn classify(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            if cause.is::<LoadConfigError>() {
+                return ErrorKind::Config;
+            }
+            if cause.is::<UnknownPrompt>() {
+                return ErrorKind::UnknownPrompt;
+            }
+            if cause.is::<RenderError>() {
+                return ErrorKind::Render;
+            }
+            if cause.is::<io::Error>() {
+                return ErrorKind::Io;
+            }
+        }
+        ErrorKind::Other
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::Config => "config",
+            ErrorKind::UnknownPrompt => "unknown_prompt",
+            ErrorKind::Render => "render",
+            ErrorKind::Io => "io",
+        }
+    }
+
+    fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Config => 2,
+            ErrorKind::UnknownPrompt => 3,
+            ErrorKind::Render => 4,
+            ErrorKind::Io => 5,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorEnvelope {
+    error: JsonError,
+}
+
+#[derive(Serialize)]
+struct JsonError {
+    kind: &'static str,
+    message: String,
+}
+
+fn load_options(offline: bool) -> LoadOptions {
+    LoadOptions {
+        offline,
+        ..LoadOptions::default()
+    }
+}
+
+fn load_runtime_assembler(config_dir: &Utf8Path, offline: bool) -> Result<PromptAssembler> {
+    PromptAssembler::load_with_options(config_dir, &load_options(offline))
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to load configuration from {config_dir}"))
+}
+
+fn ensure_prompts_available(assembler: &PromptAssembler) -> Result<()> {
+    if assembler.has_prompts() {
+        Ok(())
+    } else {
+        bail!("no prompts defined; ensure config.toml exists with prompt entries");
+    }
+}
+
+fn list_prompts(assembler: &PromptAssembler, args: &ListArgs) -> Result<()> {
+    let prompts = assembler.available_prompts();
+    let names: Vec<&String> = prompts
+        .keys()
+        .filter(|name| in_group(name, args.group.as_deref()))
+        .collect();
 
-    let output = match kind {
-        PromptKind::Sequence { .. } => {
-            let mut positional_args = args;
-            if let Some(ref input) = stdin_arg {
-                positional_args.insert(0, input.clone());
-            }
+    if names.is_empty()
+        && let Some(group) = &args.group
+    {
+        bail!("no prompts found in group '{group}'");
+    }
 
-            if positional_args
-                .first()
-                .is_some_and(|first| looks_like_data_file(first))
-            {
-                bail!("prompt '{prompt}' does not accept structured data");
-            }
-            assembler.render_prompt(prompt, &positional_args, None)?
-        }
-        PromptKind::Template { .. } => {
-            let mut iter = args.into_iter();
-            let data_arg = iter
-                .next()
-                .ok_or_else(|| anyhow!("prompt '{prompt}' requires a data file (JSON or TOML)"))?;
-            let data = parse_data_argument(&data_arg)?;
-            let mut remaining: Vec<String> = iter.collect();
-            if let Some(ref input) = stdin_arg {
-                remaining.insert(0, input.clone());
+    if args.tree {
+        for line in group_tree_lines(&names) {
+            println!("{line}");
+        }
+    } else if args.detailed {
+        for name in names {
+            println!("{name}");
+            if let Some(spec) = assembler.prompt_spec(name) {
+                for var in &spec.metadata.vars {
+                    println!("{}", describe_var(var));
+                }
             }
-            assembler.render_prompt(prompt, &remaining, Some(data))?
         }
-    };
+    } else {
+        for name in names {
+            println!("{name}");
+        }
+    }
 
-    print!("{output}");
     Ok(())
 }
 
-fn run_parts(assembler: &PromptAssembler, files: &[String]) -> Result<()> {
-    let cwd = std::env::current_dir().context("failed to determine current directory")?;
-    let cwd = Utf8PathBuf::from_path_buf(cwd)
-        .map_err(|_| anyhow!("current directory is not valid UTF-8"))?;
-
-    let output = assembler.assemble_parts(cwd.as_ref(), files)?;
-    print!("{output}");
-    Ok(())
+fn in_group(name: &str, group: Option<&str>) -> bool {
+    let Some(group) = group else {
+        return true;
+    };
+    name == group
+        || name
+            .strip_prefix(group)
+            .is_some_and(|rest| rest.starts_with('.'))
 }
 
-fn load_runtime_assembler(config_dir: &Utf8Path) -> Result<PromptAssembler> {
-    PromptAssembler::from_directory(config_dir)
-        .with_context(|| format!("failed to load configuration from {config_dir}"))
+#[derive(Default)]
+struct GroupNode {
+    children: BTreeMap<String, GroupNode>,
 }
 
-fn ensure_prompts_available(assembler: &PromptAssembler) -> Result<()> {
-    if assembler.has_prompts() {
-        Ok(())
-    } else {
-        bail!("no prompts defined; ensure config.toml exists with prompt entries");
+fn group_tree_lines(names: &[&String]) -> Vec<String> {
+    fn walk(node: &GroupNode, depth: usize, lines: &mut Vec<String>) {
+        for (segment, child) in &node.children {
+            lines.push(format!("{}{segment}", "  ".repeat(depth)));
+            walk(child, depth + 1, lines);
+        }
     }
-}
 
-fn list_prompts(assembler: &PromptAssembler) {
-    for name in assembler.available_prompts().keys() {
-        println!("{name}");
+    let mut root = GroupNode::default();
+    for name in names {
+        let mut node = &mut root;
+        for segment in name.split('.') {
+            node = node.children.entry(segment.to_owned()).or_default();
+        }
     }
+
+    let mut lines = Vec::new();
+    walk(&root, 0, &mut lines);
+    lines
 }
 
-fn handle_list(config_dir: &Utf8Path, args: &ListArgs) -> Result<()> {
-    match PromptAssembler::load_with_diagnostics(config_dir) {
+fn handle_list(config_dir: &Utf8Path, offline: bool, args: &ListArgs) -> Result<()> {
+    match PromptAssembler::load_with_options(config_dir, &load_options(offline)) {
         Ok(assembler) => {
-            if args.json {
-                print_list_json(&assembler)?;
-            } else {
+            if args.verbose {
+                for skipped in assembler.skipped_prompts() {
+                    eprintln!(
+                        "skipped: {} ({}) in {}",
+                        skipped.name, skipped.reason, skipped.source
+                    );
+                }
+            }
+            let format = args.output.format();
+            if format == OutputFormat::Human {
                 ensure_prompts_available(&assembler)?;
-                list_prompts(&assembler);
+                list_prompts(&assembler, args)?;
+            } else {
+                print_list_structured(&assembler, format, args.group.as_deref(), &args.paths)?;
             }
         }
         Err(LoadConfigError::Invalid { diagnostics }) => {
@@ -215,21 +1658,40 @@ fn handle_list(config_dir: &Utf8Path, args: &ListArgs) -> Result<()> {
     Ok(())
 }
 
-fn handle_show(config_dir: &Utf8Path, args: &ShowArgs) -> Result<()> {
-    match PromptAssembler::load_with_diagnostics(config_dir) {
+fn handle_show(config_dir: &Utf8Path, offline: bool, args: &ShowArgs) -> Result<()> {
+    match PromptAssembler::load_with_options(config_dir, &load_options(offline)) {
         Ok(assembler) => {
             let Some(spec) = assembler.prompt_spec(&args.name) else {
-                eprintln!("error: unknown prompt '{}'", args.name);
-                process::exit(1);
+                return Err(UnknownPrompt::new(&assembler, &args.name).into());
             };
 
-            if args.json {
-                let profile = assembler.prompt_profile(&args.name)?;
-                let profile = Some(profile_to_json(profile));
-                print_prompt_json(&args.name, spec, profile)?;
+            let preview = args.preview.as_deref().map(|raw| {
+                parse_data_argument(raw, spec.metadata.data_format)
+                    .and_then(|data| assembler.render_prompt(&args.name, &[], Some(data)))
+                    .with_context(|| format!("failed to render preview of '{}'", args.name))
+            });
+            let rendered = preview.as_ref().and_then(|result| result.as_ref().ok());
+
+            let format = args.output.format();
+            if format == OutputFormat::Human {
+                let mut text = Vec::new();
+                write_prompt_human(&mut text, &assembler, &args.name, spec)?;
+                if let Some(rendered) = rendered {
+                    writeln!(text, "preview:")?;
+                    write!(text, "{rendered}")?;
+                }
+                pager::print_paged(&String::from_utf8_lossy(&text), !args.no_pager)?;
             } else {
-                print_prompt_human(&args.name, spec);
+                let profile = assembler.prompt_profile(&args.name)?;
+                let mut profile = profile_to_json(profile);
+                profile.rendered = rendered.cloned();
+                let prompt =
+                    args.paths
+                        .apply(prompt_to_json(&assembler, &args.name, spec, Some(profile)));
+                print_structured(format, &prompt)?;
             }
+            // The metadata is shown either way; a failed preview still fails the command.
+            preview.transpose()?;
         }
         Err(LoadConfigError::Invalid { diagnostics }) => {
             emit_human_diagnostics("error", &diagnostics.errors);
@@ -242,77 +1704,188 @@ fn handle_show(config_dir: &Utf8Path, args: &ShowArgs) -> Result<()> {
     Ok(())
 }
 
-fn handle_validate(config_dir: &Utf8Path, args: &ValidateArgs) -> Result<()> {
-    match PromptAssembler::load_with_diagnostics(config_dir) {
-        Ok(assembler) => {
-            let warnings: Vec<ConfigIssue> = assembler.config_warnings().to_vec();
-            if args.json {
-                print_validate_json(&[], &warnings)?;
-            } else {
-                if !warnings.is_empty() {
-                    emit_human_diagnostics("warning", &warnings);
-                }
-                println!("configuration is valid");
-            }
+fn handle_validate(config_dir: &Utf8Path, offline: bool, args: &ValidateArgs) -> Result<()> {
+    let (assembler, diagnostics) =
+        match PromptAssembler::load_valid_prompts(config_dir, &load_options(offline)) {
+            Ok(loaded) => loaded,
+            Err(err) => exit_with_load_error(err),
+        };
+    let failed = |name: &str| {
+        diagnostics
+            .errors
+            .iter()
+            .any(|issue| issue.prompt.as_deref() == Some(name))
+    };
+    if let Some(unknown) = args
+        .prompts
+        .iter()
+        .find(|name| assembler.prompt_spec(name).is_none() && !failed(name))
+    {
+        return Err(UnknownPrompt::new(&assembler, unknown).into());
+    }
+    let tags_of = |name: &str| diagnostics.prompt_tags.get(name).cloned();
+    let mut warnings = diagnostics.warnings.clone();
+    warnings.extend(assembler.check_template_vars());
+    warnings.extend(assembler.check_placeholder_gaps());
+    let mut errors = diagnostics.errors.clone();
+    errors.extend(assembler.check_templates());
+    errors.extend(assembler.check_data_files());
+    errors.extend(assembler.check_pinned_hashes());
+    args.retain_in_scope(&mut warnings, tags_of);
+    args.retain_in_scope(&mut errors, tags_of);
+    let format = args.output.format();
+    if !errors.is_empty() {
+        if format == OutputFormat::Human {
+            emit_human_diagnostics("error", &errors);
+            emit_human_diagnostics("warning", &warnings);
+        } else {
+            print_validate_structured(&errors, &warnings, format)?;
         }
-        Err(LoadConfigError::Invalid { diagnostics }) => {
-            if args.json {
-                print_validate_json(&diagnostics.errors, &diagnostics.warnings)?;
-            } else {
-                emit_human_diagnostics("error", &diagnostics.errors);
-                emit_human_diagnostics("warning", &diagnostics.warnings);
-            }
-            process::exit(2);
+        process::exit(2);
+    }
+    if format == OutputFormat::Human {
+        if !warnings.is_empty() {
+            emit_human_diagnostics("warning", &warnings);
         }
-        Err(other) => exit_with_load_error(other),
+        println!("configuration is valid");
+    } else {
+        print_validate_structured(&[], &warnings, format)?;
     }
 
     Ok(())
 }
 
-fn handle_self_update(args: &SelfUpdateArgs) -> Result<()> {
-    use self_update::backends::github::Update;
+fn handle_init(config_dir: &Utf8Path, args: &InitArgs) -> Result<()> {
+    let mut created: Vec<Utf8PathBuf> = Vec::new();
+
+    if !config_dir.exists() {
+        fs::create_dir_all(config_dir.as_std_path())
+            .with_context(|| format!("failed to create config directory {config_dir}"))?;
+        created.push(config_dir.to_owned());
+    }
 
-    const REPO_OWNER: &str = "bedecarroll";
-    const REPO_NAME: &str = "prompt-assembler";
-    const BIN_NAME: &str = "pa";
+    let config_path = config_dir.join("config.toml");
+    if config_path.exists() {
+        println!("config.toml already exists at {config_path}; leaving it untouched");
+    } else {
+        let mut contents = DEFAULT_CONFIG.to_vec();
+        if args.example {
+            contents.extend_from_slice(EXAMPLE_CONFIG.as_bytes());
+        }
+        fs::write(config_path.as_std_path(), contents)
+            .with_context(|| format!("failed to write default config at {config_path}"))?;
+        created.push(config_path);
+
+        if args.example {
+            let fragment_path = config_dir.join("example.md");
+            if !fragment_path.exists() {
+                fs::write(fragment_path.as_std_path(), EXAMPLE_FRAGMENT).with_context(|| {
+                    format!("failed to write example fragment at {fragment_path}")
+                })?;
+                created.push(fragment_path);
+            }
+        }
+    }
 
-    let mut builder = Update::configure();
-    builder
-        .repo_owner(REPO_OWNER)
-        .repo_name(REPO_NAME)
-        .bin_name(BIN_NAME)
-        .current_version(env!("CARGO_PKG_VERSION"))
-        .show_download_progress(true);
+    if args.conf_d {
+        let conf_d = config_dir.join("conf.d");
+        if !conf_d.exists() {
+            fs::create_dir_all(conf_d.as_std_path())
+                .with_context(|| format!("failed to create {conf_d}"))?;
+            created.push(conf_d);
+        }
+    }
 
-    if let Some(version) = args.version.as_ref() {
-        let normalized = if version.starts_with('v') {
-            version.clone()
-        } else {
-            format!("v{version}")
-        };
-        builder.target_version_tag(&normalized);
+    for path in &created {
+        println!("created {path}");
     }
 
-    if let Ok(token) = std::env::var("PA_GITHUB_TOKEN")
-        && let token = token.trim()
-        && !token.is_empty()
+    Ok(())
+}
+
+/// Scaffold prompt `args.name`: append its definition to config.toml (or a new `conf.d` file)
+/// and create its empty fragment or template under the default `prompt_path`.
+fn handle_new(config_dir: &Utf8Path, assembler: &PromptAssembler, args: &NewArgs) -> Result<()> {
+    let name = args.name.as_str();
+    if name.is_empty() || name.contains(['/', '\\']) {
+        bail!("prompt name '{name}' must be non-empty and cannot contain '/' or '\\'");
+    }
+    let skipped = assembler
+        .skipped_prompts()
+        .iter()
+        .any(|prompt| prompt.name == name);
+    if assembler.prompt_spec(name).is_some() || skipped {
+        bail!("prompt '{name}' already exists; pick another name");
+    }
+
+    let base = assembler.default_prompt_path();
+    if !base.is_dir() {
+        bail!("cannot create prompt files: prompt_path {base} is not a directory");
+    }
+    let (file, key) = match args.kind {
+        NewKind::Sequence => (format!("{name}.md"), "prompts"),
+        NewKind::Template => (format!("{name}.j2"), "template"),
+    };
+    let file_value = toml::Value::String(file.clone());
+    let value = match args.kind {
+        NewKind::Sequence => format!("[{file_value}]"),
+        NewKind::Template => file_value.to_string(),
+    };
+    let table = if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
     {
-        builder.auth_token(token);
+        name.to_owned()
+    } else {
+        toml::Value::String(name.to_owned()).to_string()
+    };
+    let block = format!("[prompt.{table}]\n{key} = {value}\n");
+
+    let conf_d = config_dir.join("conf.d");
+    let conf_d_path = conf_d.join(format!("{name}.toml"));
+    if args.conf_d && conf_d_path.exists() {
+        bail!("{conf_d_path} already exists; leaving it untouched");
     }
 
-    let status = builder
-        .build()
-        .context("failed to configure self-updater")?
-        .update()
-        .context("failed to apply update")?;
+    let mut created: Vec<Utf8PathBuf> = Vec::new();
+    let file_path = base.join(&file);
+    if file_path.exists() {
+        println!("{file_path} already exists; leaving it untouched");
+    } else {
+        fs::write(file_path.as_std_path(), "")
+            .with_context(|| format!("failed to create {file_path}"))?;
+        created.push(file_path);
+    }
 
-    if status.updated() {
-        println!("Updated pa to {}", status.version());
+    if args.conf_d {
+        fs::create_dir_all(conf_d.as_std_path())
+            .with_context(|| format!("failed to create {conf_d}"))?;
+        fs::write(conf_d_path.as_std_path(), block)
+            .with_context(|| format!("failed to write {conf_d_path}"))?;
+        created.push(conf_d_path);
     } else {
-        println!("pa is already up to date ({})", status.version());
+        let config_path = config_dir.join("config.toml");
+        let existing = fs::read_to_string(config_path.as_std_path())
+            .with_context(|| format!("failed to read {config_path}"))?;
+        let separator = match existing.as_str() {
+            "" => "",
+            text if text.ends_with("\n\n") => "",
+            text if text.ends_with('\n') => "\n",
+            _ => "\n\n",
+        };
+        let mut config = fs::OpenOptions::new()
+            .append(true)
+            .open(config_path.as_std_path())
+            .with_context(|| format!("failed to open {config_path}"))?;
+        config
+            .write_all(format!("{separator}{block}").as_bytes())
+            .with_context(|| format!("failed to append to {config_path}"))?;
+        println!("added prompt '{name}' to {config_path}");
     }
 
+    for path in &created {
+        println!("created {path}");
+    }
     Ok(())
 }
 
@@ -339,88 +1912,265 @@ fn generate_completions(shell: Shell, assembler: &PromptAssembler) -> Result<()>
                 writeln!(stdout, "\n# prompts: {}", prompts.join(" "))?;
             }
         }
+
+        writeln!(stdout, "# prompt-assembler prompt hints")?;
+        for (name, spec) in assembler.prompt_specs() {
+            let arity = assembler.placeholder_arity(name).ok().flatten();
+            writeln!(stdout, "{}", completion_hint(name, spec, arity))?;
+        }
+    }
+
+    Ok(())
+}
+
+// Wrappers around the clap-generated completion functions that offer prompt names for the
+// first argument by running `pa list` each time completion is requested.
+const DYNAMIC_BASH: &str = r#"
+# prompt-assembler dynamic prompt list
+_pa_dynamic() {
+    if [[ ${COMP_CWORD} -eq 1 && ${COMP_WORDS[1]} != -* ]]; then
+        _pa "$@"
+        COMPREPLY+=( $(compgen -W "$(command pa list 2>/dev/null)" -- "${COMP_WORDS[1]}") )
+        return 0
+    fi
+    _pa "$@"
+}
+complete -F _pa_dynamic -o nosort -o bashdefault -o default pa
+"#;
+
+const DYNAMIC_ZSH: &str = r#"
+# prompt-assembler dynamic prompt list
+_pa_dynamic() {
+    if (( CURRENT == 2 )) && [[ ${words[2]} != -* ]]; then
+        local -a prompts
+        prompts=(${(f)"$(command pa list 2>/dev/null)"})
+        compadd -a prompts
+    fi
+    _pa "$@"
+}
+compdef _pa_dynamic pa
+"#;
+
+const DYNAMIC_FISH: &str = r#"
+# prompt-assembler dynamic prompt list
+complete -c pa -n "__fish_use_subcommand" -f -a "(command pa list 2>/dev/null)" -d "prompt"
+"#;
+
+fn generate_dynamic_completions(shell: Shell) -> Result<()> {
+    let wrapper = match shell {
+        Shell::Bash => DYNAMIC_BASH,
+        Shell::Zsh => DYNAMIC_ZSH,
+        Shell::Fish => DYNAMIC_FISH,
+        other => bail!("--dynamic completions support bash, zsh, and fish, not {other}"),
+    };
+
+    let mut cmd = Cli::command();
+    let mut stdout = io::stdout();
+    generate(shell, &mut cmd, "pa", &mut stdout);
+    stdout.write_all(wrapper.as_bytes())?;
+    Ok(())
+}
+
+/// A one-line invocation example built from the prompt's kind and declared vars, written
+/// with the flags and arguments `pa` accepts.
+///
+/// Optional values are shown in square brackets. Sequence vars that describe positional
+/// arguments appear as `<name:kind>` in position order and the rest as `--set` flags; template
+/// vars are `--var` flags or keys of the data file.
+fn usage_line(assembler: &PromptAssembler, name: &str, spec: &PromptSpec) -> String {
+    let flag = |flag: &str, var: &PromptVariable| {
+        let text = format!("{flag} {}=<{}>", var.name, var.kind.as_str());
+        if var.required {
+            text
+        } else {
+            format!("[{text}]")
+        }
+    };
+    let vars = &spec.metadata.vars;
+    let mut words = vec![format!("pa {name}")];
+    match spec.kind {
+        PromptKind::Sequence { .. } | PromptKind::Inline { .. } => {
+            let positional = assembler.positional_vars(name).unwrap_or_default();
+            words.extend(
+                vars.iter()
+                    .filter(|var| !positional.iter().any(|(_, bound)| bound.name == var.name))
+                    .map(|var| flag("--set", var)),
+            );
+            if positional.is_empty() {
+                words.push("[ARGS...]".to_owned());
+            }
+            words.extend(
+                positional
+                    .iter()
+                    .map(|(_, var)| format!("<{}:{}>", var.name, var.kind.as_str())),
+            );
+        }
+        PromptKind::Template { .. } | PromptKind::InlineTemplate { .. } => {
+            words.extend(vars.iter().map(|var| flag("--var", var)));
+            words.push(if vars.is_empty() { "<DATA>" } else { "[DATA]" }.to_owned());
+        }
     }
+    words.join(" ")
+}
+
+/// One parseable comment line per prompt: its kind, argument count, and var names.
+///
+/// The count is the placeholder arity when it can be read, otherwise the declared vars.
+fn completion_hint(name: &str, spec: &PromptSpec, arity: Option<usize>) -> String {
+    let kind = match spec.kind {
+        PromptKind::Sequence { .. } => "sequence",
+        PromptKind::Template { .. } => "template",
+        PromptKind::Inline { .. } => "inline",
+        PromptKind::InlineTemplate { .. } => "template_inline",
+    };
+    let vars: Vec<&str> = spec
+        .metadata
+        .vars
+        .iter()
+        .map(|var| var.name.as_str())
+        .collect();
+    format!(
+        "# pa-prompt name={name} kind={kind} args={} vars={}",
+        arity.unwrap_or(vars.len()),
+        vars.join(",")
+    )
+}
 
+fn print_structured<T: Serialize>(format: OutputFormat, payload: &T) -> Result<()> {
+    let rendered = match format {
+        OutputFormat::Toml => toml::to_string_pretty(payload)?,
+        OutputFormat::Jsonl | OutputFormat::Compact => serde_json::to_string(payload)?,
+        OutputFormat::Human | OutputFormat::Json => serde_json::to_string_pretty(payload)?,
+    };
+    println!("{}", rendered.trim_end());
     Ok(())
 }
 
-fn print_list_json(assembler: &PromptAssembler) -> Result<()> {
+fn print_list_structured(
+    assembler: &PromptAssembler,
+    format: OutputFormat,
+    group: Option<&str>,
+    paths: &PathArgs,
+) -> Result<()> {
     let prompts: Vec<JsonPrompt> = assembler
         .prompt_specs()
         .iter()
-        .map(|(name, spec)| prompt_to_json(name, spec, None))
+        .filter(|(name, _)| in_group(name, group))
+        .map(|(name, spec)| paths.apply(prompt_to_json(assembler, name, spec, None)))
         .collect();
 
+    if format == OutputFormat::Jsonl {
+        for prompt in &prompts {
+            print_structured(format, prompt)?;
+        }
+        return Ok(());
+    }
+
     let payload = ListEnvelope {
         schema_version: SCHEMA_VERSION,
         generated_at: current_timestamp(),
         prompts,
     };
 
-    let rendered = serde_json::to_string_pretty(&payload)?;
-    println!("{rendered}");
-    Ok(())
+    print_structured(format, &payload)
 }
 
-fn print_prompt_json(
+fn write_prompt_human(
+    out: &mut impl Write,
+    assembler: &PromptAssembler,
     name: &str,
     spec: &PromptSpec,
-    profile: Option<JsonPromptProfile>,
-) -> Result<()> {
-    let payload = prompt_to_json(name, spec, profile);
-    let rendered = serde_json::to_string_pretty(&payload)?;
-    println!("{rendered}");
-    Ok(())
-}
-
-fn print_prompt_human(name: &str, spec: &PromptSpec) {
-    println!("name: {name}");
+) -> io::Result<()> {
+    writeln!(out, "usage: {}", usage_line(assembler, name, spec))?;
+    writeln!(out, "name: {name}")?;
 
     match spec.kind {
-        PromptKind::Sequence { .. } => println!("kind: sequence"),
-        PromptKind::Template { .. } => println!("kind: template"),
+        PromptKind::Sequence { .. } => writeln!(out, "kind: sequence")?,
+        PromptKind::Template { .. } => writeln!(out, "kind: template")?,
+        PromptKind::Inline { .. } => writeln!(out, "kind: inline")?,
+        PromptKind::InlineTemplate { .. } => writeln!(out, "kind: template_inline")?,
     }
 
     if let Some(description) = &spec.metadata.description {
-        println!("description: {description}");
+        writeln!(out, "description: {description}")?;
     }
 
     if !spec.metadata.tags.is_empty() {
-        println!("tags: {}", spec.metadata.tags.join(", "));
+        writeln!(out, "tags: {}", spec.metadata.tags.join(", "))?;
+    }
+
+    if let Some(command) = &spec.metadata.post_process {
+        writeln!(out, "post process: {command}")?;
     }
 
-    println!(
+    writeln!(
+        out,
         "stdin supported: {}",
         if effective_stdin_supported(spec) {
             "yes"
         } else {
             "no"
         }
-    );
+    )?;
 
     if let Some(last_modified) = format_system_time(spec.metadata.source.last_modified) {
-        println!("last modified: {last_modified}");
+        writeln!(out, "last modified: {last_modified}")?;
     }
 
-    println!("source: {}", spec.metadata.source.path);
+    writeln!(out, "source: {}", spec.metadata.source.path)?;
 
     if !spec.metadata.vars.is_empty() {
-        println!("vars:");
+        writeln!(out, "vars:")?;
         for var in &spec.metadata.vars {
-            let mut details = format!("  - {} ({})", var.name, var.kind.as_str());
-            if var.required {
-                details.push_str(" [required]");
-            }
-            if let Some(description) = &var.description {
-                details.push_str(" — ");
-                details.push_str(description);
-            }
-            println!("{details}");
+            writeln!(out, "{}", describe_var(var))?;
         }
     }
+    Ok(())
+}
+
+fn describe_var(var: &PromptVariable) -> String {
+    use std::fmt::Write as _;
+
+    let mut details = format!("  - {} ({})", var.name, var.kind.as_str());
+    if var.required {
+        details.push_str(" [required]");
+    }
+    if let Some(condition) = &var.required_if {
+        let _ = write!(details, " [required if {condition}]");
+    }
+    if let Some(default) = &var.default {
+        let _ = write!(details, " [default: {default}]");
+    }
+    if let Some(position) = var.position {
+        let _ = write!(details, " [position: {position}]");
+    }
+    if let Some(description) = &var.description {
+        details.push_str(" — ");
+        details.push_str(description);
+    }
+    details
 }
 
-fn print_validate_json(errors: &[ConfigIssue], warnings: &[ConfigIssue]) -> Result<()> {
+fn print_validate_structured(
+    errors: &[ConfigIssue],
+    warnings: &[ConfigIssue],
+    format: OutputFormat,
+) -> Result<()> {
+    if format == OutputFormat::Jsonl {
+        let levels = errors
+            .iter()
+            .map(|issue| ("error", issue))
+            .chain(warnings.iter().map(|issue| ("warning", issue)));
+        for (level, issue) in levels {
+            let line = JsonlDiagnostic {
+                level,
+                diagnostic: JsonDiagnostic::from(issue),
+            };
+            print_structured(format, &line)?;
+        }
+        return Ok(());
+    }
+
     let payload = ValidateEnvelope {
         schema_version: SCHEMA_VERSION,
         generated_at: current_timestamp(),
@@ -428,24 +2178,42 @@ fn print_validate_json(errors: &[ConfigIssue], warnings: &[ConfigIssue]) -> Resu
         warnings: warnings.iter().map(JsonDiagnostic::from).collect(),
     };
 
-    let rendered = serde_json::to_string_pretty(&payload)?;
-    println!("{rendered}");
-    Ok(())
+    print_structured(format, &payload)
 }
 
-fn prompt_to_json(name: &str, spec: &PromptSpec, profile: Option<JsonPromptProfile>) -> JsonPrompt {
+fn prompt_to_json(
+    assembler: &PromptAssembler,
+    name: &str,
+    spec: &PromptSpec,
+    profile: Option<JsonPromptProfile>,
+) -> JsonPrompt {
     JsonPrompt {
+        usage: usage_line(assembler, name, spec),
         name: name.to_string(),
         description: spec.metadata.description.clone(),
         tags: spec.metadata.tags.clone(),
         vars: convert_vars(&spec.metadata.vars),
         stdin_supported: effective_stdin_supported(spec),
+        post_process: spec.metadata.post_process.clone(),
         last_modified: format_system_time(spec.metadata.source.last_modified),
         source_path: spec.metadata.source.path.as_str().to_owned(),
         profile,
     }
 }
 
+/// Resolve `path` against the current directory and follow symlinks when it exists, keeping
+/// the lexical result otherwise.
+fn absolute_path(path: &str) -> String {
+    let path = Utf8Path::new(path);
+    if let Ok(canonical) = path.canonicalize_utf8() {
+        return canonical.into_string();
+    }
+    std::env::current_dir()
+        .ok()
+        .and_then(|cwd| Utf8PathBuf::from_path_buf(cwd).ok())
+        .map_or_else(|| path.to_string(), |cwd| cwd.join(path).into_string())
+}
+
 fn convert_vars(vars: &[PromptVariable]) -> Vec<JsonPromptVar> {
     vars.iter()
         .map(|var| JsonPromptVar {
@@ -453,6 +2221,9 @@ fn convert_vars(vars: &[PromptVariable]) -> Vec<JsonPromptVar> {
             required: var.required,
             kind: var.kind.as_str().to_owned(),
             description: var.description.clone(),
+            required_if: var.required_if.clone(),
+            default: var.default.clone(),
+            position: var.position,
         })
         .collect()
 }
@@ -464,6 +2235,7 @@ fn profile_to_json(profile: PromptProfile) -> JsonPromptProfile {
             parts: parts.into_iter().map(JsonPromptPart::from).collect(),
             template: None,
             content: combined,
+            rendered: None,
         },
         PromptProfile::Template { template } => {
             let part = JsonPromptPart::from(template);
@@ -473,8 +2245,23 @@ fn profile_to_json(profile: PromptProfile) -> JsonPromptProfile {
                 parts: Vec::new(),
                 template: Some(part),
                 content,
+                rendered: None,
             }
         }
+        PromptProfile::Inline { body } => JsonPromptProfile {
+            kind: "inline".to_string(),
+            parts: Vec::new(),
+            template: None,
+            content: body,
+            rendered: None,
+        },
+        PromptProfile::InlineTemplate { body } => JsonPromptProfile {
+            kind: "template_inline".to_string(),
+            parts: Vec::new(),
+            template: None,
+            content: body,
+            rendered: None,
+        },
     }
 }
 
@@ -494,6 +2281,129 @@ fn emit_human_diagnostics(level: &str, issues: &[ConfigIssue]) {
     }
 }
 
+fn run_orphans(assembler: &PromptAssembler, args: &OrphansArgs) -> Result<()> {
+    let orphans = assembler.orphaned_files()?;
+    let format = args.output.format();
+    if format == OutputFormat::Jsonl {
+        for path in &orphans {
+            print_structured(
+                format,
+                &JsonOrphan {
+                    path: path.as_str(),
+                },
+            )?;
+        }
+        return Ok(());
+    }
+    if format != OutputFormat::Human {
+        let payload = OrphansEnvelope {
+            schema_version: SCHEMA_VERSION,
+            generated_at: current_timestamp(),
+            orphans: orphans.iter().map(ToString::to_string).collect(),
+        };
+        return print_structured(format, &payload);
+    }
+
+    if orphans.is_empty() {
+        println!("no orphaned files");
+    }
+    for path in orphans {
+        println!("{path}");
+    }
+    Ok(())
+}
+
+/// Count the prompts carrying each tag, most used first and then by name.
+fn tag_counts(assembler: &PromptAssembler) -> Vec<(String, usize)> {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for spec in assembler.prompt_specs().values() {
+        let unique: BTreeSet<&String> = spec.metadata.tags.iter().collect();
+        for tag in unique {
+            *counts.entry(tag.clone()).or_default() += 1;
+        }
+    }
+    let mut counts: Vec<_> = counts.into_iter().collect();
+    counts.sort_by(|(a_tag, a_count), (b_tag, b_count)| {
+        b_count.cmp(a_count).then_with(|| a_tag.cmp(b_tag))
+    });
+    counts
+}
+
+fn run_tags(assembler: &PromptAssembler, args: &TagsArgs) -> Result<()> {
+    let counts = tag_counts(assembler);
+    let format = args.output.format();
+    if format == OutputFormat::Jsonl {
+        for (tag, count) in &counts {
+            print_structured(format, &JsonTag { tag, count: *count })?;
+        }
+        return Ok(());
+    }
+    if format != OutputFormat::Human {
+        let payload = TagsEnvelope {
+            schema_version: SCHEMA_VERSION,
+            generated_at: current_timestamp(),
+            tags: counts.into_iter().collect(),
+        };
+        return print_structured(format, &payload);
+    }
+
+    if counts.is_empty() {
+        println!("no tags");
+    }
+    let width = counts.iter().map(|(tag, _)| tag.len()).max().unwrap_or(0);
+    for (tag, count) in counts {
+        println!("{tag:<width$}  {count}");
+    }
+    Ok(())
+}
+
+fn run_deps(assembler: &PromptAssembler, args: &DepsArgs) -> Result<()> {
+    if assembler.prompt_spec(&args.name).is_none() {
+        return Err(UnknownPrompt::new(assembler, &args.name).into());
+    }
+    let tree = assembler.template_dependencies(&args.name)?;
+    let format = args.output.format();
+    if format == OutputFormat::Jsonl {
+        return print_structured(format, &JsonDependency::from(&tree));
+    }
+    if format != OutputFormat::Human {
+        let payload = DepsEnvelope {
+            schema_version: SCHEMA_VERSION,
+            generated_at: current_timestamp(),
+            prompt: &args.name,
+            tree: JsonDependency::from(&tree),
+        };
+        return print_structured(format, &payload);
+    }
+
+    println!("{}", dependency_label(&tree));
+    print_dependencies(&tree.dependencies, "");
+    Ok(())
+}
+
+fn print_dependencies(dependencies: &[TemplateDependency], indent: &str) {
+    for (index, dependency) in dependencies.iter().enumerate() {
+        let last = index + 1 == dependencies.len();
+        let (branch, nested) = if last {
+            ("└── ", "    ")
+        } else {
+            ("├── ", "│   ")
+        };
+        println!("{indent}{branch}{}", dependency_label(dependency));
+        print_dependencies(&dependency.dependencies, &format!("{indent}{nested}"));
+    }
+}
+
+fn dependency_label(dependency: &TemplateDependency) -> String {
+    if dependency.missing {
+        format!("{} (missing)", dependency.name)
+    } else if dependency.repeated {
+        format!("{} (see above)", dependency.name)
+    } else {
+        dependency.name.clone()
+    }
+}
+
 fn format_issue(issue: &ConfigIssue) -> String {
     match issue.line {
         Some(line) => format!("{}:{}: {}", issue.path, line, issue.message),
@@ -520,9 +2430,10 @@ fn exit_with_load_error(err: LoadConfigError) -> ! {
 }
 
 fn effective_stdin_supported(spec: &PromptSpec) -> bool {
-    spec.metadata
-        .stdin_supported
-        .unwrap_or(matches!(spec.kind, PromptKind::Sequence { .. }))
+    spec.metadata.stdin_supported.unwrap_or(matches!(
+        spec.kind,
+        PromptKind::Sequence { .. } | PromptKind::Inline { .. }
+    ))
 }
 
 fn current_timestamp() -> String {
@@ -542,9 +2453,27 @@ struct ListEnvelope {
     prompts: Vec<JsonPrompt>,
 }
 
+#[derive(Serialize)]
+struct RenderEnvelope {
+    schema_version: u8,
+    generated_at: String,
+    name: String,
+    output: String,
+    sources: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PartsEnvelope {
+    schema_version: u8,
+    generated_at: String,
+    parts: Vec<JsonPromptPart>,
+    content: String,
+}
+
 #[derive(Serialize)]
 struct JsonPrompt {
     name: String,
+    usage: String,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
@@ -553,6 +2482,8 @@ struct JsonPrompt {
     vars: Vec<JsonPromptVar>,
     stdin_supported: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    post_process: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     last_modified: Option<String>,
     source_path: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -567,6 +2498,8 @@ struct JsonPromptProfile {
     #[serde(skip_serializing_if = "Option::is_none")]
     template: Option<JsonPromptPart>,
     content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rendered: Option<String>,
 }
 
 #[derive(Serialize, Clone)]
@@ -583,6 +2516,74 @@ struct JsonPromptVar {
     kind: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    required_if: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    default: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    position: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct OrphansEnvelope {
+    schema_version: u8,
+    generated_at: String,
+    orphans: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct JsonOrphan<'a> {
+    path: &'a str,
+}
+
+#[derive(Serialize)]
+struct DepsEnvelope<'a> {
+    schema_version: u8,
+    generated_at: String,
+    prompt: &'a str,
+    tree: JsonDependency<'a>,
+}
+
+#[derive(Serialize)]
+struct JsonDependency<'a> {
+    name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    missing: bool,
+    repeated: bool,
+    dependencies: Vec<JsonDependency<'a>>,
+}
+
+impl<'a> From<&'a TemplateDependency> for JsonDependency<'a> {
+    fn from(dependency: &'a TemplateDependency) -> Self {
+        Self {
+            name: &dependency.name,
+            path: dependency.path.as_deref().map(Utf8Path::as_str),
+            missing: dependency.missing,
+            repeated: dependency.repeated,
+            dependencies: dependency.dependencies.iter().map(Self::from).collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct TagsEnvelope {
+    schema_version: u8,
+    generated_at: String,
+    /// Most used first, like the human listing.
+    tags: IndexMap<String, usize>,
+}
+
+#[derive(Serialize)]
+struct JsonMessage<'a> {
+    role: &'a str,
+    content: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonTag<'a> {
+    tag: &'a str,
+    count: usize,
 }
 
 #[derive(Serialize)]
@@ -600,6 +2601,15 @@ struct JsonDiagnostic {
     line: Option<u32>,
     code: String,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    prompt: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonlDiagnostic {
+    level: &'static str,
+    #[serde(flatten)]
+    diagnostic: JsonDiagnostic,
 }
 
 impl From<&ConfigIssue> for JsonDiagnostic {
@@ -609,19 +2619,26 @@ impl From<&ConfigIssue> for JsonDiagnostic {
             line: issue.line,
             code: issue.code.as_str().to_owned(),
             message: issue.message.clone(),
+            prompt: issue.prompt.clone(),
         }
     }
 }
 
-fn parse_data_argument(raw: &str) -> Result<StructuredData> {
+/// A data file argument, read as `format` when the prompt declares one and otherwise by its
+/// extension.
+fn parse_data_argument(raw: &str, format: Option<DataFormat>) -> Result<StructuredData> {
+    if let Some(format) = format {
+        return Ok(StructuredData::with_format(Utf8PathBuf::from(raw), format));
+    }
     if !looks_like_data_file(raw) {
-        bail!("data file must use JSON or TOML format");
+        bail!("data file must use JSON, TOML, or YAML format");
     }
     let path = Utf8PathBuf::from(raw);
     match path.extension().map(str::to_ascii_lowercase).as_deref() {
         Some("json") => Ok(StructuredData::Json(path)),
         Some("toml") => Ok(StructuredData::Toml(path)),
-        _ => bail!("data file must use JSON or TOML format"),
+        Some("yaml" | "yml") => Ok(StructuredData::Yaml(path)),
+        _ => bail!("data file must use JSON, TOML, or YAML format"),
     }
 }
 
@@ -659,6 +2676,21 @@ fn discover_config_dir() -> Result<Utf8PathBuf> {
     }
 }
 
+fn auto_init_enabled() -> bool {
+    std::env::var(AUTO_INIT_ENV)
+        .is_ok_and(|value| matches!(value.trim(), "1" | "true" | "yes" | "on"))
+}
+
+fn ensure_config_exists(config_dir: &Utf8Path) -> Result<()> {
+    if config_dir.is_dir() {
+        Ok(())
+    } else {
+        bail!(
+            "configuration directory {config_dir} does not exist; run `pa init` to create it or set {AUTO_INIT_ENV}=1"
+        );
+    }
+}
+
 fn ensure_config_initialized(config_dir: &Utf8Path) -> Result<()> {
     fs::create_dir_all(config_dir.as_std_path())
         .with_context(|| format!("failed to create config directory {config_dir}"))?;
@@ -677,7 +2709,7 @@ fn looks_like_data_file(value: &str) -> bool {
         .extension()
         .map(str::to_ascii_lowercase)
         .as_deref()
-        .is_some_and(|ext| ext == "json" || ext == "toml")
+        .is_some_and(|ext| matches!(ext, "json" | "toml" | "yaml" | "yml"))
 }
 
 fn parse_shell(raw: &str) -> Result<Shell> {
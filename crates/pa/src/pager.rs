@@ -0,0 +1,60 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use anyhow::{Context, Result};
+
+/// Terminal height assumed when `LINES` is unset or unreadable.
+const DEFAULT_TERMINAL_LINES: usize = 24;
+
+/// Print human output, piping it through `$PAGER` (else `less`) when stdout is a terminal and
+/// the text is taller than it. `enabled` is false for `--no-pager`. Structured output must not
+/// come through here.
+pub(crate) fn print_paged(text: &str, enabled: bool) -> Result<()> {
+    if !enabled || !atty::is(atty::Stream::Stdout) || text.lines().count() < terminal_lines() {
+        return print_direct(text);
+    }
+
+    let pager = std::env::var("PAGER")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .unwrap_or_else(|| "less".to_owned());
+    let mut words = pager.split_whitespace();
+    let program = words.next().unwrap_or("less");
+    // A pager that cannot be started should not lose the output.
+    let Ok(mut child) = Command::new(program)
+        .args(words)
+        .stdin(Stdio::piped())
+        .spawn()
+    else {
+        return print_direct(text);
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        match stdin.write_all(text.as_bytes()) {
+            // Quitting the pager early closes the pipe.
+            Err(err) if err.kind() != io::ErrorKind::BrokenPipe => {
+                return Err(err).with_context(|| format!("failed to write to pager '{pager}'"));
+            }
+            _ => {}
+        }
+    }
+    child
+        .wait()
+        .with_context(|| format!("failed to wait for pager '{pager}'"))?;
+    Ok(())
+}
+
+fn print_direct(text: &str) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    stdout.write_all(text.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+fn terminal_lines() -> usize {
+    std::env::var("LINES")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|&lines| lines > 0)
+        .unwrap_or(DEFAULT_TERMINAL_LINES)
+}
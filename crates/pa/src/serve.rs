@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use prompt_assembler::{LoadOptions, PromptAssembler};
+use serde::{Deserialize, Serialize};
+
+/// One line of a client's request stream.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ServeRequest {
+    prompt: String,
+    #[serde(default)]
+    args: Vec<String>,
+}
+
+/// The line written back for each request: the rendered text or why it failed.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum ServeResponse {
+    Output(String),
+    Error(String),
+}
+
+/// Modification time and size of `config.toml` and everything under `conf.d`, compared
+/// between requests to notice configuration edits.
+type ConfigStamp = Vec<(Utf8PathBuf, Option<SystemTime>, u64)>;
+
+/// The loaded configuration shared by every connection, with the stamp it was loaded from.
+struct Shared {
+    config_dir: Utf8PathBuf,
+    options: LoadOptions,
+    watch_config: bool,
+    assembler: RwLock<PromptAssembler>,
+    stamp: Mutex<ConfigStamp>,
+}
+
+impl Shared {
+    /// Reload the configuration when its files changed since the last load. A failed reload
+    /// keeps the previous configuration.
+    fn refresh(&self) {
+        let Ok(mut stamp) = self.stamp.lock() else {
+            return;
+        };
+        let current = config_stamp(&self.config_dir);
+        if current == *stamp {
+            return;
+        }
+        *stamp = current;
+        let Ok(mut assembler) = self.assembler.write() else {
+            return;
+        };
+        match assembler.reload(&self.config_dir, &self.options) {
+            Ok(()) => eprintln!("reloaded configuration from {}", self.config_dir),
+            Err(err) => eprintln!("warning: keeping previous configuration: {err}"),
+        }
+    }
+}
+
+/// Serve renders on `socket` until the process is stopped. Each connection is handled on its
+/// own thread, sending one JSON request per line and reading one JSON response per line. With
+/// `watch_config`, the configuration is reloaded before a request whenever its files changed.
+pub(crate) fn run_serve(
+    config_dir: &Utf8Path,
+    options: &LoadOptions,
+    socket: &Utf8Path,
+    watch_config: bool,
+) -> Result<()> {
+    let assembler = PromptAssembler::load_with_options(config_dir, options)
+        .map_err(anyhow::Error::from)
+        .with_context(|| format!("failed to load configuration from {config_dir}"))?;
+    let shared = Arc::new(Shared {
+        config_dir: config_dir.to_owned(),
+        options: options.clone(),
+        watch_config,
+        assembler: RwLock::new(assembler),
+        stamp: Mutex::new(config_stamp(config_dir)),
+    });
+
+    let listener = bind(socket)?;
+    eprintln!("listening on {socket}");
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                eprintln!("warning: failed to accept connection: {err}");
+                continue;
+            }
+        };
+        let shared = Arc::clone(&shared);
+        thread::spawn(move || serve_connection(stream, &shared));
+    }
+    Ok(())
+}
+
+fn serve_connection(stream: UnixStream, shared: &Shared) {
+    let reader = match stream.try_clone() {
+        Ok(clone) => BufReader::new(clone),
+        Err(err) => {
+            eprintln!("warning: failed to read connection: {err}");
+            return;
+        }
+    };
+    let mut writer = stream;
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        if shared.watch_config {
+            shared.refresh();
+        }
+        let response = match shared.assembler.read() {
+            Ok(assembler) => answer(&assembler, &line),
+            Err(_) => ServeResponse::Error("configuration is unavailable".to_owned()),
+        };
+        let Ok(mut payload) = serde_json::to_string(&response) else {
+            break;
+        };
+        payload.push('\n');
+        if writer.write_all(payload.as_bytes()).is_err() {
+            break;
+        }
+    }
+}
+
+fn answer(assembler: &PromptAssembler, line: &str) -> ServeResponse {
+    let request: ServeRequest = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(err) => return ServeResponse::Error(format!("invalid request: {err}")),
+    };
+    match assembler.render_prompt(&request.prompt, &request.args, None) {
+        Ok(output) => ServeResponse::Output(output),
+        Err(err) => ServeResponse::Error(format!("{err:#}")),
+    }
+}
+
+/// Bind `socket`, replacing a stale socket file that nothing is listening on. Any other file
+/// at that path is left alone.
+fn bind(socket: &Utf8Path) -> Result<UnixListener> {
+    if let Ok(metadata) = fs::symlink_metadata(socket.as_std_path()) {
+        if !metadata.file_type().is_socket() {
+            bail!("{socket} exists and is not a socket");
+        }
+        if UnixStream::connect(socket.as_std_path()).is_ok() {
+            bail!("another pa serve is listening on {socket}");
+        }
+        fs::remove_file(socket.as_std_path())
+            .with_context(|| format!("failed to remove stale socket {socket}"))?;
+    }
+    UnixListener::bind(socket.as_std_path()).with_context(|| format!("failed to bind {socket}"))
+}
+
+/// Directories are followed through symlinks like loading does, but each canonical directory
+/// is visited once so a symlink cycle under `conf.d` cannot loop.
+fn config_stamp(config_dir: &Utf8Path) -> ConfigStamp {
+    let mut stamp = Vec::new();
+    let mut visited = HashSet::new();
+    let mut pending = vec![config_dir.join("config.toml"), config_dir.join("conf.d")];
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = fs::metadata(path.as_std_path()) else {
+            continue;
+        };
+        if metadata.is_dir()
+            && fs::canonicalize(path.as_std_path()).is_ok_and(|canonical| visited.insert(canonical))
+            && let Ok(entries) = path.read_dir_utf8()
+        {
+            pending.extend(entries.flatten().map(|entry| entry.path().to_owned()));
+        }
+        stamp.push((path, metadata.modified().ok(), metadata.len()));
+    }
+    stamp.sort();
+    stamp
+}
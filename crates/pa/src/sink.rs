@@ -0,0 +1,162 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process;
+
+use anyhow::{Context, Result, bail};
+use camino::Utf8PathBuf;
+use clap::Args;
+
+/// Where rendered output goes. Without any flag it is printed to stdout.
+#[derive(Args, Debug, Clone, Default)]
+pub(crate) struct OutputArgs {
+    #[command(flatten)]
+    files: FileArgs,
+    /// Print the output to stdout as well as any --output or --copy destination
+    #[arg(long)]
+    print: bool,
+    /// Copy the output to the system clipboard
+    #[arg(long)]
+    copy: bool,
+    /// Report the files that would be written and commands that would run on stderr instead
+    /// of writing, copying, post-processing, or logging anything
+    #[arg(long)]
+    pub(crate) dry_run: bool,
+}
+
+/// Files the output is written to.
+#[derive(Args, Debug, Clone, Default)]
+struct FileArgs {
+    /// Write the output to FILE
+    #[arg(long, value_name = "FILE")]
+    output: Option<Utf8PathBuf>,
+    /// Write the output to a new file in the temp directory (`$TMPDIR`) and print only its
+    /// path; the file is kept
+    #[arg(long, conflicts_with_all = ["output", "print"])]
+    to_tempfile: bool,
+}
+
+/// One destination for the assembled output.
+enum Sink<'a> {
+    Stdout,
+    File(&'a Utf8PathBuf),
+    TempFile,
+    Clipboard,
+}
+
+impl OutputArgs {
+    fn sinks(&self) -> Vec<Sink<'_>> {
+        let mut sinks = Vec::new();
+        let files = &self.files;
+        if self.print || (files.output.is_none() && !files.to_tempfile && !self.copy) {
+            sinks.push(Sink::Stdout);
+        }
+        if let Some(path) = &files.output {
+            sinks.push(Sink::File(path));
+        }
+        if files.to_tempfile {
+            sinks.push(Sink::TempFile);
+        }
+        if self.copy {
+            sinks.push(Sink::Clipboard);
+        }
+        sinks
+    }
+
+    /// Send `output` to every selected destination, in the order stdout, file, temp file,
+    /// clipboard.
+    /// Under `--dry-run` only stdout is written; the other destinations are reported on stderr.
+    pub(crate) fn deliver(&self, output: &str) -> Result<()> {
+        for sink in self.sinks() {
+            match sink {
+                Sink::Stdout => {
+                    let mut stdout = io::stdout().lock();
+                    stdout.write_all(output.as_bytes())?;
+                    stdout.flush()?;
+                }
+                Sink::File(path) if self.dry_run => {
+                    eprintln!("dry run: would write {} bytes to {path}", output.len());
+                }
+                Sink::File(path) => fs::write(path, output)
+                    .with_context(|| format!("failed to write output to {path}"))?,
+                Sink::TempFile if self.dry_run => {
+                    eprintln!(
+                        "dry run: would write {} bytes to a new file in {}",
+                        output.len(),
+                        env::temp_dir().display()
+                    );
+                }
+                Sink::TempFile => {
+                    let path = write_tempfile(output)?;
+                    println!("{}", path.display());
+                }
+                Sink::Clipboard if self.dry_run => {
+                    eprintln!(
+                        "dry run: would copy {} bytes to the clipboard",
+                        output.len()
+                    );
+                }
+                Sink::Clipboard => copy_to_clipboard(output)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write `output` to a new file in the temp directory that outlives the process.
+fn write_tempfile(output: &str) -> Result<PathBuf> {
+    let mut file = tempfile::Builder::new()
+        .prefix("pa-")
+        .suffix(".txt")
+        .tempfile()
+        .context("failed to create a temp file")?;
+    file.write_all(output.as_bytes())
+        .and_then(|()| file.flush())
+        .with_context(|| format!("failed to write output to {}", file.path().display()))?;
+    let (_, path) = file.keep().context("failed to keep the temp file")?;
+    Ok(path)
+}
+
+/// Pipe `output` into the first clipboard command available on this platform.
+fn copy_to_clipboard(output: &str) -> Result<()> {
+    let candidates: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbcopy"]]
+    } else if cfg!(windows) {
+        &[&["clip"]]
+    } else {
+        &[
+            &["wl-copy"],
+            &["xclip", "-selection", "clipboard"],
+            &["xsel", "--clipboard", "--input"],
+        ]
+    };
+
+    for command in candidates {
+        let Ok(mut child) = process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(output.as_bytes())
+                .with_context(|| format!("failed to write to {}", command[0]))?;
+        }
+        let status = child
+            .wait()
+            .with_context(|| format!("failed to wait for {}", command[0]))?;
+        if !status.success() {
+            bail!("{} exited with {status}", command[0]);
+        }
+        return Ok(());
+    }
+    let names: Vec<&str> = candidates.iter().map(|command| command[0]).collect();
+    bail!(
+        "no clipboard command found; install one of: {}",
+        names.join(", ")
+    )
+}
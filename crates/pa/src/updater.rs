@@ -0,0 +1,204 @@
+use std::fs;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use camino::{Utf8Path, Utf8PathBuf};
+use glob::Pattern;
+use self_update::backends::github::Update;
+use serde::{Deserialize, Serialize};
+
+use crate::{SelfUpdateArgs, current_timestamp};
+
+const REPO_OWNER: &str = "bedecarroll";
+const REPO_NAME: &str = "prompt-assembler";
+const BIN_NAME: &str = "pa";
+const STATE_FILE: &str = "self-update.toml";
+
+/// The version that was running before the most recent update, kept for `--rollback`.
+#[derive(Debug, Serialize, Deserialize)]
+struct UpdateState {
+    previous_version: String,
+    binary: Option<String>,
+    updated_at: String,
+}
+
+/// What to install, detached from the CLI arguments so it can move to the worker thread.
+struct UpdateRequest {
+    version: Option<String>,
+    target: Option<String>,
+    asset_pattern: Option<Pattern>,
+}
+
+pub(crate) fn handle_self_update(config_dir: &Utf8Path, args: &SelfUpdateArgs) -> Result<()> {
+    check_proxy_env()?;
+
+    let state_path = config_dir.join(STATE_FILE);
+    let version = if args.rollback {
+        let state = read_state(&state_path)?.ok_or_else(|| {
+            anyhow!("no previous version recorded in {state_path}; nothing to roll back to")
+        })?;
+        Some(state.previous_version)
+    } else {
+        args.version.clone()
+    };
+
+    let request = UpdateRequest {
+        version: version.as_deref().map(normalize_tag),
+        target: args.target.clone(),
+        asset_pattern: args
+            .asset_pattern
+            .as_deref()
+            .map(|raw| {
+                Pattern::new(raw).map_err(|err| anyhow!("invalid --asset-pattern '{raw}': {err}"))
+            })
+            .transpose()?,
+    };
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(apply_self_update(&request));
+    });
+
+    let outcome = if args.timeout == 0 {
+        rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+    } else {
+        rx.recv_timeout(Duration::from_secs(args.timeout))
+    };
+    let status = match outcome {
+        Ok(result) => result?,
+        Err(RecvTimeoutError::Timeout) => bail!(
+            "self-update timed out after {}s; retry with a larger --timeout",
+            args.timeout
+        ),
+        Err(RecvTimeoutError::Disconnected) => bail!("self-update stopped unexpectedly"),
+    };
+
+    if status.updated() {
+        // A rollback leaves the recorded version alone; recording the version just rolled back
+        // from would make the next rollback reinstall it.
+        if !args.rollback {
+            write_state(&state_path)?;
+        }
+        println!("Updated pa to {}", status.version());
+    } else {
+        println!("pa is already up to date ({})", status.version());
+    }
+
+    Ok(())
+}
+
+fn read_state(path: &Utf8Path) -> Result<Option<UpdateState>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let raw =
+        fs::read_to_string(path.as_std_path()).with_context(|| format!("failed to read {path}"))?;
+    let state = toml::from_str(&raw).with_context(|| format!("failed to parse {path}"))?;
+    Ok(Some(state))
+}
+
+fn write_state(path: &Utf8Path) -> Result<()> {
+    let binary = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Utf8PathBuf::from_path_buf(exe).ok())
+        .map(Utf8PathBuf::into_string);
+    let state = UpdateState {
+        previous_version: env!("CARGO_PKG_VERSION").to_owned(),
+        binary,
+        updated_at: current_timestamp(),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent.as_std_path())
+            .with_context(|| format!("failed to create {parent}"))?;
+    }
+    fs::write(path.as_std_path(), toml::to_string_pretty(&state)?)
+        .with_context(|| format!("failed to record update state in {path}"))
+}
+
+fn normalize_tag(version: &str) -> String {
+    if version.starts_with('v') {
+        version.to_owned()
+    } else {
+        format!("v{version}")
+    }
+}
+
+fn apply_self_update(request: &UpdateRequest) -> Result<self_update::Status> {
+    let mut builder = Update::configure();
+    builder
+        .repo_owner(REPO_OWNER)
+        .repo_name(REPO_NAME)
+        .bin_name(BIN_NAME)
+        .current_version(env!("CARGO_PKG_VERSION"))
+        .show_download_progress(true);
+
+    if let Some(version) = &request.version {
+        builder.target_version_tag(version);
+    }
+
+    if let Some(target) = &request.target {
+        builder.target(target);
+    }
+
+    if let Ok(token) = std::env::var("PA_GITHUB_TOKEN")
+        && let token = token.trim()
+        && !token.is_empty()
+    {
+        builder.auth_token(token);
+    }
+
+    if let Some(pattern) = &request.asset_pattern {
+        let updater = builder
+            .build()
+            .context("failed to configure self-updater")?;
+        let release = match &request.version {
+            Some(version) => updater.get_release_version(version),
+            None => updater.get_latest_release(),
+        }
+        .context("failed to look up release assets")?;
+        let names: Vec<&str> = release
+            .assets
+            .iter()
+            .map(|asset| asset.name.as_str())
+            .collect();
+        let asset = select_asset(&names, pattern)?;
+        // The backend picks the first asset whose name contains both the target and the
+        // identifier, so naming the asset in full for each selects exactly that one.
+        builder.target(asset).identifier(asset);
+    }
+
+    builder
+        .build()
+        .context("failed to configure self-updater")?
+        .update()
+        .context("failed to apply update")
+}
+
+fn select_asset<'a>(names: &[&'a str], pattern: &Pattern) -> Result<&'a str> {
+    names
+        .iter()
+        .copied()
+        .find(|name| pattern.matches(name))
+        .ok_or_else(|| {
+            anyhow!(
+                "no release asset matches '{pattern}'; available assets: {}",
+                names.join(", ")
+            )
+        })
+}
+
+/// The HTTP client picks up `HTTPS_PROXY` on its own; reject malformed values up front so a
+/// typo does not surface as an opaque connection failure.
+fn check_proxy_env() -> Result<()> {
+    for key in ["HTTPS_PROXY", "https_proxy"] {
+        if let Ok(value) = std::env::var(key)
+            && !value.trim().is_empty()
+            && !value.contains("://")
+        {
+            bail!("{key} must be a URL such as http://proxy:8080, got '{value}'");
+        }
+    }
+    Ok(())
+}
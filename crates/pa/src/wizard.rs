@@ -0,0 +1,172 @@
+use std::io::{BufRead, Write};
+
+use anyhow::{Result, bail};
+use prompt_assembler::{MissingInput, PromptVariableKind, is_truthy_json};
+
+/// Whether the wizard can ask questions: stdin is a terminal.
+pub(crate) fn available() -> bool {
+    atty::is(atty::Stream::Stdin)
+}
+
+/// Ask for each var in `missing` on `out` and read one answer per line from `input`.
+///
+/// Answers are checked against the var's kind and asked again when invalid. A required var,
+/// or one whose `required_if` var was answered truthy, must be answered; an empty answer skips
+/// any other var so the template's own default applies.
+pub(crate) fn ask_for_vars(
+    missing: &[MissingInput<'_>],
+    input: &mut impl BufRead,
+    out: &mut impl Write,
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let mut answers = serde_json::Map::new();
+    for item in missing {
+        let var = item.var;
+        let required = item.required
+            || var
+                .required_if
+                .as_ref()
+                .is_some_and(|condition| answers.get(condition).is_some_and(is_truthy_json));
+        let need = if required { "required" } else { "optional" };
+        loop {
+            write!(out, "{} ({}, {need})", var.name, var.kind.as_str())?;
+            if let Some(description) = &var.description {
+                write!(out, ": {description}")?;
+            }
+            write!(out, "\n> ")?;
+            out.flush()?;
+
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                if required {
+                    bail!(
+                        "input ended before required var '{}' was answered",
+                        var.name
+                    );
+                }
+                break;
+            }
+            let answer = line.trim();
+            if answer.is_empty() {
+                if required {
+                    writeln!(out, "'{}' is required", var.name)?;
+                    continue;
+                }
+                break;
+            }
+            match parse_answer(&var.kind, answer) {
+                Ok(value) => {
+                    answers.insert(var.name.clone(), value);
+                    break;
+                }
+                Err(message) => writeln!(out, "{message}")?,
+            }
+        }
+    }
+    Ok(answers)
+}
+
+fn parse_answer(
+    kind: &PromptVariableKind,
+    answer: &str,
+) -> std::result::Result<serde_json::Value, String> {
+    match kind {
+        PromptVariableKind::String | PromptVariableKind::Path => {
+            Ok(serde_json::Value::String(answer.to_owned()))
+        }
+        PromptVariableKind::Number => {
+            if let Ok(integer) = answer.parse::<i64>() {
+                return Ok(integer.into());
+            }
+            answer
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+                .map(serde_json::Value::Number)
+                .ok_or_else(|| format!("'{answer}' is not a number"))
+        }
+        PromptVariableKind::Boolean => match answer.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "y" | "1" => Ok(true.into()),
+            "false" | "no" | "n" | "0" => Ok(false.into()),
+            _ => Err(format!("'{answer}' is not a boolean; answer yes or no")),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use prompt_assembler::{MissingInput, PromptVariable, PromptVariableKind};
+
+    use super::ask_for_vars;
+
+    fn var(name: &str, kind: PromptVariableKind, required_if: Option<&str>) -> PromptVariable {
+        PromptVariable {
+            name: name.to_owned(),
+            required: false,
+            kind,
+            description: None,
+            required_if: required_if.map(str::to_owned),
+            default: None,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn asks_again_until_answers_fit_the_kind() {
+        let mut title = var("title", PromptVariableKind::String, None);
+        title.description = Some("Report title".to_owned());
+        let pages = var("pages", PromptVariableKind::Number, None);
+        let draft = var("draft", PromptVariableKind::Boolean, None);
+        let missing = [
+            MissingInput {
+                var: &title,
+                required: true,
+            },
+            MissingInput {
+                var: &pages,
+                required: false,
+            },
+            MissingInput {
+                var: &draft,
+                required: false,
+            },
+        ];
+        let mut input = Cursor::new("\nQuarterly\nmany\n12\n\n");
+        let mut out = Vec::new();
+
+        let answers = ask_for_vars(&missing, &mut input, &mut out).unwrap();
+
+        assert_eq!(answers["title"], "Quarterly");
+        assert_eq!(answers["pages"], 12);
+        assert!(!answers.contains_key("draft"));
+        let prompts = String::from_utf8(out).unwrap();
+        assert!(prompts.contains("title (string, required): Report title"));
+        assert!(prompts.contains("'title' is required"));
+        assert!(prompts.contains("'many' is not a number"));
+    }
+
+    #[test]
+    fn truthy_answer_makes_dependent_var_required() {
+        let deploy = var("deploy", PromptVariableKind::Boolean, None);
+        let target = var("target", PromptVariableKind::String, Some("deploy"));
+        let missing = [
+            MissingInput {
+                var: &deploy,
+                required: false,
+            },
+            MissingInput {
+                var: &target,
+                required: false,
+            },
+        ];
+        let mut input = Cursor::new("yes\n");
+
+        let err = ask_for_vars(&missing, &mut input, &mut Vec::new()).unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "input ended before required var 'target' was answered"
+        );
+    }
+}
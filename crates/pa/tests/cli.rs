@@ -49,6 +49,7 @@ fn first_run_creates_default_config() {
 
     let mut cmd = base_command();
     cmd.env("XDG_CONFIG_HOME", xdg_config_home.as_str());
+    cmd.env("PA_AUTO_INIT", "1");
     cmd.current_dir(temp.path());
     cmd.args(["validate", "--json"]);
 
@@ -68,6 +69,71 @@ fn first_run_creates_default_config() {
     assert!(contents.trim().is_empty(), "default config should be empty");
 }
 
+#[test]
+fn run_without_config_errors_and_suggests_init() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let xdg_config_home = root.join("xdg-config");
+
+    let mut cmd = base_command();
+    cmd.env("XDG_CONFIG_HOME", xdg_config_home.as_str());
+    cmd.env_remove("PA_AUTO_INIT");
+    cmd.current_dir(temp.path());
+    cmd.arg("list");
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("pa init"));
+    assert!(
+        !xdg_config_home.join("pa").exists(),
+        "config directory should not be created implicitly"
+    );
+}
+
+#[test]
+fn init_creates_config_conf_d_and_example() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let xdg_config_home = root.join("xdg-config");
+    let library_dir = xdg_config_home.join("pa");
+
+    let mut cmd = base_command();
+    cmd.env("XDG_CONFIG_HOME", xdg_config_home.as_str());
+    cmd.current_dir(temp.path());
+    cmd.args(["init", "--conf-d", "--example"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("created"));
+
+    assert!(library_dir.join("config.toml").exists());
+    assert!(library_dir.join("conf.d").is_dir());
+    assert!(library_dir.join("example.md").exists());
+
+    let mut run = command_with_xdg(&temp, xdg_config_home.as_ref());
+    run.args(["example", "World"]);
+    run.assert()
+        .success()
+        .stdout(predicate::str::contains("Hello World!"));
+}
+
+#[test]
+fn init_leaves_existing_config_untouched() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+    let existing = "[prompt.keep]\nprompts = [\"keep.md\"]\n";
+    fs::write(library_dir.join("config.toml").as_std_path(), existing).unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["init", "--example"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("already exists"));
+
+    let contents = fs::read_to_string(library_dir.join("config.toml").as_std_path()).unwrap();
+    assert_eq!(contents, existing);
+}
+
 #[test]
 fn prints_sequence_prompt_output() {
     let temp = TempDir::new().unwrap();
@@ -90,6 +156,38 @@ prompts = ["body.md"]
         .stdout(predicate::str::contains("Hello World!"));
 }
 
+#[test]
+fn render_json_wraps_output_in_envelope() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[prompt.simple]
+prompts = ["intro.md", "body.md"]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "intro.md", "Intro\n");
+    write_file(&library_dir, "body.md", "Hello {0}!\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["simple", "--json", "World"]);
+
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+
+    assert_eq!(json["schema_version"], Value::from(1));
+    assert_eq!(json["name"], Value::from("simple"));
+    assert_eq!(json["output"], Value::from("Intro\nHello World!\n"));
+    assert!(json["generated_at"].as_str().is_some());
+    let sources = json["sources"].as_array().unwrap();
+    assert_eq!(sources.len(), 2);
+    assert!(sources[0].as_str().unwrap().ends_with("intro.md"));
+    assert!(sources[1].as_str().unwrap().ends_with("body.md"));
+}
+
 #[test]
 fn sequence_prompts_insert_newlines_between_fragments() {
     let temp = TempDir::new().unwrap();
@@ -113,6 +211,146 @@ prompts = ["first.md", "second.md"]
     assert_eq!(stdout, "First fragment without newline\nSecond fragment\n");
 }
 
+#[test]
+fn binary_mode_concatenates_fragments_without_added_newlines() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[prompt.combo]
+prompts = ["first.md", "second.md"]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "first.md", "First  ");
+    write_file(&library_dir, "second.md", "second\t");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["combo", "--binary"]);
+
+    let assert = cmd.assert().success();
+    assert_eq!(assert.get_output().stdout, b"First  second\t");
+}
+
+#[test]
+fn lossy_flag_renders_invalid_utf8_with_warning() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.legacy]\nprompts = [\"legacy.md\"]\n",
+    )
+    .unwrap();
+    fs::write(library_dir.join("legacy.md").as_std_path(), b"caf\xe9\n").unwrap();
+
+    let mut strict = command_with_xdg(&temp, xdg_home.as_ref());
+    strict.arg("legacy");
+    strict.assert().failure();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--lossy", "legacy"]);
+
+    cmd.assert().success().stdout("caf\u{fffd}\n").stderr(
+        predicate::str::contains("warning:").and(predicate::str::contains(
+            "legacy.md is not valid UTF-8; invalid bytes were replaced",
+        )),
+    );
+}
+
+#[test]
+fn annotate_appends_footer_after_output() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.note]\nprompts = [\"note.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "note.md", "Body");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--annotate", "note"]);
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let (body, footer) = stdout.split_once('\n').unwrap();
+    assert_eq!(body, "Body");
+    assert!(footer.starts_with("<!-- generated by pa from prompt 'note' at "));
+    assert!(footer.ends_with(" -->\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--annotate=hash", "note"]);
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let last = stdout.lines().last().unwrap();
+    assert!(last.starts_with("# generated by pa from prompt 'note' at "));
+}
+
+#[test]
+fn on_missing_policies_control_absent_fragments() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.draft]\nprompts = [\"one.md\", \"absent.md\", \"two.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "one.md", "One\n");
+    write_file(&library_dir, "two.md", "Two\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--on-missing", "error", "draft"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "failed to read fragment 'absent.md'",
+    ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--on-missing", "skip", "draft"]);
+    cmd.assert()
+        .success()
+        .stdout("One\nTwo\n")
+        .stderr(predicate::str::contains(
+            "warning: skipped missing fragment 'absent.md' for prompt 'draft'",
+        ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--on-missing", "empty", "draft"]);
+    cmd.assert()
+        .success()
+        .stdout("One\nTwo\n")
+        .stderr(predicate::str::is_empty());
+}
+
+#[test]
+fn split_args_expands_single_argument() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.triple]\nprompts = [\"triple.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "triple.md", "{0}-{1}-{2}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["triple", "--split-args", "|", "a|b|c"]);
+
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert_eq!(stdout, "a-b-c\n");
+
+    let mut empty = command_with_xdg(&temp, xdg_home.as_ref());
+    empty.args(["triple", "--split-args", "", "a|b|c"]);
+    empty
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be empty"));
+}
+
 #[test]
 fn stdin_provides_first_argument() {
     let temp = TempDir::new().unwrap();
@@ -135,6 +373,44 @@ prompts = ["echo.md"]
         .stdout(predicate::str::contains("Echo piped text"));
 }
 
+#[test]
+fn stdin_json_key_nests_piped_json_in_template_context() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.report]\ntemplate = \"report.j2\"\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "report.j2",
+        "{{ title | default('untitled') }}: a={{ input.a }}\n",
+    );
+    write_file(root, "data.json", r#"{"title": "Daily"}"#);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--stdin-json-key", "input", "report"])
+        .write_stdin(r#"{"a":1}"#);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("untitled: a=1\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--stdin-json-key", "input", "report", "data.json"])
+        .write_stdin(r#"{"a":1}"#);
+    cmd.assert().success().stdout(predicate::eq("Daily: a=1\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--stdin-json-key", "input", "report"])
+        .write_stdin("not json");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("not valid JSON"));
+}
+
 #[test]
 fn prints_template_prompt_with_json_data() {
     let temp = TempDir::new().unwrap();
@@ -204,6 +480,149 @@ fn list_command_prints_available_prompts() {
     assert_eq!(lines, vec!["alpha", "bravo"]);
 }
 
+#[test]
+fn list_group_filters_and_tree_indents_dotted_names() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt."review.security"]
+prompts = ["a.md"]
+[prompt."review.style"]
+prompts = ["a.md"]
+[prompt."review.style.strict"]
+prompts = ["a.md"]
+[prompt.reviewer]
+prompts = ["a.md"]
+[prompt.simple]
+prompts = ["a.md"]
+"#,
+    )
+    .unwrap();
+
+    let mut filtered = command_with_xdg(&temp, xdg_home.as_ref());
+    filtered.args(["list", "--group", "review"]);
+    let assert = filtered.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec!["review.security", "review.style", "review.style.strict"]
+    );
+
+    let mut tree = command_with_xdg(&temp, xdg_home.as_ref());
+    tree.args(["list", "--tree"]);
+    let assert = tree.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "review",
+            "  security",
+            "  style",
+            "    strict",
+            "reviewer",
+            "simple"
+        ]
+    );
+
+    let mut json = command_with_xdg(&temp, xdg_home.as_ref());
+    json.args(["list", "--json", "--group", "review.style"]);
+    let assert = json.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    let names: Vec<_> = json["prompts"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|prompt| prompt["name"].as_str().unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["review.style", "review.style.strict"]);
+}
+
+#[test]
+fn when_env_prompts_are_skipped_and_reported_verbosely() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.always]
+prompts = ["a.md"]
+[prompt.work]
+when_env = "PA_TEST_PROFILE=work"
+prompts = ["a.md"]
+"#,
+    )
+    .unwrap();
+
+    let mut home = command_with_xdg(&temp, xdg_home.as_ref());
+    home.env("PA_TEST_PROFILE", "home")
+        .args(["list", "--verbose"]);
+    let assert = home
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("skipped: work"));
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), vec!["always"]);
+
+    let mut work = command_with_xdg(&temp, xdg_home.as_ref());
+    work.env("PA_TEST_PROFILE", "work").arg("list");
+    let assert = work.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), vec!["always", "work"]);
+}
+
+#[test]
+fn self_update_rejects_malformed_proxy() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, _) = prepare_config(&temp);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("HTTPS_PROXY", "proxy.internal:8080")
+        .args(["self-update", "--timeout", "1"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("HTTPS_PROXY must be a URL"));
+}
+
+#[test]
+fn self_update_rejects_invalid_asset_pattern() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, _) = prepare_config(&temp);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args([
+        "self-update",
+        "--asset-pattern",
+        "pa-[x86",
+        "--timeout",
+        "1",
+    ]);
+
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "invalid --asset-pattern 'pa-[x86'",
+    ));
+}
+
+#[test]
+fn self_update_rollback_requires_recorded_version() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, _) = prepare_config(&temp);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["self-update", "--rollback"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("no previous version recorded"));
+}
+
 #[test]
 fn self_update_help_is_available() {
     let temp = TempDir::new().unwrap();
@@ -214,6 +633,9 @@ fn self_update_help_is_available() {
         .assert()
         .success()
         .stdout(predicate::str::contains("Usage: pa self-update"))
+        .stdout(predicate::str::contains("--timeout <SECS>"))
+        .stdout(predicate::str::contains("--target <TRIPLE>"))
+        .stdout(predicate::str::contains("--asset-pattern <GLOB>"))
         .stdout(predicate::str::contains("--version <TAG>"));
 }
 
@@ -266,19 +688,80 @@ fn completions_include_prompts_from_conf_d() {
 }
 
 #[test]
-fn completions_error_on_unsupported_shell() {
+fn completions_include_prompt_hints() {
     let temp = TempDir::new().unwrap();
     let (xdg_home, library_dir) = prepare_config(&temp);
 
     fs::write(
         library_dir.join("config.toml").as_std_path(),
-        "[prompt.alpha]\nprompts = [\"alpha.md\"]\n",
+        r#"[prompt.report]
+template = "report.j2"
+vars = [{ name = "topic" }, { name = "audience" }]
+
+[prompt.plain]
+prompts = ["plain.md"]
+"#,
     )
     .unwrap();
-    write_file(&library_dir, "alpha.md", "Alpha\n");
+    write_file(&library_dir, "report.j2", "{{ topic }}\n");
+    write_file(&library_dir, "plain.md", "Plain {1}\n");
 
     let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
-    cmd.args(["completions", "unknown-shell"]);
+    cmd.args(["completions", "bash"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "# pa-prompt name=report kind=template args=2 vars=topic,audience",
+        ))
+        .stdout(predicate::str::contains(
+            "# pa-prompt name=plain kind=sequence args=2 vars=\n",
+        ));
+}
+
+#[test]
+fn dynamic_completions_query_pa_list() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.baked]\nprompts = [\"baked.md\"]\n",
+    )
+    .unwrap();
+
+    for shell in ["bash", "zsh", "fish"] {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.args(["completions", shell, "--dynamic"]);
+
+        cmd.assert()
+            .success()
+            .stdout(predicate::str::contains("pa list"))
+            .stdout(predicate::str::contains("_pa_prompt_list").not())
+            .stdout(predicate::str::contains("baked").not());
+    }
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["completions", "elvish", "--dynamic"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("bash, zsh, and fish"));
+}
+
+#[test]
+fn completions_error_on_unsupported_shell() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.alpha]\nprompts = [\"alpha.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "alpha.md", "Alpha\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["completions", "unknown-shell"]);
 
     cmd.assert()
         .failure()
@@ -301,6 +784,29 @@ fn parts_command_succeeds_with_no_prompts_defined() {
         .stdout(predicate::str::contains("Local only"));
 }
 
+#[test]
+fn parts_command_with_no_config_ignores_config_directory() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let xdg_home = root.join("xdg-config");
+
+    write_file(root, "a.md", "A\n");
+    write_file(root, "b.md", "B\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("PA_AUTO_INIT", "1");
+    cmd.args(["parts", "--no-config", "a.md", "b.md"]);
+
+    cmd.assert().success().stdout(predicate::eq("A\nB\n"));
+    assert!(!xdg_home.exists(), "config directory must not be created");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["list", "--no-config"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("only supported by `pa parts`"));
+}
+
 #[test]
 fn list_command_errors_when_no_prompts_defined() {
     let temp = TempDir::new().unwrap();
@@ -342,6 +848,98 @@ prompts = ["placeholder.md"]
     ));
 }
 
+#[test]
+fn parts_command_json_lists_each_part_in_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "prompt_path = \"snippets\"\n[prompt.placeholder]\nprompts = [\"placeholder.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "snippets/placeholder.md", "unused\n");
+    write_file(&library_dir, "snippets/library.md", "Library\n");
+    write_file(root, "local.md", "Local\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["parts", "--json", "local.md", "library.md"]);
+
+    let assert = cmd.assert().success();
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    let parts = json["parts"].as_array().unwrap();
+    assert_eq!(parts.len(), 2);
+    assert!(parts[0]["path"].as_str().unwrap().ends_with("local.md"));
+    assert_eq!(parts[0]["content"], Value::from("Local\n"));
+    assert!(
+        parts[1]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("snippets/library.md")
+    );
+    assert_eq!(json["content"], Value::from("Local\nLibrary\n"));
+    assert_eq!(json["schema_version"], Value::from(1));
+}
+
+#[test]
+fn parts_command_reads_concurrently_in_order() {
+    use std::fmt::Write as _;
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, _library_dir) = prepare_config(&temp);
+
+    let mut args = vec![
+        "parts".to_owned(),
+        "--concurrency".to_owned(),
+        "4".to_owned(),
+    ];
+    let mut expected = String::new();
+    for i in 0..12 {
+        write_file(root, &format!("part{i}.md"), &format!("part {i}\n"));
+        args.push(format!("part{i}.md"));
+        writeln!(expected, "part {i}").unwrap();
+    }
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(&args);
+
+    cmd.assert().success().stdout(predicate::eq(expected));
+}
+
+#[test]
+fn parts_command_writes_output_file_and_prints() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, _library_dir) = prepare_config(&temp);
+    write_file(root, "a.md", "A\n");
+    write_file(root, "b.md", "B\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["parts", "--output", "out.md", "--print", "a.md", "b.md"]);
+    cmd.assert().success().stdout(predicate::eq("A\nB\n"));
+    assert_eq!(fs::read_to_string(root.join("out.md")).unwrap(), "A\nB\n");
+}
+
+#[test]
+fn parts_command_resolves_from_root() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, _library_dir) = prepare_config(&temp);
+
+    write_file(root, "a.md", "from cwd\n");
+    write_file(root, "elsewhere/a.md", "from root\n");
+    write_file(root, "elsewhere/b.md", "second\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["parts", "--root", "elsewhere", "a.md", "b.md"]);
+
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("from root\nsecond\n"));
+}
+
 #[test]
 fn parts_command_errors_when_file_missing() {
     let temp = TempDir::new().unwrap();
@@ -367,6 +965,27 @@ prompts = ["placeholder.md"]
         .stderr(predicate::str::contains("missing part"));
 }
 
+#[test]
+fn json_errors_reports_unknown_prompt_as_envelope() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.alpha]\nprompts = [\"alpha.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "alpha.md", "Alpha\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--json-errors", "missing"]);
+
+    let output = cmd.assert().failure().code(3).get_output().clone();
+    let envelope: Value = serde_json::from_slice(&output.stderr).expect("stderr is JSON");
+    assert_eq!(envelope["error"]["kind"], "unknown_prompt");
+    assert_eq!(envelope["error"]["message"], "unknown prompt: missing");
+}
+
 #[test]
 fn errors_for_unknown_prompt_name() {
     let temp = TempDir::new().unwrap();
@@ -407,6 +1026,87 @@ fn errors_when_template_missing_data_cli() {
         .stderr(predicate::str::contains("data file"));
 }
 
+#[test]
+fn renders_template_with_yaml_data_file_cli() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.tmpl]\ntemplate = \"tmpl.j2\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "tmpl.j2", "Hello {{ name }}\n");
+    write_file(root, "data.yaml", "name: Ada\n");
+    write_file(root, "data.YML", "name: Grace\n");
+
+    for (file, expected) in [("data.yaml", "Hello Ada\n"), ("data.YML", "Hello Grace\n")] {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.args(["tmpl", file]);
+        cmd.assert().success().stdout(predicate::eq(expected));
+    }
+}
+
+#[test]
+fn render_subcommand_takes_data_only_from_flag() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.seq]\nprompts = [\"seq.md\"]\n\n\
+         [prompt.tmpl]\ntemplate = \"tmpl.j2\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "seq.md", "Summarize {0} as {1}\n");
+    write_file(
+        &library_dir,
+        "tmpl.j2",
+        "Hello {{ name }} ({{ _args[0] }})\n",
+    );
+    write_file(root, "data.json", r#"{"name": "Ada"}"#);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args([
+        "render",
+        "seq",
+        "--arg",
+        "report.json",
+        "--arg",
+        "-bullets-",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Summarize report.json as -bullets-\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args([
+        "render",
+        "tmpl",
+        "--data",
+        "data.json",
+        "--arg",
+        "extra.json",
+    ]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Hello Ada (extra.json)\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["render", "tmpl", "--arg", "data.json"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("requires a data file"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["render", "seq", "--data", "data.json"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("does not accept structured data"));
+}
+
 #[test]
 fn errors_when_sequence_prompt_passed_data_file_cli() {
     let temp = TempDir::new().unwrap();
@@ -536,6 +1236,66 @@ prompts = ["beta.md"]
     );
 }
 
+#[test]
+fn list_format_flag_supports_jsonl_toml_and_compact() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.alpha]\nprompts = [\"a.md\"]\n[prompt.bravo]\nprompts = [\"b.md\"]\n",
+    )
+    .unwrap();
+
+    let mut jsonl = command_with_xdg(&temp, xdg_home.as_ref());
+    jsonl.args(["list", "--format", "jsonl"]);
+    let assert = jsonl.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let names: Vec<String> = stdout
+        .lines()
+        .map(|line| {
+            let value: Value = serde_json::from_str(line).unwrap();
+            value["name"].as_str().unwrap().to_owned()
+        })
+        .collect();
+    assert_eq!(names, vec!["alpha", "bravo"]);
+
+    let mut toml_cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    toml_cmd.args(["list", "--format", "toml"]);
+    let assert = toml_cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let parsed: toml::Value = toml::from_str(&stdout).unwrap();
+    assert_eq!(parsed["schema_version"].as_integer(), Some(1));
+    assert_eq!(parsed["prompts"].as_array().unwrap().len(), 2);
+
+    let mut compact = command_with_xdg(&temp, xdg_home.as_ref());
+    compact.args(["list", "--format", "compact"]);
+    let assert = compact.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    assert_eq!(stdout.lines().count(), 1);
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["prompts"].as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn json_flag_conflicts_with_explicit_format() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.alpha]\nprompts = [\"a.md\"]\n",
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json", "--format", "toml"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
 #[test]
 fn show_json_returns_prompt() {
     let temp = TempDir::new().unwrap();
@@ -602,6 +1362,18 @@ fn show_json_missing_prompt_exits_one() {
         .failure()
         .code(predicate::eq(1))
         .stderr(predicate::str::contains("unknown prompt"));
+
+    for args in [
+        ["show", "missing", "--json-errors"],
+        ["deps", "missing", "--json-errors"],
+        ["open", "missing", "--json-errors"],
+    ] {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.args(args);
+        let output = cmd.assert().failure().code(3).get_output().clone();
+        let envelope: Value = serde_json::from_slice(&output.stderr).expect("stderr is JSON");
+        assert_eq!(envelope["error"]["kind"], "unknown_prompt", "{args:?}");
+    }
 }
 
 #[test]
@@ -691,3 +1463,1625 @@ prompts = ["one.md"]
     assert_eq!(warnings.len(), 1);
     assert_eq!(warnings[0]["code"], Value::from("override"));
 }
+
+#[test]
+fn validate_scoped_to_prompts_ignores_unrelated_errors() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.problem]
+prompts = ["problem.md"]
+vars = [{ name = "seed" }, { name = "seed" }]
+
+[prompt.good]
+prompts = ["good.md"]
+tags = ["ci"]
+
+[prompt.broken]
+template = "broken.j2"
+tags = ["ci"]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "problem.md", "Problem\n");
+    write_file(&library_dir, "good.md", "Good\n");
+    write_file(&library_dir, "broken.j2", "{% if %}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--prompt", "good"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("configuration is valid"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--prompt", "problem", "--json"]);
+    let assert = cmd.assert().failure().code(predicate::eq(2));
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    let errors = json["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["prompt"], Value::from("problem"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--prompt", "broken", "--json"]);
+    let assert = cmd.assert().failure().code(predicate::eq(2));
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    let errors = json["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["prompt"], Value::from("broken"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--prompt", "missing"]);
+    cmd.assert()
+        .failure()
+        .code(predicate::eq(1))
+        .stderr(predicate::str::contains("unknown prompt: missing"));
+}
+
+#[test]
+fn validate_scoped_to_tag_checks_templates_of_tagged_prompts() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.good]
+prompts = ["good.md"]
+tags = ["docs"]
+
+[prompt.broken]
+template = "broken.j2"
+tags = ["ci"]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "good.md", "Good\n");
+    write_file(&library_dir, "broken.j2", "{% if %}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--tag", "docs"]);
+    cmd.assert().success();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--tag", "ci"]);
+    cmd.assert()
+        .failure()
+        .code(predicate::eq(2))
+        .stderr(predicate::str::contains("prompt 'broken'"));
+}
+
+#[test]
+fn validate_reports_template_syntax_errors() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.broken]\ntemplate = \"broken.j2\"\n[prompt.fine]\ntemplate = \"fine.j2\"\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "broken.j2",
+        "Header\n{% for item in items %}\n{{ item }}\n",
+    );
+    write_file(&library_dir, "fine.j2", "{{ value }}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json"]);
+
+    let assert = cmd.assert().failure().code(predicate::eq(2));
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+
+    let errors = json["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["code"], Value::from("invalid_prompt"));
+    assert!(errors[0]["file"].as_str().unwrap().ends_with("broken.j2"));
+    assert!(errors[0]["line"].as_u64().is_some());
+    assert!(
+        errors[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("prompt 'broken'")
+    );
+}
+
+#[test]
+fn validate_reports_malformed_declared_data_file() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.report]\ntemplate = \"report.j2\"\ndata = \"fixtures/report.json\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "report.j2", "{{ title }}\n");
+    write_file(&library_dir, "fixtures/report.json", "{\"title\": ");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json"]);
+
+    let assert = cmd.assert().failure().code(predicate::eq(2));
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+
+    let errors = json["errors"].as_array().unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0]["code"], Value::from("parse_error"));
+    assert!(
+        errors[0]["file"]
+            .as_str()
+            .unwrap()
+            .ends_with("fixtures/report.json")
+    );
+
+    write_file(
+        &library_dir,
+        "fixtures/report.json",
+        "{\"title\": \"Weekly\"}",
+    );
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["report"]);
+    cmd.assert().success().stdout(predicate::eq("Weekly\n"));
+}
+
+#[test]
+fn offline_fails_when_remote_fragment_is_not_cached() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.shared]\nprompts = [\"http://127.0.0.1:9/shared.md\"]\n",
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--offline", "shared"]);
+
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is not cached"));
+}
+
+#[test]
+fn offline_applies_to_validate() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.shared]
+prompts = [{ file = "http://127.0.0.1:9/shared.md", sha256 = "0000000000000000000000000000000000000000000000000000000000000000" }]
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--offline"]);
+
+    cmd.assert()
+        .failure()
+        .code(predicate::eq(2))
+        .stderr(predicate::str::contains("is not cached"));
+}
+
+#[cfg(all(unix, feature = "serve"))]
+#[test]
+fn serve_renders_over_socket_and_reloads_config() {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::time::{Duration, Instant};
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+    let socket = root.join("pa.sock");
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "hello.md", "Hello {0}\n");
+    write_file(&library_dir, "bye.md", "Bye {0}\n");
+
+    let mut server = std::process::Command::new(assert_cmd::cargo::cargo_bin!("pa"))
+        .env("XDG_CONFIG_HOME", &xdg_home)
+        .current_dir(temp.path())
+        .args(["serve", "--watch-config", "--socket", socket.as_str()])
+        .spawn()
+        .unwrap();
+
+    let started = Instant::now();
+    let stream = loop {
+        match UnixStream::connect(socket.as_std_path()) {
+            Ok(stream) => break stream,
+            Err(_) if started.elapsed() < Duration::from_secs(10) => {
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(err) => {
+                server.kill().unwrap();
+                panic!("pa serve did not listen: {err}");
+            }
+        }
+    };
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    let mut ask = |request: &str| {
+        writeln!(writer, "{request}").unwrap();
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        serde_json::from_str::<Value>(&line).unwrap()
+    };
+
+    let response = ask(r#"{"prompt": "hello", "args": ["Ada"]}"#);
+    // The first connection stays open while a second one is answered.
+    let mut second = UnixStream::connect(socket.as_std_path()).unwrap();
+    second
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    writeln!(second, r#"{{"prompt": "hello", "args": ["Bo"]}}"#).unwrap();
+    let mut line = String::new();
+    let concurrent = BufReader::new(&second).read_line(&mut line).map(|_| line);
+    let missing = ask(r#"{"prompt": "bye", "args": ["Ada"]}"#);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n\n[prompt.bye]\nprompts = [\"bye.md\"]\n",
+    )
+    .unwrap();
+    let reloaded = ask(r#"{"prompt": "bye", "args": ["Ada"]}"#);
+    // A symlink cycle under conf.d must not hang the change check; the reload it triggers
+    // fails and keeps the previous configuration.
+    fs::create_dir_all(library_dir.join("conf.d").as_std_path()).unwrap();
+    std::os::unix::fs::symlink(
+        library_dir.join("conf.d").as_std_path(),
+        library_dir.join("conf.d/loop").as_std_path(),
+    )
+    .unwrap();
+    let invalid = ask("not json");
+
+    server.kill().unwrap();
+    server.wait().unwrap();
+
+    assert_eq!(response["output"], Value::from("Hello Ada\n"));
+    let concurrent: Value = serde_json::from_str(&concurrent.unwrap()).unwrap();
+    assert_eq!(concurrent["output"], Value::from("Hello Bo\n"));
+    assert!(
+        missing["error"]
+            .as_str()
+            .is_some_and(|error| error.contains("bye")),
+        "{missing}"
+    );
+    assert_eq!(reloaded["output"], Value::from("Bye Ada\n"));
+    assert!(
+        invalid["error"]
+            .as_str()
+            .is_some_and(|error| error.starts_with("invalid request")),
+        "{invalid}"
+    );
+}
+
+#[cfg(all(unix, feature = "serve"))]
+#[test]
+fn serve_refuses_to_replace_a_file_that_is_not_a_socket() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+    write_file(&library_dir, "config.toml", "");
+    write_file(root, "notes.txt", "keep me\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["serve", "--socket", root.join("notes.txt").as_str()]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("is not a socket"));
+
+    assert_eq!(
+        fs::read_to_string(root.join("notes.txt").as_std_path()).unwrap(),
+        "keep me\n"
+    );
+}
+
+#[cfg(not(feature = "serve"))]
+#[test]
+fn serve_requires_the_serve_feature() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+    write_file(&library_dir, "config.toml", "");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["serve"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("`serve` feature"));
+}
+
+#[cfg(unix)]
+#[test]
+fn post_process_pipes_output_through_command() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.shout]\nprompts = [\"shout.md\"]\npost_process = \"tr a-z A-Z\"\n\n\
+         [prompt.fails]\nprompts = [\"shout.md\"]\npost_process = \"false\"\n\n\
+         [prompt.quoted]\nprompts = [\"shout.md\"]\npost_process = \"sed 's/hello world/hi/'\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "shout.md", "hello {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["shout", "world"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--allow-post-process"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--allow-post-process", "shout", "world"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("HELLO WORLD\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--allow-post-process", "quoted", "world"]);
+    cmd.assert().success().stdout(predicate::eq("hi\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--allow-post-process", "fails", "world"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("post_process command 'false'"));
+}
+
+#[cfg(unix)]
+#[test]
+fn post_process_is_killed_after_timeout() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.slow]\nprompts = [\"slow.md\"]\npost_process = \"sleep 30\"\n\
+         post_process_timeout = 1\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "slow.md", "hello\n");
+
+    let started = std::time::Instant::now();
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--allow-post-process", "slow"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("timed out after 1s"));
+    assert!(started.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[test]
+fn show_includes_usage_derived_from_vars() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.review]
+template = "review.j2"
+vars = [
+  { name = "input", required = true, type = "path" },
+  { name = "verbose", type = "boolean" },
+]
+
+[prompt.seeded]
+prompts = ["seeded.md"]
+vars = [
+  { name = "seed", type = "number" },
+  { name = "tone", default = "formal" },
+  { name = "owner", required = true },
+]
+"#,
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "review.j2",
+        "{{ input }}{% if verbose %}!{% endif %}\n",
+    );
+    write_file(&library_dir, "seeded.md", "Seed {0}, {tone}, {owner}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "review", "--json"]);
+    let assert = cmd.assert().success();
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    assert_eq!(
+        json["usage"],
+        Value::from("pa review --var input=<path> [--var verbose=<boolean>] [DATA]")
+    );
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "review"]);
+    cmd.assert().success().stdout(predicate::str::starts_with(
+        "usage: pa review --var input=<path> [--var verbose=<boolean>] [DATA]\n",
+    ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "seeded"]);
+    cmd.assert().success().stdout(predicate::str::starts_with(
+        "usage: pa seeded [--set tone=<string>] --set owner=<string> <seed:number>\n",
+    ));
+}
+
+#[cfg(feature = "git")]
+#[test]
+fn template_renders_git_context() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.branch]\ntemplate = \"branch.j2\"\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "branch.j2",
+        "{% if _git %}on {{ _git.branch }}{% else %}no repo{% endif %}\n",
+    );
+    write_file(root, "data.json", "{}");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["branch", "data.json"]);
+    cmd.assert().success().stdout(predicate::eq("no repo\n"));
+
+    let git = |args: &[&str]| {
+        let status = std::process::Command::new("git")
+            .args(["-c", "user.name=pa", "-c", "user.email=pa@example.com"])
+            .args(args)
+            .current_dir(root)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {args:?} failed");
+    };
+    git(&["init", "-q", "-b", "feature-x"]);
+    git(&["add", "data.json"]);
+    git(&["commit", "-q", "-m", "init"]);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["branch", "data.json"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("on feature-x\n"));
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[settings]\ngit_context = false\n\n[prompt.branch]\ntemplate = \"branch.j2\"\n",
+    )
+    .unwrap();
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["branch", "data.json"]);
+    cmd.assert().success().stdout(predicate::eq("no repo\n"));
+}
+
+#[test]
+fn list_detailed_shows_var_kinds() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"
+[prompt.alpha]
+vars = [
+  { name = "input", required = true, type = "path", description = "Input file" },
+  { name = "count", type = "number" },
+]
+prompts = ["alpha.md"]
+
+[prompt.beta]
+prompts = ["beta.md"]
+"#,
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["list", "--detailed"]);
+
+    cmd.assert().success().stdout(predicate::eq(
+        "alpha\n  - input (path) [required] — Input file\n  - count (number)\nbeta\n",
+    ));
+}
+
+#[test]
+fn literal_args_passes_data_file_names_to_sequences() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.explain]\nprompts = [\"explain.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "explain.md", "Explain {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["explain", "foo.json"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--literal-args"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--literal-args", "explain", "foo.json"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Explain foo.json\n"));
+}
+
+#[test]
+fn open_prints_config_and_prompt_directories() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.base]\nprompts = [\"base.md\"]\n\n\
+         [prompt.nested]\nprompt_path = \"snippets\"\nprompts = [\"nested.md\"]\n",
+    )
+    .unwrap();
+    fs::create_dir_all(library_dir.join("snippets").as_std_path()).unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["open", "--print"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq(format!("{library_dir}\n")));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["open", "--print", "nested"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::ends_with("snippets\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["open", "--print", "missing"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown prompt: missing"));
+}
+
+#[test]
+fn set_fills_named_placeholders() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.ticket]\nprompts = [\"ticket.md\"]\n\
+         vars = [{ name = \"ticket_id\", required = true }]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "ticket.md", "Ticket {ticket_id}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["ticket"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("pass --set ticket_id=VALUE"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--set", "ticket_id=PA-7", "ticket"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Ticket PA-7\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--set", "ticket_id", "ticket"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("expected NAME=VALUE"));
+}
+
+#[test]
+fn var_sets_template_context_and_sequence_placeholders() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.greeting]\ntemplate = \"greeting.j2\"\n\n\
+         [prompt.ticket]\nprompts = [\"ticket.md\"]\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "greeting.j2",
+        "Hello {{ name }} x{{ count }}\n",
+    );
+    write_file(&library_dir, "ticket.md", "Ticket {ticket_id}\n");
+    write_file(root, "data.json", r#"{"name": "file", "count": 1}"#);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--var", "name=World", "--var", "count=3", "greeting"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Hello World x3\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--var", "name=a=b", "greeting", "data.json"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Hello a=b x1\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--var", "ticket_id=PA-7", "ticket"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Ticket PA-7\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--var", "name", "greeting"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "invalid --var 'name': expected KEY=VALUE",
+    ));
+}
+
+#[test]
+fn force_renders_despite_missing_required_var() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.ticket]\nprompts = [\"ticket.md\"]\n\
+         vars = [{ name = \"ticket_id\", required = true }]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "ticket.md", "Ticket [{ticket_id}]\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["ticket"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("required var 'ticket_id'"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--force", "ticket"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Ticket []\n"))
+        .stderr(predicate::str::contains(
+            "warning: required var 'ticket_id' was not supplied",
+        ));
+}
+
+#[test]
+fn escape_wraps_output_as_json_or_shell_literal() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.quote]\nprompts = [\"quote.md\"]\n\n\
+         [prompt.shelled]\nprompts = [\"quote.md\"]\nescape = \"shell\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "quote.md", "Say \"{0}\"\n\tit's done\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--escape", "json", "quote", "hi"]);
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let decoded: String = serde_json::from_str(stdout.trim_end()).unwrap();
+    assert_eq!(decoded, "Say \"hi\"\n\tit's done\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["shelled", "hi"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("'Say \"hi\"\n\tit'\\''s done\n'\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--escape", "none", "shelled", "hi"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Say \"hi\"\n\tit's done\n"));
+}
+
+#[test]
+fn show_preview_includes_rendered_output() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.greet]\ntemplate = \"greet.j2\"\ndescription = \"Greeting\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "greet.j2", "Hello {{ name }}!\n");
+    write_file(root, "sample.json", r#"{"name": "Ada"}"#);
+    write_file(root, "broken.json", "{");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "greet", "--preview", "sample.json"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("description: Greeting"))
+        .stdout(predicate::str::ends_with("preview:\nHello Ada!\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "greet", "--json", "--preview", "sample.json"]);
+    let assert = cmd.assert().success();
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    assert_eq!(json["profile"]["rendered"], Value::from("Hello Ada!\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "greet", "--preview", "broken.json"]);
+    cmd.assert()
+        .failure()
+        .stdout(predicate::str::contains("description: Greeting"))
+        .stderr(predicate::str::contains(
+            "failed to render preview of 'greet'",
+        ));
+}
+
+#[test]
+fn show_never_pages_without_a_terminal() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.greet]\ntemplate = \"greet.j2\"\ndescription = \"Greeting\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "greet.j2", "Hello {{ name }}!\n");
+
+    // A pager that would swallow the output, with a terminal too short for it.
+    let show = |extra: &[&str]| {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.env("PAGER", "false").env("LINES", "1");
+        cmd.args(["show", "greet"]).args(extra);
+        cmd
+    };
+
+    show(&["--no-pager"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("description: Greeting"));
+    show(&[])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("description: Greeting"));
+
+    let assert = show(&["--json"]).assert().success();
+    let json: Value = serde_json::from_slice(&assert.get_output().stdout).unwrap();
+    assert_eq!(json["description"], Value::from("Greeting"));
+}
+
+#[test]
+fn orphans_lists_unreferenced_library_files() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.alpha]\nprompts = [\"alpha.md\"]\n\n[prompt.page]\ntemplate = \"page.j2\"\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "alpha.md",
+        "Alpha {{{> stale.md }}\n{{> shared/footer.md }}\n",
+    );
+    write_file(&library_dir, "shared/footer.md", "Footer\n");
+    write_file(
+        &library_dir,
+        "page.j2",
+        "{% include \"partials/head.j2\" %}\n",
+    );
+    write_file(&library_dir, "partials/head.j2", "Head\n");
+    write_file(&library_dir, "stale.md", "Unused\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["orphans", "--json"]);
+    let output = cmd.assert().success().get_output().clone();
+    let payload: Value = serde_json::from_slice(&output.stdout).expect("valid JSON");
+    let orphans = payload["orphans"].as_array().expect("orphans array");
+    assert_eq!(orphans.len(), 1);
+    assert!(orphans[0].as_str().unwrap().ends_with("stale.md"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("orphans");
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("stale.md"))
+        .stdout(predicate::str::contains("alpha.md").not());
+}
+
+#[test]
+fn trace_render_lists_each_fragment_with_duration() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.review]\nprompts = [\"intro.md\", \"body.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "intro.md", "Intro\n");
+    write_file(&library_dir, "body.md", "Body {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--trace-render", "review", "x"]);
+    let output = cmd.assert().success().get_output().clone();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "Intro\nBody x\n");
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for fragment in ["intro.md", "body.md"] {
+        let line = stderr
+            .lines()
+            .find(|line| line.starts_with(fragment))
+            .unwrap_or_else(|| panic!("no trace line for {fragment} in {stderr}"));
+        assert!(line.ends_with("ms"), "{line}");
+    }
+    assert!(stderr.contains("total: "));
+}
+
+#[test]
+fn absolute_paths_flag_resolves_json_paths() {
+    let temp = TempDir::new().unwrap();
+    let (_, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.echo]\nprompts = [\"echo.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "echo.md", "{0}\n");
+    let canonical_library = library_dir.canonicalize_utf8().unwrap();
+
+    let mut cmd = base_command();
+    cmd.env("XDG_CONFIG_HOME", "xdg-config");
+    cmd.current_dir(temp.path());
+    cmd.args(["list", "--json"]);
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        json["prompts"][0]["source_path"],
+        Value::from("xdg-config/pa/config.toml")
+    );
+
+    let mut cmd = base_command();
+    cmd.env("XDG_CONFIG_HOME", "xdg-config");
+    cmd.current_dir(temp.path());
+    cmd.args(["list", "--json", "--absolute-paths"]);
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        json["prompts"][0]["source_path"],
+        Value::from(canonical_library.join("config.toml").as_str())
+    );
+
+    let mut cmd = base_command();
+    cmd.env("XDG_CONFIG_HOME", "xdg-config");
+    cmd.current_dir(temp.path());
+    cmd.args(["show", "echo", "--json", "--absolute-paths"]);
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(
+        json["profile"]["parts"][0]["path"],
+        Value::from(canonical_library.join("echo.md").as_str())
+    );
+}
+
+#[test]
+fn print_config_path_reports_xdg_override_without_loading() {
+    let temp = TempDir::new().unwrap();
+    let xdg_home = utf8_path(temp.path()).join("elsewhere");
+
+    let mut cmd = command_with_xdg(&temp, &xdg_home);
+    cmd.arg("--print-config-path");
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq(format!("{}\n", xdg_home.join("pa"))));
+    assert!(!xdg_home.exists());
+}
+
+#[test]
+fn each_renders_template_once_per_array_element() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.greet]\ntemplate = \"greet.j2\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "greet.j2", "Hello {{ name }}\n");
+    let root = utf8_path(temp.path());
+    write_file(
+        root,
+        "people.json",
+        r#"[{"name": "Ada"}, {"name": "Grace"}]"#,
+    );
+    write_file(root, "single.json", r#"{"name": "Ada"}"#);
+    write_file(root, "people.yaml", "- name: Ada\n- name: Grace\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--each", "greet", "people.json"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Hello Ada\n\nHello Grace\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--each", "greet", "people.yaml"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("Hello Ada\n\nHello Grace\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--each", "greet", "single.json"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("to contain an array"));
+}
+
+#[test]
+fn validate_warns_about_placeholder_index_gaps() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.gap]\nprompts = [\"gap.md\"]\n\n[prompt.dense]\nprompts = [\"dense.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "gap.md", "From {0} to {2}\n");
+    write_file(&library_dir, "dense.md", "{1} then {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json"]);
+
+    let assert = cmd.assert().success();
+    let stdout = String::from_utf8(assert.get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+
+    let warnings = json["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0]["code"], Value::from("placeholder_gap"));
+    assert_eq!(warnings[0]["prompt"], Value::from("gap"));
+    assert!(
+        warnings[0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("never {1}")
+    );
+}
+
+#[test]
+fn default_prompt_env_runs_when_no_prompt_is_named() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.echo]\nprompts = [\"echo.md\"]\n\n[prompt.other]\nprompts = [\"other.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "echo.md", "echo {0}\n");
+    write_file(&library_dir, "other.md", "other\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("PA_DEFAULT_PROMPT", "echo");
+    cmd.args(["--", "arg1"]);
+    cmd.assert().success().stdout(predicate::eq("echo arg1\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("PA_DEFAULT_PROMPT", "echo");
+    cmd.arg("other");
+    cmd.assert().success().stdout(predicate::eq("other\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("PA_DEFAULT_PROMPT", "echo");
+    cmd.args(["--json-errors", "ech"]);
+    let output = cmd.assert().failure().code(3).get_output().clone();
+    let envelope: Value = serde_json::from_slice(&output.stderr).expect("stderr is JSON");
+    let message = envelope["error"]["message"].as_str().unwrap();
+    assert!(message.contains("unknown prompt: ech"), "{message}");
+    assert!(message.contains("echo"), "{message}");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env_remove("PA_DEFAULT_PROMPT");
+    cmd.args(["--", "arg1"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("prompt name is required"));
+}
+
+#[test]
+fn output_and_print_write_identical_content() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.echo]\nprompts = [\"echo.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "echo.md", "echo {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--output", "f.txt", "--print", "echo", "arg1"]);
+    cmd.assert().success().stdout(predicate::eq("echo arg1\n"));
+    let written = fs::read_to_string(temp.path().join("f.txt")).unwrap();
+    assert_eq!(written, "echo arg1\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--output", "quiet.txt", "echo", "arg2"]);
+    cmd.assert().success().stdout(predicate::str::is_empty());
+    let written = fs::read_to_string(temp.path().join("quiet.txt")).unwrap();
+    assert_eq!(written, "echo arg2\n");
+}
+
+#[test]
+fn tags_command_counts_prompts_per_tag() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[prompt.a]
+prompts = ["a.md"]
+tags = ["review", "rust"]
+
+[prompt.b]
+prompts = ["a.md"]
+tags = ["rust", "docs"]
+
+[prompt.c]
+prompts = ["a.md"]
+tags = ["rust", "review"]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "a.md", "a\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("tags");
+    cmd.assert()
+        .success()
+        .stdout(predicate::eq("rust    3\nreview  2\ndocs    1\n"));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["tags", "--json"]);
+    let stdout = String::from_utf8(cmd.assert().success().get_output().stdout.clone()).unwrap();
+    let json: Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(json["tags"]["rust"], Value::from(3));
+    assert_eq!(json["tags"]["review"], Value::from(2));
+    assert_eq!(json["tags"]["docs"], Value::from(1));
+    let order: Vec<usize> = ["\"rust\"", "\"review\"", "\"docs\""]
+        .iter()
+        .map(|tag| stdout.find(tag).unwrap())
+        .collect();
+    assert!(order.is_sorted(), "{stdout}");
+}
+
+#[test]
+fn audit_log_records_each_render_without_content() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[settings]\naudit_log = \"audit/renders.jsonl\"\n\n[prompt.echo]\nprompts = [\"echo.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "echo.md", "echo {0}\n");
+
+    for arg in ["first", "second"] {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.args(["echo", arg]);
+        cmd.assert().success();
+    }
+
+    let log = fs::read_to_string(library_dir.join("audit/renders.jsonl")).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 2);
+    let record: Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(record["prompt"], Value::from("echo"));
+    assert_eq!(record["version"], Value::from(env!("CARGO_PKG_VERSION")));
+    assert_eq!(
+        record["source"],
+        Value::from(library_dir.join("config.toml").as_str())
+    );
+    assert_eq!(
+        record["output_sha256"],
+        Value::from("93fa6d2343372bd5d804d19068d4793afdf000a3ca9f62c9aefdcb1ea992db9d")
+    );
+    assert!(record["timestamp"].as_str().is_some());
+    assert!(!log.contains("echo first"));
+}
+
+#[test]
+fn audit_log_skips_renders_that_fail_to_deliver() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[settings]\naudit_log = \"renders.jsonl\"\n\n[prompt.echo]\nprompts = [\"echo.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "echo.md", "echo {0}\n");
+    let occupied = utf8_path(temp.path()).join("occupied");
+    fs::create_dir_all(occupied.as_std_path()).unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["echo", "--output", occupied.as_str(), "first"]);
+    cmd.assert().failure();
+
+    assert!(!library_dir.join("renders.jsonl").exists());
+}
+
+#[test]
+fn invalid_audit_log_is_an_invalid_setting() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[settings]\nrequire_absolute_paths = true\naudit_log = \"renders.jsonl\"\n\n[prompt.echo]\ninline = \"echo\"\n",
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json"]);
+    let output = cmd.assert().code(2).get_output().clone();
+    let report: Value = serde_json::from_slice(&output.stdout).expect("stdout is JSON");
+    assert_eq!(report["errors"][0]["code"], Value::from("invalid_setting"));
+}
+
+#[test]
+fn unknown_prompt_suggests_case_insensitive_match() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.troubleshoot]\nprompts = [\"troubleshoot.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "troubleshoot.md", "Troubleshoot\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("Troubleshoot");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unknown prompt: Troubleshoot (did you mean 'troubleshoot'?)",
+    ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["show", "TROUBLESHOOT"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("did you mean 'troubleshoot'?"));
+}
+
+#[test]
+fn unknown_prompt_suggests_names_within_a_small_edit_distance() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.review]\nprompts = [\"a.md\"]\n\n[prompt.preview]\nprompts = [\"a.md\"]\n\n[prompt.summarize]\nprompts = [\"a.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "a.md", "a\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("summarise");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unknown prompt: summarise (did you mean 'summarize'?)",
+    ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("reviews");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "(did you mean: 'review', 'preview'?)",
+    ));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["--json-errors", "deploy"]);
+    let output = cmd.assert().failure().code(3).get_output().clone();
+    let envelope: Value = serde_json::from_slice(&output.stderr).expect("stderr is JSON");
+    assert_eq!(envelope["error"]["message"], "unknown prompt: deploy");
+}
+
+#[test]
+fn format_messages_splits_sequence_by_role() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.chat]\nprompts = [\n  { file = \"system.md\", role = \"system\" },\n  { file = \"user.md\", role = \"user\" },\n]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "system.md", "You are terse.\n");
+    write_file(&library_dir, "user.md", "Explain {0}.\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["chat", "--format", "messages", "ownership"]);
+    let output = cmd.assert().success().get_output().clone();
+    let messages: Value = serde_json::from_slice(&output.stdout).expect("stdout is JSON");
+    assert_eq!(
+        messages,
+        serde_json::json!([
+            { "role": "system", "content": "You are terse.\n" },
+            { "role": "user", "content": "Explain ownership.\n" },
+        ])
+    );
+}
+
+#[test]
+fn format_messages_keeps_roles_of_repeated_entries_apart() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.chat]\nprompts = [\n  { file = \"turn.md\", role = \"user\" },\n  \"sep.md\",\n  { file = \"turn.md\", role = \"assistant\" },\n]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "turn.md", "Turn.\n");
+    write_file(&library_dir, "sep.md", "--\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["chat", "--format", "messages"]);
+    let output = cmd.assert().success().get_output().clone();
+    let messages: Value = serde_json::from_slice(&output.stdout).expect("stdout is JSON");
+    assert_eq!(
+        messages,
+        serde_json::json!([
+            { "role": "user", "content": "Turn.\n--\n" },
+            { "role": "assistant", "content": "Turn.\n" },
+        ])
+    );
+}
+
+#[test]
+fn dry_run_reports_output_file_without_writing_it() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "hello.md", "Hello {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["hello", "--dry-run", "--output", "f.txt", "world"]);
+    cmd.assert()
+        .success()
+        .stdout("")
+        .stderr(predicate::str::contains(
+            "dry run: would write 12 bytes to f.txt",
+        ));
+    assert!(!temp.path().join("f.txt").exists());
+}
+
+#[test]
+fn data_format_reads_data_regardless_of_extension() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.greet]\ntemplate = \"greet.j2\"\ndata_format = \"toml\"\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "greet.j2", "Hello {{ name }}\n");
+    fs::write(temp.path().join("input.dat"), "name = \"Ada\"\n").unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["greet", "input.dat"]);
+    cmd.assert().success().stdout("Hello Ada\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.arg("greet").write_stdin("name = \"Grace\"\n");
+    cmd.assert().success().stdout("Hello Grace\n");
+}
+
+#[test]
+fn interactive_without_a_terminal_reads_data_as_usual() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[prompt.report]
+template = "report.j2"
+vars = [{ name = "title", required = true, description = "Report title" }]
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "report.j2", "{{ title }}\n");
+
+    write_file(
+        utf8_path(temp.path()),
+        "data.json",
+        r#"{"title": "Quarterly"}"#,
+    );
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["report", "--interactive", "data.json"]);
+    cmd.assert()
+        .success()
+        .stdout("Quarterly\n")
+        .stderr(predicate::str::contains("Report title").not());
+}
+
+#[test]
+fn trim_removes_exactly_one_trailing_newline() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.word]\ntemplate_inline = \"{{ word }}\\n\\n\"\n",
+    )
+    .unwrap();
+    write_file(utf8_path(temp.path()), "data.json", r#"{"word": "hi"}"#);
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["word", "--trim", "data.json"]);
+    cmd.assert().success().stdout("hi\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["word", "--trim=all", "data.json"]);
+    cmd.assert().success().stdout("hi");
+}
+
+#[test]
+fn preset_seeds_context_and_data_overrides_it() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[prompt.deploy]
+template_inline = "{{ region }}/{{ tier }}\n"
+
+[prompt.deploy.presets.prod]
+region = "us-east"
+tier = "gold"
+
+[prompt.deploy.presets.staging]
+region = "us-west"
+tier = "bronze"
+"#,
+    )
+    .unwrap();
+    write_file(
+        utf8_path(temp.path()),
+        "eu.json",
+        r#"{"region": "eu-central"}"#,
+    );
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["deploy", "--preset", "prod"]);
+    cmd.assert().success().stdout("us-east/gold\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["deploy", "--preset", "prod", "eu.json"]);
+    cmd.assert().success().stdout("eu-central/gold\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["deploy", "--preset", "dev"]);
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "unknown preset 'dev' for prompt 'deploy'; available: prod, staging",
+    ));
+}
+
+#[test]
+fn to_tempfile_prints_path_of_kept_file() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+    let tmpdir = temp.path().join("tmp");
+    fs::create_dir(&tmpdir).unwrap();
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "hello.md", "Hello {0}\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.env("TMPDIR", &tmpdir)
+        .args(["hello", "--to-tempfile", "world"]);
+    let output = cmd.assert().success().get_output().clone();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let path = std::path::Path::new(stdout.trim_end());
+
+    assert!(path.starts_with(&tmpdir), "{stdout}");
+    assert_eq!(fs::read_to_string(path).unwrap(), "Hello world\n");
+}
+
+#[test]
+fn new_scaffolds_prompts_and_refuses_existing_names() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n",
+    )
+    .unwrap();
+    write_file(&library_dir, "hello.md", "Hello\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["new", "draft"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "created {}",
+            library_dir.join("draft.md")
+        )));
+    assert_eq!(
+        fs::read_to_string(library_dir.join("config.toml").as_std_path()).unwrap(),
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n\n[prompt.draft]\nprompts = [\"draft.md\"]\n"
+    );
+    assert_eq!(
+        fs::read_to_string(library_dir.join("draft.md").as_std_path()).unwrap(),
+        ""
+    );
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["new", "review.code", "--kind", "template", "--conf-d"]);
+    cmd.assert().success();
+    assert_eq!(
+        fs::read_to_string(library_dir.join("conf.d/review.code.toml").as_std_path()).unwrap(),
+        "[prompt.\"review.code\"]\ntemplate = \"review.code.j2\"\n"
+    );
+    assert!(library_dir.join("review.code.j2").is_file());
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["list"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("draft").and(predicate::str::contains("review.code")));
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["new", "hello"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("prompt 'hello' already exists"));
+}
+
+#[test]
+fn validate_warns_about_keys_the_prompt_kind_ignores() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.report]\ninline = \"Report\"\nprompt_path = \"reports\"\n",
+    )
+    .unwrap();
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["validate", "--json"]);
+    let output = cmd.assert().success().get_output().clone();
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    let warnings = json["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 1, "{json}");
+    assert_eq!(warnings[0]["code"], Value::from("ignored_key"));
+    assert_eq!(
+        warnings[0]["message"],
+        Value::from(
+            "prompt 'report' sets prompt_path, which inline prompts do not use; it is ignored"
+        )
+    );
+}
+
+#[test]
+fn global_prepend_and_append_wrap_every_prompt() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        r#"[settings]
+global_prepend = "disclaimer.md"
+global_append = "footer.md"
+
+[prompt.hello]
+prompts = ["hello.md"]
+
+[prompt.note]
+inline = "A note"
+
+[prompt.card]
+template_inline = "Card for {{ name }}"
+"#,
+    )
+    .unwrap();
+    write_file(&library_dir, "disclaimer.md", "Internal use only.");
+    write_file(&library_dir, "footer.md", "-- end --\n");
+    write_file(&library_dir, "hello.md", "Hello {0}\n");
+    write_file(utf8_path(temp.path()), "card.json", r#"{"name": "you"}"#);
+
+    for (args, body) in [
+        (["hello", "you"], "Hello you\n"),
+        (["note", "you"], "A note\n"),
+        (["card", "card.json"], "Card for you"),
+    ] {
+        let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+        cmd.args(args);
+        cmd.assert()
+            .success()
+            .stdout(format!("Internal use only.\n{body}-- end --\n"));
+    }
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["hello", "--no-global", "you"]);
+    cmd.assert().success().stdout("Hello you\n");
+}
+
+#[test]
+fn deps_prints_tree_of_included_templates() {
+    let temp = TempDir::new().unwrap();
+    let (xdg_home, library_dir) = prepare_config(&temp);
+
+    fs::write(
+        library_dir.join("config.toml").as_std_path(),
+        "[prompt.report]\ntemplate = \"report.j2\"\n",
+    )
+    .unwrap();
+    write_file(
+        &library_dir,
+        "report.j2",
+        "{% include \"partials/header.j2\" %}\n{% import 'partials/macros.j2' as m %}\n",
+    );
+    write_file(&library_dir, "partials/header.j2", "Header\n");
+    write_file(
+        &library_dir,
+        "partials/macros.j2",
+        "{% macro x() %}x{% endmacro %}\n",
+    );
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["deps", "report"]);
+    cmd.assert()
+        .success()
+        .stdout("report.j2\n├── partials/header.j2\n└── partials/macros.j2\n");
+
+    let mut cmd = command_with_xdg(&temp, xdg_home.as_ref());
+    cmd.args(["deps", "report", "--format", "json"]);
+    let output = cmd.assert().success().get_output().clone();
+    let json: Value = serde_json::from_slice(&output.stdout).unwrap();
+    let tree = &json["tree"];
+    assert_eq!(tree["name"], "report.j2");
+    let children: Vec<&str> = tree["dependencies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|child| child["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(children, ["partials/header.j2", "partials/macros.j2"]);
+}
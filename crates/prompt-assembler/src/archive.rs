@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::{Context, anyhow};
+use camino::{Utf8Path, Utf8PathBuf};
+use flate2::read::GzDecoder;
+
+use crate::{FragmentSource, Result, SourceEntry};
+
+/// Every file in one archive, keyed by its `/`-separated path inside the archive.
+type Entries = Arc<HashMap<String, Vec<u8>>>;
+
+/// Serves paths inside a `.zip`, `.tar.gz`, or `.tgz` file and delegates everything else.
+///
+/// A `prompt_path` such as `library.zip` makes fragments resolve to `library.zip/intro.md`;
+/// the first ancestor of a path that is an archive file on disk is opened, and the rest of the
+/// path names the entry. Each archive is read once and kept in memory for the life of the
+/// source.
+pub struct ArchiveSource<S> {
+    inner: S,
+    archives: Mutex<HashMap<Utf8PathBuf, Entries>>,
+}
+
+impl<S> ArchiveSource<S> {
+    /// Wrap `inner`, which serves every path that is not inside an archive.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            archives: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Split `path` into the archive containing it and the entry name, when it is inside one.
+    fn locate(path: &Utf8Path) -> Option<(&Utf8Path, String)> {
+        path.ancestors().skip(1).find_map(|ancestor| {
+            if !is_archive_name(ancestor.as_str()) || !ancestor.is_file() {
+                return None;
+            }
+            let entry = path.strip_prefix(ancestor).ok()?;
+            let name: Vec<&str> = entry.components().map(|part| part.as_str()).collect();
+            Some((ancestor, name.join("/")))
+        })
+    }
+
+    /// Like [`ArchiveSource::locate`], but an archive file itself is its own root directory.
+    fn locate_dir(path: &Utf8Path) -> Option<(&Utf8Path, String)> {
+        if is_archive_name(path.as_str()) && path.is_file() {
+            return Some((path, String::new()));
+        }
+        Self::locate(path)
+    }
+
+    fn entries(&self, archive: &Utf8Path) -> Result<Entries> {
+        let mut archives = self.archives.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entries) = archives.get(archive) {
+            return Ok(Arc::clone(entries));
+        }
+        let entries = Arc::new(
+            load_archive(archive).with_context(|| format!("failed to read archive {archive}"))?,
+        );
+        archives.insert(archive.to_owned(), Arc::clone(&entries));
+        Ok(entries)
+    }
+
+    fn read_entry(&self, archive: &Utf8Path, entry: &str) -> Result<Vec<u8>> {
+        self.entries(archive)?
+            .get(entry)
+            .cloned()
+            .ok_or_else(|| anyhow!("{entry} not found in archive {archive}"))
+    }
+}
+
+impl<S> fmt::Debug for ArchiveSource<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveSource").finish_non_exhaustive()
+    }
+}
+
+impl<S: FragmentSource> FragmentSource for ArchiveSource<S> {
+    fn read(&self, path: &Utf8Path) -> Result<String> {
+        let Some((archive, entry)) = Self::locate(path) else {
+            return self.inner.read(path);
+        };
+        String::from_utf8(self.read_entry(archive, &entry)?)
+            .map_err(|_| anyhow!("{entry} in archive {archive} is not valid UTF-8"))
+    }
+
+    fn read_bytes(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        match Self::locate(path) {
+            Some((archive, entry)) => self.read_entry(archive, &entry),
+            None => self.inner.read_bytes(path),
+        }
+    }
+
+    fn exists(&self, path: &Utf8Path) -> bool {
+        match Self::locate(path) {
+            Some((archive, entry)) => self
+                .entries(archive)
+                .is_ok_and(|entries| entries.contains_key(&entry)),
+            None => self.inner.exists(path),
+        }
+    }
+
+    fn is_dir(&self, path: &Utf8Path) -> bool {
+        match Self::locate_dir(path) {
+            Some((archive, entry)) => self.entries(archive).is_ok_and(|entries| {
+                let prefix = dir_prefix(&entry);
+                entries.keys().any(|name| name.starts_with(&prefix))
+            }),
+            None => self.inner.is_dir(path),
+        }
+    }
+
+    fn list_dir(&self, dir: &Utf8Path) -> Result<Vec<SourceEntry>> {
+        let Some((archive, entry)) = Self::locate_dir(dir) else {
+            return self.inner.list_dir(dir);
+        };
+        let prefix = dir_prefix(&entry);
+        // Keyed by child name; a child with further segments below it is a directory.
+        let mut children: BTreeMap<&str, bool> = BTreeMap::new();
+        let entries = self.entries(archive)?;
+        for name in entries.keys() {
+            let Some(rest) = name.strip_prefix(&prefix) else {
+                continue;
+            };
+            match rest.split_once('/') {
+                Some((child, _)) => *children.entry(child).or_default() = true,
+                None => {
+                    children.entry(rest).or_default();
+                }
+            }
+        }
+        Ok(children
+            .into_iter()
+            .map(|(child, is_dir)| SourceEntry {
+                path: dir.join(child),
+                is_dir,
+            })
+            .collect())
+    }
+}
+
+/// The prefix every entry name inside directory `entry` starts with.
+fn dir_prefix(entry: &str) -> String {
+    if entry.is_empty() {
+        String::new()
+    } else {
+        format!("{entry}/")
+    }
+}
+
+fn is_archive_name(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    [".zip", ".tar.gz", ".tgz"]
+        .iter()
+        .any(|extension| name.ends_with(extension))
+}
+
+fn load_archive(path: &Utf8Path) -> Result<HashMap<String, Vec<u8>>> {
+    let file = File::open(path.as_std_path())?;
+    let mut entries = HashMap::new();
+    if path.as_str().to_ascii_lowercase().ends_with(".zip") {
+        let mut archive = zip::ZipArchive::new(file)?;
+        for index in 0..archive.len() {
+            let mut entry = archive.by_index(index)?;
+            if !entry.is_file() {
+                continue;
+            }
+            let Some(name) = entry.enclosed_name().and_then(|name| entry_name(&name)) else {
+                continue;
+            };
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            entries.insert(name, content);
+        }
+    } else {
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let Some(name) = entry_name(&entry.path()?) else {
+                continue;
+            };
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            entries.insert(name, content);
+        }
+    }
+    Ok(entries)
+}
+
+/// The `/`-separated name of an archive entry, without any leading `./`. Names that are not
+/// UTF-8 or that climb out of the archive are skipped.
+fn entry_name(path: &std::path::Path) -> Option<String> {
+    let path = Utf8Path::from_path(path)?;
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            camino::Utf8Component::Normal(part) => parts.push(part),
+            camino::Utf8Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (!parts.is_empty()).then(|| parts.join("/"))
+}
@@ -0,0 +1,192 @@
+use std::fmt::Write as _;
+
+use camino::Utf8Path;
+
+use crate::{Config, PromptKind, PromptSpec, PromptVariable};
+
+/// Render `config` as stable text: prompts sorted by name, paths relative to the root, and
+/// nothing machine-specific such as modification times.
+pub(crate) fn dump_config(config: &Config) -> String {
+    let root = config.root.as_path();
+    let mut lines = vec![
+        format!(
+            "prompt_path = {}",
+            optional_path(root, config.default_prompt_path.as_deref())
+        ),
+        format!(
+            "settings.require_absolute_paths = {}",
+            config.settings.require_absolute_paths
+        ),
+        format!("settings.lossy_utf8 = {}", config.settings.lossy_utf8),
+        format!(
+            "settings.remote_cache_ttl = {}",
+            config
+                .settings
+                .remote_cache_ttl
+                .map_or_else(|| "-".to_owned(), |ttl| ttl.to_string())
+        ),
+        format!("settings.git_context = {}", config.settings.git_context),
+        format!(
+            "settings.fragment_extensions = {}",
+            config.settings.fragment_extensions.as_ref().map_or_else(
+                || "-".to_owned(),
+                |extensions| format!("[{}]", extensions.join(", "))
+            )
+        ),
+        format!("settings.macros = [{}]", config.settings.macros.join(", ")),
+        format!(
+            "settings.default_prompt = {}",
+            config.settings.default_prompt.as_deref().unwrap_or("-")
+        ),
+        format!(
+            "settings.audit_log = {}",
+            optional_path(root, config.settings.audit_log.as_deref())
+        ),
+        format!(
+            "settings.context_file = {}",
+            optional_path(root, config.settings.context_file.as_deref())
+        ),
+        format!(
+            "settings.global_prepend = {}",
+            optional_path(root, config.settings.global_prepend.as_deref())
+        ),
+        format!(
+            "settings.global_append = {}",
+            optional_path(root, config.settings.global_append.as_deref())
+        ),
+    ];
+
+    let mut names: Vec<&String> = config.prompts.keys().collect();
+    names.sort();
+    for name in names {
+        lines.push(String::new());
+        dump_prompt(root, name, &config.prompts[name], &mut lines);
+    }
+
+    let mut output = lines.join("\n");
+    output.push('\n');
+    output
+}
+
+fn dump_prompt(root: &Utf8Path, name: &str, spec: &PromptSpec, lines: &mut Vec<String>) {
+    let metadata = &spec.metadata;
+    lines.push(format!("[prompt.{name}]"));
+    match &spec.kind {
+        PromptKind::Sequence { files } => {
+            let files: Vec<String> = files.iter().map(|file| normalize(file)).collect();
+            lines.push("kind = sequence".to_owned());
+            lines.push(format!("files = [{}]", files.join(", ")));
+            if let Some(body) = &spec.metadata.repeat_for_args {
+                lines.push(format!("repeat_for_args = {}", normalize(body)));
+            }
+        }
+        PromptKind::Template { template } => {
+            lines.push("kind = template".to_owned());
+            lines.push(format!("template = {}", normalize(template)));
+        }
+        PromptKind::Inline { body } => {
+            lines.push("kind = inline".to_owned());
+            lines.push(format!("inline = {body:?}"));
+        }
+        PromptKind::InlineTemplate { body } => {
+            lines.push("kind = template_inline".to_owned());
+            lines.push(format!("template_inline = {body:?}"));
+        }
+    }
+    lines.push(format!(
+        "prompt_path = {}",
+        optional_path(root, spec.prompt_path_override.as_deref())
+    ));
+    if let Some(working_dir) = &spec.working_dir {
+        lines.push(format!("working_dir = {}", relative(root, working_dir)));
+    }
+    lines.push(format!(
+        "source = {}",
+        relative(root, &metadata.source.path)
+    ));
+    if let Some(description) = &metadata.description {
+        lines.push(format!("description = {description:?}"));
+    }
+    if !metadata.tags.is_empty() {
+        lines.push(format!("tags = [{}]", metadata.tags.join(", ")));
+    }
+    if let Some(stdin) = metadata.stdin_supported {
+        lines.push(format!("stdin = {stdin}"));
+    }
+    if let Some(command) = &metadata.post_process {
+        lines.push(format!("post_process = {command:?}"));
+    }
+    if let Some(timeout) = metadata.post_process_timeout {
+        lines.push(format!("post_process_timeout = {timeout}"));
+    }
+    if let Some(escape) = metadata.escape {
+        lines.push(format!("escape = {}", escape.as_str()));
+    }
+    if metadata.dedent {
+        lines.push("dedent = true".to_owned());
+    }
+    if metadata.autoescape {
+        lines.push("autoescape = true".to_owned());
+    }
+    for (find, replacement) in &metadata.replace {
+        lines.push(format!("replace {find:?} = {replacement:?}"));
+    }
+    for (preset, entries) in &metadata.presets {
+        lines.push(format!(
+            "preset {preset} = {}",
+            serde_json::Value::Object(entries.clone())
+        ));
+    }
+    if let Some(data) = &metadata.data {
+        lines.push(format!("data = {data}"));
+    }
+    if let Some(format) = metadata.data_format {
+        lines.push(format!("data_format = {}", format.as_str()));
+    }
+    if let PromptKind::Sequence { files } = &spec.kind {
+        for (&index, sha256) in &metadata.pins {
+            lines.push(format!("pin = {} sha256:{sha256}", files[index]));
+        }
+        for (&index, role) in &metadata.roles {
+            lines.push(format!("role = {} {}", files[index], role.as_str()));
+        }
+    }
+    for var in &metadata.vars {
+        lines.push(var_line(var));
+    }
+}
+
+/// One `var name: type` line, followed by whichever of its requirement, default, and position
+/// are set.
+fn var_line(var: &PromptVariable) -> String {
+    let mut line = format!("var {}: {}", var.name, var.kind.as_str());
+    if var.required {
+        line.push_str(" required");
+    }
+    if let Some(condition) = &var.required_if {
+        let _ = write!(line, " required_if={condition}");
+    }
+    if let Some(default) = &var.default {
+        let _ = write!(line, " default={default:?}");
+    }
+    if let Some(position) = var.position {
+        let _ = write!(line, " position={position}");
+    }
+    line
+}
+
+fn optional_path(root: &Utf8Path, path: Option<&Utf8Path>) -> String {
+    path.map_or_else(|| "-".to_owned(), |path| relative(root, path))
+}
+
+fn relative(root: &Utf8Path, path: &Utf8Path) -> String {
+    match path.strip_prefix(root) {
+        Ok(rel) if rel.as_str().is_empty() => ".".to_owned(),
+        Ok(rel) => normalize(rel),
+        Err(_) => normalize(path),
+    }
+}
+
+fn normalize(path: &Utf8Path) -> String {
+    path.as_str().replace('\\', "/")
+}
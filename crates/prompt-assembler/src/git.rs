@@ -0,0 +1,26 @@
+use std::process::Command;
+
+use serde_json::{Map, Value};
+
+/// Describe the git checkout containing the current directory as `branch`, `sha` (short), and
+/// `dirty`. Returns `None` outside a repository, before the first commit, or without `git`.
+pub(crate) fn git_context() -> Option<Value> {
+    let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"])?;
+    let sha = git(&["rev-parse", "--short", "HEAD"])?;
+    let dirty = !git(&["status", "--porcelain"])?.is_empty();
+
+    let mut map = Map::new();
+    map.insert("branch".into(), Value::String(branch));
+    map.insert("sha".into(), Value::String(sha));
+    map.insert("dirty".into(), Value::Bool(dirty));
+    Some(Value::Object(map))
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_owned())
+}
@@ -1,36 +1,170 @@
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::fs;
-use std::io::Read;
-use std::time::SystemTime;
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{Context, anyhow, bail};
 use camino::{Utf8Path, Utf8PathBuf};
 use directories::BaseDirs;
 use indexmap::IndexMap;
-use minijinja::Environment;
+use minijinja::{AutoEscape, Environment};
 use serde::Deserialize;
 use thiserror::Error;
 
+#[cfg(feature = "archive")]
+mod archive;
+mod dump;
+#[cfg(feature = "git")]
+mod git;
+mod orphans;
+#[cfg(feature = "remote")]
+mod remote;
+mod secret;
+mod source;
+
+#[cfg(feature = "archive")]
+pub use archive::ArchiveSource;
+#[cfg(feature = "remote")]
+pub use remote::RemoteSource;
+#[cfg(feature = "keyring")]
+pub use secret::KeyringStore;
+pub use secret::{SecretRef, SecretStore};
+pub use source::{FileSystemSource, FragmentSource, SourceEntry};
+use source::{FragmentReader, read_utf8};
+
 pub type Result<T> = std::result::Result<T, anyhow::Error>;
 
+const MAX_TRANSCLUSION_DEPTH: usize = 8;
+
+/// Deepest subdirectory of `conf.d` whose files are loaded.
+const MAX_CONF_D_DEPTH: usize = 8;
+
+/// Furthest edit distance at which an unknown prompt name still gets a suggestion.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Stands in for the value of a redacted context entry.
+const REDACTED: &str = "<redacted>";
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub root: Utf8PathBuf,
     pub default_prompt_path: Option<Utf8PathBuf>,
     pub prompts: IndexMap<String, PromptSpec>,
+    pub settings: Settings,
+}
+
+impl Config {
+    /// Create an empty configuration whose prompt files resolve relative to `root`.
+    #[must_use]
+    pub fn new(root: impl Into<Utf8PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            default_prompt_path: Some(root.clone()),
+            root,
+            prompts: IndexMap::new(),
+            settings: Settings::default(),
+        }
+    }
+}
+
+/// Library-wide options read from `[settings]` tables; later files override earlier ones.
+///
+/// A file's table applies to that whole file, whatever its position among the prompts.
+/// `require_absolute_paths` is checked as each file is read, so it never reaches back to
+/// prompts declared in earlier files.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    pub require_absolute_paths: bool,
+    /// Decode fragments that are not valid UTF-8 lossily instead of failing the render.
+    pub lossy_utf8: bool,
+    /// Seconds a cached remote fragment is used without revalidating it.
+    pub remote_cache_ttl: Option<u64>,
+    /// Expose the current git branch, short SHA, and dirty flag to templates as `_git`.
+    /// Only takes effect with the `git` feature.
+    pub git_context: bool,
+    /// Extensions (without the dot) kept when a sequence entry or part names a directory.
+    /// `None` keeps every file.
+    pub fragment_extensions: Option<Vec<String>>,
+    /// Glob patterns, relative to a prompt's `prompt_path`, naming macro templates imported
+    /// into every template prompt under their file stem.
+    pub macros: Vec<String>,
+    /// Prompt a front end runs when it is given arguments but no prompt name.
+    pub default_prompt: Option<String>,
+    /// JSON Lines file a front end appends a record of each successful render to.
+    pub audit_log: Option<Utf8PathBuf>,
+    /// JSON, TOML, or YAML file whose top-level entries every template render sees, overridden by
+    /// the data file and caller-supplied context.
+    pub context_file: Option<Utf8PathBuf>,
+    /// Fragment, resolved like a sequence fragment against the default `prompt_path`, placed
+    /// before every prompt's output.
+    pub global_prepend: Option<Utf8PathBuf>,
+    /// Fragment placed after every prompt's output, resolved like `global_prepend`.
+    pub global_append: Option<Utf8PathBuf>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            require_absolute_paths: false,
+            lossy_utf8: false,
+            remote_cache_ttl: None,
+            git_context: true,
+            fragment_extensions: None,
+            macros: Vec::new(),
+            default_prompt: None,
+            audit_log: None,
+            context_file: None,
+            global_prepend: None,
+            global_append: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct PromptSpec {
     pub prompt_path_override: Option<Utf8PathBuf>,
+    /// Directory a sequence prompt resolves its fragments against, taking precedence over
+    /// `prompt_path`.
+    pub working_dir: Option<Utf8PathBuf>,
     pub kind: PromptKind,
     pub metadata: PromptMetadata,
 }
 
+impl PromptSpec {
+    /// Create a spec with no metadata that resolves files from the configuration default.
+    #[must_use]
+    pub fn new(kind: PromptKind) -> Self {
+        Self {
+            prompt_path_override: None,
+            working_dir: None,
+            kind,
+            metadata: PromptMetadata::default(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PromptKind {
-    Sequence { files: Vec<Utf8PathBuf> },
-    Template { template: Utf8PathBuf },
+    Sequence {
+        files: Vec<Utf8PathBuf>,
+    },
+    Template {
+        template: Utf8PathBuf,
+    },
+    /// A body written directly in the configuration, rendered like a single sequence fragment
+    /// but without reading any files (so transclusion markers are left as-is).
+    Inline {
+        body: String,
+    },
+    /// A minijinja template written directly in the configuration. Renders like
+    /// [`PromptKind::Template`], with `include` and friends resolved against `prompt_path`.
+    InlineTemplate {
+        body: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -48,6 +182,12 @@ pub enum PromptProfile {
     Template {
         template: PromptPart,
     },
+    Inline {
+        body: String,
+    },
+    InlineTemplate {
+        body: String,
+    },
 }
 
 impl PromptProfile {
@@ -56,31 +196,81 @@ impl PromptProfile {
         match self {
             PromptProfile::Sequence { combined, .. } => combined,
             PromptProfile::Template { template } => &template.content,
+            PromptProfile::Inline { body } | PromptProfile::InlineTemplate { body } => body,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PromptMetadata {
     pub description: Option<String>,
     pub tags: Vec<String>,
     pub vars: Vec<PromptVariable>,
     pub stdin_supported: Option<bool>,
+    /// Command the rendered output is piped through. `pa` splits it into words with POSIX shell
+    /// quoting rules and runs it without a shell. The library only records it; running it is up
+    /// to the caller.
+    pub post_process: Option<String>,
+    /// Seconds the `post_process` command may run before it is killed. `None` leaves the bound
+    /// to the caller.
+    pub post_process_timeout: Option<u64>,
+    /// Escaping applied to the output when the caller does not choose one.
+    pub escape: Option<OutputEscape>,
+    /// Fragment a sequence prompt renders once per trailing argument (each one beyond the
+    /// highest `{N}` its fragments use), with `{item}` bound to that argument.
+    pub repeat_for_args: Option<Utf8PathBuf>,
+    /// Strip whitespace common to the start of every output line after rendering.
+    pub dedent: bool,
+    /// HTML-escape values a template prompt prints. Off by default, whatever the template's
+    /// extension, since prompts are usually plain text.
+    pub autoescape: bool,
+    /// Literal find/replace pairs applied to the output, in declaration order, once it is
+    /// rendered.
+    pub replace: IndexMap<String, String>,
+    /// Named sets of template context entries, one of which a caller may select with
+    /// [`RenderOptions::preset`].
+    pub presets: IndexMap<String, serde_json::Map<String, serde_json::Value>>,
+    /// JSON, TOML, or YAML file, relative to `prompt_path`, a template prompt renders with when the
+    /// caller supplies no data.
+    pub data: Option<Utf8PathBuf>,
+    /// Format a template prompt's data is read in, whatever the file extension. `None` picks
+    /// it from the extension.
+    pub data_format: Option<DataFormat>,
+    /// Expected lowercase hex SHA-256 of sequence entries, keyed by the entry's index in
+    /// `prompts`.
+    pub pins: BTreeMap<usize, String>,
+    /// Chat role of sequence entries, keyed by the entry's index in `prompts`. A fragment
+    /// without one continues the message before it.
+    pub roles: BTreeMap<usize, MessageRole>,
     pub source: PromptSource,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct PromptSource {
     pub path: Utf8PathBuf,
     pub last_modified: Option<SystemTime>,
 }
 
+/// A declared var the context does not supply, from [`PromptAssembler::missing_inputs`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MissingInput<'a> {
+    pub var: &'a PromptVariable,
+    /// Whether the render fails without it.
+    pub required: bool,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PromptVariable {
     pub name: String,
     pub required: bool,
     pub kind: PromptVariableKind,
     pub description: Option<String>,
+    /// Another var of the same prompt; when it is truthy this var becomes required.
+    pub required_if: Option<String>,
+    /// Value used when none is supplied, for `{name}` placeholders and template context alike.
+    pub default: Option<String>,
+    /// Positional argument `{N}` this var describes; its value is checked against `kind`.
+    pub position: Option<usize>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -109,6 +299,13 @@ pub enum ConfigIssueCode {
     Override,
     InvalidPrompt,
     ParseError,
+    ReadError,
+    UndeclaredVar,
+    PlaceholderGap,
+    HashMismatch,
+    IgnoredKey,
+    RequiredWithDefault,
+    InvalidSetting,
 }
 
 impl ConfigIssueCode {
@@ -119,6 +316,13 @@ impl ConfigIssueCode {
             ConfigIssueCode::Override => "override",
             ConfigIssueCode::InvalidPrompt => "invalid_prompt",
             ConfigIssueCode::ParseError => "parse_error",
+            ConfigIssueCode::ReadError => "read_error",
+            ConfigIssueCode::UndeclaredVar => "undeclared_var",
+            ConfigIssueCode::PlaceholderGap => "placeholder_gap",
+            ConfigIssueCode::HashMismatch => "hash_mismatch",
+            ConfigIssueCode::IgnoredKey => "ignored_key",
+            ConfigIssueCode::RequiredWithDefault => "required_with_default",
+            ConfigIssueCode::InvalidSetting => "invalid_setting",
         }
     }
 }
@@ -129,6 +333,8 @@ pub struct ConfigIssue {
     pub message: String,
     pub path: Utf8PathBuf,
     pub line: Option<u32>,
+    /// The prompt this issue concerns, when it is specific to one prompt.
+    pub prompt: Option<String>,
 }
 
 impl ConfigIssue {
@@ -143,14 +349,22 @@ impl ConfigIssue {
             path,
             line,
             message: message.into(),
+            prompt: None,
         }
     }
+
+    fn for_prompt(mut self, name: &str) -> Self {
+        self.prompt = Some(name.to_owned());
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ConfigDiagnostics {
     pub errors: Vec<ConfigIssue>,
     pub warnings: Vec<ConfigIssue>,
+    /// Tags declared by every prompt that was read, including ones that failed to load.
+    pub prompt_tags: BTreeMap<String, Vec<String>>,
 }
 
 #[derive(Debug, Error)]
@@ -171,22 +385,338 @@ pub enum LoadConfigError {
     },
 }
 
+/// A template that failed to compile or render, located at the template that raised it
+/// (which may be an included one rather than the prompt's own template).
+#[derive(Debug, Error)]
+#[error("template {template}{}: {message}", location(*line, *column))]
+pub struct RenderError {
+    pub template: String,
+    /// 1-based line of the failing expression, when minijinja reports one.
+    pub line: Option<usize>,
+    /// 1-based column of the failing expression, when its span is known.
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl RenderError {
+    fn from_minijinja(env: &Environment, fallback: &str, err: &minijinja::Error) -> Self {
+        let template = err.name().unwrap_or(fallback).to_owned();
+        let column = err.range().and_then(|range| {
+            let template = env.get_template(&template).ok()?;
+            let before = template.source().get(..range.start)?;
+            let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+            Some(before[line_start..].chars().count() + 1)
+        });
+        let message = match err.detail() {
+            Some(detail) => format!("{}: {detail}", err.kind()),
+            None => err.kind().to_string(),
+        };
+        Self {
+            template,
+            line: err.line(),
+            column,
+            message,
+        }
+    }
+}
+
+fn location(line: Option<usize>, column: Option<usize>) -> String {
+    match (line, column) {
+        (Some(line), Some(column)) => format!(":{line}:{column}"),
+        (Some(line), None) => format!(":{line}"),
+        _ => String::new(),
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PromptAssembler {
     config: Config,
     warnings: Vec<ConfigIssue>,
+    skipped: Vec<SkippedPrompt>,
+    source: Arc<dyn FragmentSource>,
+}
+
+/// Inputs to configuration loading that would otherwise come from the running process.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Operating system name compared against `when_os`, as in [`std::env::consts::OS`].
+    pub os: String,
+    /// Serve remote fragments only from the cache, never from the network.
+    pub offline: bool,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            os: std::env::consts::OS.to_owned(),
+            offline: false,
+        }
+    }
+}
+
+/// A prompt whose `when_os`/`when_env` predicate did not match and was left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedPrompt {
+    pub name: String,
+    pub reason: String,
+    pub source: Utf8PathBuf,
+}
+
+/// Per-render switches that adjust how a prompt is assembled.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// How fragments are joined and the finished text is shaped.
+    pub layout: LayoutOptions,
+    /// Replace invalid UTF-8 in fragments instead of failing; also enabled by `lossy_utf8`.
+    pub lossy: bool,
+    /// What to do when a sequence fragment does not exist.
+    pub on_missing: MissingFragmentPolicy,
+    /// Values for `{name}` placeholders in sequence fragments.
+    pub vars: BTreeMap<String, String>,
+    /// Top-level template context entries layered over the data file. A template prompt may
+    /// render without a data file when this is non-empty.
+    pub context: serde_json::Map<String, serde_json::Value>,
+    /// Report missing placeholder values and required vars as warnings and render anyway,
+    /// leaving the affected placeholders empty.
+    pub force: bool,
+    /// Record how long each fragment took in [`RenderOutput::timings`].
+    pub trace: bool,
+    /// Context entries, such as secrets, whose values are never shown by
+    /// [`PromptAssembler::dump_context`].
+    pub redact: BTreeSet<String>,
+    /// One of the prompt's `presets`, layered under the data file and `context`. A template
+    /// prompt may render without a data file when one is selected.
+    pub preset: Option<String>,
+}
+
+/// The layout switches of [`RenderOptions`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LayoutOptions {
+    /// Concatenate sequence fragments byte-for-byte instead of ensuring each ends with a newline.
+    pub exact: bool,
+    /// Strip whitespace common to the start of every output line, even when the prompt does
+    /// not set `dedent`.
+    pub dedent: bool,
+    /// Leave out the `global_prepend` and `global_append` fragments.
+    pub no_global: bool,
+}
+
+/// How a sequence render treats fragments that do not exist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingFragmentPolicy {
+    /// Fail the render.
+    #[default]
+    Error,
+    /// Leave the fragment out and report a warning.
+    Skip,
+    /// Render the fragment as if it were an empty file.
+    Empty,
+}
+
+/// How the final output is escaped for embedding into another format.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputEscape {
+    /// Leave the output as rendered.
+    #[default]
+    None,
+    /// Encode the output as a JSON string literal.
+    Json,
+    /// Quote the output as a single POSIX shell word.
+    Shell,
+}
+
+impl OutputEscape {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputEscape::None => "none",
+            OutputEscape::Json => "json",
+            OutputEscape::Shell => "shell",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "none" => Some(OutputEscape::None),
+            "json" => Some(OutputEscape::Json),
+            "shell" => Some(OutputEscape::Shell),
+            _ => None,
+        }
+    }
+
+    /// Escape `output` according to this mode.
+    #[must_use]
+    pub fn apply(&self, output: &str) -> String {
+        match self {
+            OutputEscape::None => output.to_owned(),
+            OutputEscape::Json => serde_json::Value::String(output.to_owned()).to_string(),
+            OutputEscape::Shell => format!("'{}'", output.replace('\'', "'\\''")),
+        }
+    }
+}
+
+/// A rendered prompt together with non-fatal issues noticed while assembling it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenderOutput {
+    pub output: String,
+    pub warnings: Vec<String>,
+    /// Per-fragment durations, in render order; empty unless [`RenderOptions::trace`] is set.
+    pub timings: Vec<FragmentTiming>,
+    /// The output split into role-tagged chat messages at sequence entries that declare a
+    /// `role`. Output before the first such entry, and every other kind of prompt, is a single
+    /// `user` message.
+    pub messages: Vec<Message>,
+}
+
+/// One role-tagged message of a rendered prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+/// Who a chat message is attributed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+}
+
+impl MessageRole {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageRole::System => "system",
+            MessageRole::User => "user",
+            MessageRole::Assistant => "assistant",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "system" => Some(MessageRole::System),
+            "user" => Some(MessageRole::User),
+            "assistant" => Some(MessageRole::Assistant),
+            _ => None,
+        }
+    }
+}
+
+/// A template and the templates it loads, from [`PromptAssembler::template_dependencies`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateDependency {
+    /// The name as the loading tag wrote it, the template path for the root, or `<inline>`
+    /// for an inline template body.
+    pub name: String,
+    /// The file the name resolves to; `None` for an inline template body.
+    pub path: Option<Utf8PathBuf>,
+    /// The file does not exist, so rendering would fail to load it.
+    pub missing: bool,
+    /// The file appears earlier in the tree, which lists its dependencies there only.
+    pub repeated: bool,
+    pub dependencies: Vec<TemplateDependency>,
+}
+
+/// Time spent on one fragment of a traced render. Template prompts report a single entry whose
+/// `render` covers loading included templates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentTiming {
+    pub fragment: String,
+    /// Reading the fragment and expanding its transclusions.
+    pub read: Duration,
+    /// Substituting placeholders, or rendering the template.
+    pub render: Duration,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum StructuredData {
     Json(Utf8PathBuf),
     Toml(Utf8PathBuf),
+    Yaml(Utf8PathBuf),
+}
+
+/// A format template data can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl DataFormat {
+    #[must_use]
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DataFormat::Json => "json",
+            DataFormat::Toml => "toml",
+            DataFormat::Yaml => "yaml",
+        }
+    }
+
+    fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "json" => Some(DataFormat::Json),
+            "toml" => Some(DataFormat::Toml),
+            "yaml" => Some(DataFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parse `content` in this format into a JSON value; `origin` names the input in errors.
+    ///
+    /// # Errors
+    /// Returns an error when `content` is not valid in this format.
+    pub fn parse_content(self, content: &str, origin: &str) -> Result<serde_json::Value> {
+        match self {
+            DataFormat::Json => Ok(serde_json::from_str(content)
+                .with_context(|| format!("failed to parse JSON data from {origin}"))?),
+            DataFormat::Toml => {
+                let toml_value: toml::Value = toml::from_str(content)
+                    .with_context(|| format!("failed to parse TOML data from {origin}"))?;
+                serde_json::to_value(toml_value)
+                    .map(sort_keys)
+                    .map_err(|err| anyhow!("failed to convert TOML to JSON: {err}"))
+            }
+            DataFormat::Yaml => {
+                let yaml_value: serde_yaml::Value = serde_yaml::from_str(content)
+                    .with_context(|| format!("failed to parse YAML data from {origin}"))?;
+                serde_json::to_value(yaml_value)
+                    .map(sort_keys)
+                    .map_err(|err| anyhow!("failed to convert YAML to JSON: {err}"))
+            }
+        }
+    }
 }
 
 impl StructuredData {
-    fn path(&self) -> &Utf8Path {
+    /// Data at `path` read as `format`, whatever its extension.
+    #[must_use]
+    pub fn with_format(path: Utf8PathBuf, format: DataFormat) -> Self {
+        match format {
+            DataFormat::Json => StructuredData::Json(path),
+            DataFormat::Toml => StructuredData::Toml(path),
+            DataFormat::Yaml => StructuredData::Yaml(path),
+        }
+    }
+
+    /// Pick the format from the file extension, or `None` when it is not JSON, TOML, or YAML.
+    fn from_path(path: Utf8PathBuf) -> Option<Self> {
+        match path.extension().map(str::to_ascii_lowercase).as_deref() {
+            Some("json") => Some(StructuredData::Json(path)),
+            Some("toml") => Some(StructuredData::Toml(path)),
+            Some("yaml" | "yml") => Some(StructuredData::Yaml(path)),
+            _ => None,
+        }
+    }
+
+    /// The file the data is read from.
+    #[must_use]
+    pub fn path(&self) -> &Utf8Path {
         match self {
-            StructuredData::Json(path) | StructuredData::Toml(path) => path.as_ref(),
+            StructuredData::Json(path)
+            | StructuredData::Toml(path)
+            | StructuredData::Yaml(path) => path.as_ref(),
         }
     }
 }
@@ -200,14 +730,154 @@ impl PromptAssembler {
         Self::load_with_diagnostics(dir).map_err(anyhow::Error::from)
     }
 
+    /// Construct an assembler from a configuration built in code, bypassing TOML loading.
+    #[must_use]
+    pub fn from_config(config: Config) -> Self {
+        Self::from_config_with_source(config, FileSystemSource)
+    }
+
+    /// Construct an assembler from `config` that reads fragments through `source`.
+    #[must_use]
+    pub fn from_config_with_source(config: Config, source: impl FragmentSource + 'static) -> Self {
+        Self {
+            config,
+            warnings: Vec::new(),
+            skipped: Vec::new(),
+            source: Arc::new(source),
+        }
+    }
+
     /// Construct an assembler while retaining structured diagnostics.
     ///
     /// # Errors
     /// Returns a [`LoadConfigError`] when configuration files cannot be read or contain
     /// invalid definitions.
     pub fn load_with_diagnostics(dir: &Utf8Path) -> std::result::Result<Self, LoadConfigError> {
-        let ConfigLoad { config, warnings } = load_config(dir)?;
-        Ok(Self { config, warnings })
+        Self::load_with_options(dir, &LoadOptions::default())
+    }
+
+    /// Construct an assembler, evaluating conditional prompts against `options`.
+    ///
+    /// # Errors
+    /// Returns a [`LoadConfigError`] under the same conditions as
+    /// [`PromptAssembler::load_with_diagnostics`].
+    pub fn load_with_options(
+        dir: &Utf8Path,
+        options: &LoadOptions,
+    ) -> std::result::Result<Self, LoadConfigError> {
+        let ConfigLoad {
+            config,
+            warnings,
+            skipped,
+            ..
+        } = load_config(dir, options)?;
+        let source = default_source(dir, &config.settings, options);
+        Ok(Self {
+            config,
+            warnings,
+            skipped,
+            source,
+        })
+    }
+
+    /// Construct an assembler from every prompt in `dir` that loads, alongside the errors and
+    /// warnings of the whole configuration. Prompts that fail to load are left out of the
+    /// assembler and reported in the returned [`ConfigDiagnostics`].
+    ///
+    /// # Errors
+    /// Returns a [`LoadConfigError`] only when configuration files or directories cannot be
+    /// read.
+    pub fn load_valid_prompts(
+        dir: &Utf8Path,
+        options: &LoadOptions,
+    ) -> std::result::Result<(Self, ConfigDiagnostics), LoadConfigError> {
+        let ConfigLoad {
+            config,
+            errors,
+            warnings,
+            skipped,
+            prompt_tags,
+        } = collect_config(dir, options)?;
+        let diagnostics = ConfigDiagnostics {
+            errors,
+            warnings: warnings.clone(),
+            prompt_tags,
+        };
+        let source = default_source(dir, &config.settings, options);
+        let assembler = Self {
+            config,
+            warnings,
+            skipped,
+            source,
+        };
+        Ok((assembler, diagnostics))
+    }
+
+    /// Re-read the configuration in `dir`, replacing this assembler's prompts, settings, and
+    /// warnings. Fragments are read through the default source for the new settings, so a
+    /// source given to [`PromptAssembler::from_config_with_source`] is dropped; rebuild the
+    /// assembler with that constructor instead to keep it.
+    ///
+    /// # Errors
+    /// Returns a [`LoadConfigError`] under the same conditions as
+    /// [`PromptAssembler::load_with_options`], leaving the current configuration in place.
+    pub fn reload(
+        &mut self,
+        dir: &Utf8Path,
+        options: &LoadOptions,
+    ) -> std::result::Result<(), LoadConfigError> {
+        *self = Self::load_with_options(dir, options)?;
+        Ok(())
+    }
+
+    /// Construct an assembler and report every error and warning found while loading, whether
+    /// or not the configuration is valid.
+    ///
+    /// The assembler is `None` when any error was found. Files or directories that cannot be
+    /// read are reported as [`ConfigIssueCode::ReadError`] issues.
+    #[must_use]
+    pub fn load_reporting_diagnostics(
+        dir: &Utf8Path,
+        options: &LoadOptions,
+    ) -> (Option<Self>, ConfigDiagnostics) {
+        match load_config(dir, options) {
+            Ok(ConfigLoad {
+                config,
+                warnings,
+                skipped,
+                prompt_tags,
+                ..
+            }) => {
+                let diagnostics = ConfigDiagnostics {
+                    errors: Vec::new(),
+                    warnings: warnings.clone(),
+                    prompt_tags,
+                };
+                let source = default_source(dir, &config.settings, options);
+                let assembler = Self {
+                    config,
+                    warnings,
+                    skipped,
+                    source,
+                };
+                (Some(assembler), diagnostics)
+            }
+            Err(err) => {
+                let path = match err {
+                    LoadConfigError::Invalid { diagnostics } => return (None, diagnostics),
+                    LoadConfigError::ReadDir { ref path, .. }
+                    | LoadConfigError::Io { ref path, .. } => path.clone(),
+                };
+                let issue =
+                    ConfigIssue::new(ConfigIssueCode::ReadError, path, None, err.to_string());
+                let diagnostics = ConfigDiagnostics {
+                    errors: vec![issue],
+                    warnings: Vec::new(),
+                    prompt_tags: BTreeMap::new(),
+                };
+                (None, diagnostics)
+            }
+        }
     }
 
     /// Assemble the prompt identified by `name` using provided arguments and optional data.
@@ -221,65 +891,589 @@ impl PromptAssembler {
         args: &[String],
         data: Option<StructuredData>,
     ) -> Result<String> {
+        let mut buffer = Vec::new();
+        self.render_prompt_to_writer(name, args, data, &mut buffer)?;
+        String::from_utf8(buffer).context("rendered output is not valid UTF-8")
+    }
+
+    /// Assemble a prompt like [`PromptAssembler::render_prompt`], writing it to `out` instead
+    /// of returning it.
+    ///
+    /// Sequence fragments are written as each one is substituted, so a large prompt is never
+    /// held in memory whole. Prompts whose output needs the full text first, such as those
+    /// with `replace` or `dedent`, and template prompts are rendered before being written.
+    /// A failure part way through a sequence leaves what was already written in `out`.
+    ///
+    /// # Errors
+    /// Returns an error under the same conditions as [`PromptAssembler::render_prompt`], or
+    /// when writing to `out` fails.
+    pub fn render_prompt_to_writer(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        out: &mut dyn Write,
+    ) -> Result<()> {
+        let options = RenderOptions::default();
         let spec = self
             .config
             .prompts
             .get(name)
-            .ok_or_else(|| anyhow!("unknown prompt: {name}"))?;
-
-        match &spec.kind {
-            PromptKind::Sequence { files } => {
-                if data.is_some() {
-                    bail!("prompt '{name}' does not accept structured data");
-                }
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        if !spec.metadata.replace.is_empty() || spec.metadata.dedent {
+            let rendered = self.render_prompt_output(name, args, data, &options)?;
+            return out
+                .write_all(rendered.output.as_bytes())
+                .context("failed to write rendered output");
+        }
 
-                let base = self
-                    .resolve_prompt_path(spec)
-                    .context("sequence prompt missing prompt_path")?;
+        let reader = FragmentReader::new(Arc::clone(&self.source), self.config.settings.lossy_utf8);
+        let (prepend, append) = self.global_fragments(&reader, &options)?;
+        out.write_all(prepend.as_bytes())
+            .context("failed to write rendered output")?;
+        // Message roles are not needed when streaming, so their offsets are dropped.
+        self.render_with_reader(&reader, name, args, data, &options, &mut Emitter::new(out))?;
+        out.write_all(append.as_bytes())
+            .context("failed to write rendered output")
+    }
 
-                let mut rendered = String::new();
-                for file in files {
-                    let full_path = base.join(file);
-                    let content = read_utf8(&full_path).with_context(|| {
-                        format!("failed to read fragment '{file}' for prompt '{name}'")
-                    })?;
-                    let substituted = substitute_placeholders(&content, args)?;
-                    rendered.push_str(&substituted);
-                    if !rendered.ends_with('\n') {
-                        rendered.push('\n');
-                    }
-                }
-                Ok(rendered)
-            }
-            PromptKind::Template { template } => {
-                let data = data.ok_or_else(|| {
-                    anyhow!("prompt '{name}' requires a data file for structured context")
-                })?;
+    /// Lazily render every prompt in declaration order. `provider` is called with each prompt
+    /// name just before it renders and supplies that prompt's arguments and optional data.
+    /// A prompt that fails to render yields its error and iteration continues.
+    pub fn render_all<'a, F>(
+        &'a self,
+        mut provider: F,
+    ) -> impl Iterator<Item = (&'a str, Result<String>)> + 'a
+    where
+        F: FnMut(&str) -> (Vec<String>, Option<StructuredData>) + 'a,
+    {
+        self.prompt_names().map(move |name| {
+            let (args, data) = provider(name);
+            (name, self.render_prompt(name, &args, data))
+        })
+    }
 
-                let base = self
-                    .resolve_prompt_path(spec)
-                    .context("template prompt missing prompt_path")?;
+    /// Assemble a prompt like [`PromptAssembler::render_prompt`], honoring `options`.
+    ///
+    /// # Errors
+    /// Returns an error under the same conditions as [`PromptAssembler::render_prompt`].
+    pub fn render_prompt_with_options(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+    ) -> Result<String> {
+        self.render_prompt_output(name, args, data, options)
+            .map(|rendered| rendered.output)
+    }
 
-                render_template(name, &base, template, &data, args)
+    /// Assemble a prompt like [`PromptAssembler::render_prompt_with_options`], also returning
+    /// warnings such as fragments that had to be decoded lossily.
+    ///
+    /// # Errors
+    /// Returns an error under the same conditions as [`PromptAssembler::render_prompt`].
+    pub fn render_prompt_output(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+    ) -> Result<RenderOutput> {
+        let reader = FragmentReader::new(
+            Arc::clone(&self.source),
+            options.lossy || self.config.settings.lossy_utf8,
+        );
+        let mut buffer = Vec::new();
+        let role_marks = self.render_with_reader(
+            &reader,
+            name,
+            args,
+            data,
+            options,
+            &mut Emitter::new(&mut buffer),
+        )?;
+        let output = String::from_utf8(buffer).context("rendered output is not valid UTF-8")?;
+        let spec = self.prompt_spec(name);
+        let finish = |mut text: String| {
+            for (find, replacement) in spec.iter().flat_map(|spec| &spec.metadata.replace) {
+                text = text.replace(find.as_str(), replacement);
             }
+            if options.layout.dedent || spec.is_some_and(|spec| spec.metadata.dedent) {
+                text = dedent(&text);
+            }
+            text
+        };
+        let mut messages: Vec<Message> = split_messages(&output, &role_marks)
+            .into_iter()
+            .map(|(role, content)| Message {
+                role,
+                content: finish(content.to_owned()),
+            })
+            .collect();
+        let (prepend, append) = self.global_fragments(&reader, options)?;
+        if messages.is_empty() && !(prepend.is_empty() && append.is_empty()) {
+            messages.push(Message {
+                role: MessageRole::User,
+                content: String::new(),
+            });
+        }
+        if let Some(first) = messages.first_mut() {
+            first.content.insert_str(0, &prepend);
         }
+        if let Some(last) = messages.last_mut() {
+            last.content.push_str(&append);
+        }
+        Ok(RenderOutput {
+            output: format!("{prepend}{}{append}", finish(output)),
+            warnings: reader.take_warnings(),
+            timings: reader.take_timings(),
+            messages,
+        })
     }
 
-    #[must_use]
-    pub fn available_prompts(&self) -> BTreeMap<String, PromptKind> {
-        self.config
-            .prompts
-            .iter()
-            .map(|(name, spec)| (name.clone(), spec.kind.clone()))
-            .collect()
+    /// The `global_prepend` and `global_append` fragments, each empty when unset or when
+    /// `options.layout.no_global` is set.
+    fn global_fragments(
+        &self,
+        reader: &FragmentReader,
+        options: &RenderOptions,
+    ) -> Result<(String, String)> {
+        if options.layout.no_global {
+            return Ok((String::new(), String::new()));
+        }
+        let base = self
+            .config
+            .default_prompt_path
+            .clone()
+            .unwrap_or_else(|| self.config.root.clone());
+        let read = |key: &str, file: Option<&Utf8PathBuf>| -> Result<String> {
+            let Some(file) = file else {
+                return Ok(String::new());
+            };
+            let mut content = reader
+                .read(&resolve_fragment(&base, file))
+                .with_context(|| format!("failed to read {key} fragment '{file}'"))?;
+            if !options.layout.exact && !content.is_empty() && !content.ends_with('\n') {
+                content.push('\n');
+            }
+            Ok(content)
+        };
+        let settings = &self.config.settings;
+        Ok((
+            read("global_prepend", settings.global_prepend.as_ref())?,
+            read("global_append", settings.global_append.as_ref())?,
+        ))
     }
 
-    #[must_use]
-    pub fn prompt_specs(&self) -> &IndexMap<String, PromptSpec> {
-        &self.config.prompts
+    /// The top-level context the template prompt `name` would render with, built from the
+    /// same data file, arguments, and `options.context` as a render. Entries named in
+    /// `options.redact` are replaced with `"<redacted>"`.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or not a template prompt, or its data file
+    /// cannot be read or parsed.
+    pub fn dump_context(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let mut context = self.template_render_context(name, args, data, options)?;
+        for key in &options.redact {
+            if let Some(value) = context.get_mut(key) {
+                *value = serde_json::Value::String(REDACTED.to_owned());
+            }
+        }
+        Ok(context)
     }
 
-    #[must_use]
+    /// The vars prompt `name` declares, in declaration order.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown.
+    pub fn input_requirements(&self, name: &str) -> Result<&[PromptVariable]> {
+        self.prompt_spec(name)
+            .map(|spec| spec.metadata.vars.as_slice())
+            .ok_or_else(|| self.unknown_prompt(name))
+    }
+
+    /// The declared vars of template prompt `name` that the context built from `data`,
+    /// `args`, and `options` does not supply, in declaration order. Each is marked required
+    /// when it is `required`, or its `required_if` var is truthy in that context.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or not a template prompt, or its data file
+    /// cannot be read or parsed.
+    pub fn missing_inputs(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+    ) -> Result<Vec<MissingInput<'_>>> {
+        let context = self.template_render_context(name, args, data, options)?;
+        Ok(self
+            .input_requirements(name)?
+            .iter()
+            .filter(|var| !context.contains_key(&var.name))
+            .map(|var| MissingInput {
+                var,
+                required: var.required
+                    || var.required_if.as_ref().is_some_and(|condition| {
+                        context.get(condition).is_some_and(is_truthy_json)
+                    }),
+            })
+            .collect())
+    }
+
+    /// The context a template prompt renders with, before any redaction.
+    fn template_render_context(
+        &self,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let spec = self
+            .prompt_spec(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        let base = match &spec.kind {
+            PromptKind::Template { .. } => self
+                .resolve_prompt_path(spec)
+                .context("template prompt missing prompt_path")?,
+            PromptKind::InlineTemplate { .. } => self
+                .resolve_prompt_path(spec)
+                .unwrap_or_else(|| self.config.root.clone()),
+            PromptKind::Sequence { .. } | PromptKind::Inline { .. } => {
+                bail!("prompt '{name}' is not a template prompt and has no context")
+            }
+        };
+        let data = data.or_else(|| default_data(spec, &base));
+        self.render_context(name, data.as_ref(), args, options)
+    }
+
+    /// The context a template render sees: the shared `context_file`, then the selected
+    /// preset, then the data file and arguments, then `options.context`, each layer overriding
+    /// the one before.
+    fn render_context(
+        &self,
+        name: &str,
+        data: Option<&StructuredData>,
+        args: &[String],
+        options: &RenderOptions,
+    ) -> Result<serde_json::Map<String, serde_json::Value>> {
+        let mut context = match &self.config.settings.context_file {
+            Some(path) => shared_context(path)?,
+            None => serde_json::Map::new(),
+        };
+        if let Some(preset) = &options.preset {
+            context.extend(self.preset(name, preset)?.clone());
+        }
+        context.extend(template_context(name, data, args)?);
+        context.extend(options.context.clone());
+        let vars = self
+            .prompt_spec(name)
+            .map(|spec| spec.metadata.vars.as_slice())
+            .unwrap_or_default();
+        for var in vars {
+            if let Some(default) = &var.default
+                && !context.contains_key(&var.name)
+            {
+                context.insert(var.name.clone(), serde_json::Value::String(default.clone()));
+            }
+        }
+        Ok(context)
+    }
+
+    /// The entries of `preset` on prompt `name`, or an error listing the presets it has.
+    fn preset(
+        &self,
+        name: &str,
+        preset: &str,
+    ) -> Result<&serde_json::Map<String, serde_json::Value>> {
+        let presets = &self
+            .prompt_spec(name)
+            .ok_or_else(|| self.unknown_prompt(name))?
+            .metadata
+            .presets;
+        presets.get(preset).ok_or_else(|| {
+            if presets.is_empty() {
+                anyhow!("unknown preset '{preset}': prompt '{name}' defines no presets")
+            } else {
+                let available: Vec<&str> = presets.keys().map(String::as_str).collect();
+                anyhow!(
+                    "unknown preset '{preset}' for prompt '{name}'; available: {}",
+                    available.join(", ")
+                )
+            }
+        })
+    }
+
+    fn render_with_reader(
+        &self,
+        reader: &FragmentReader,
+        name: &str,
+        args: &[String],
+        data: Option<StructuredData>,
+        options: &RenderOptions,
+        out: &mut Emitter<'_>,
+    ) -> Result<Vec<(usize, MessageRole)>> {
+        let spec = self
+            .config
+            .prompts
+            .get(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+
+        let report = |message: String| -> Result<()> {
+            if options.force {
+                reader.warn(message);
+                Ok(())
+            } else {
+                Err(anyhow!(message))
+            }
+        };
+        let job = RenderJob {
+            reader,
+            name,
+            spec,
+            args,
+            options,
+            report: &report,
+        };
+
+        if matches!(
+            spec.kind,
+            PromptKind::Sequence { .. } | PromptKind::Inline { .. }
+        ) {
+            // Unreadable fragments fail the render below; until then only explicit positions
+            // are checked.
+            let usage = self.placeholder_usage(name, spec).ok().flatten();
+            let limit = usage
+                .as_ref()
+                .filter(|_| spec.metadata.repeat_for_args.is_none())
+                .map(|usage| usage.arity);
+            let bound = bind_positions(&spec.metadata.vars, usage.as_ref());
+            check_var_kinds(name, &bound, args, limit, &report)?;
+        }
+
+        match &spec.kind {
+            PromptKind::Sequence { files } => {
+                if data.is_some() {
+                    bail!("prompt '{name}' does not accept structured data");
+                }
+                self.render_sequence(&job, files, out)
+            }
+            PromptKind::Inline { body } => {
+                if data.is_some() {
+                    bail!("prompt '{name}' does not accept structured data");
+                }
+                render_inline(&job, body, out)?;
+                Ok(Vec::new())
+            }
+            PromptKind::Template { template } => {
+                let base = self
+                    .resolve_prompt_path(spec)
+                    .context("template prompt missing prompt_path")?;
+                let source = TemplateSource::File(template);
+                self.render_template_prompt(&job, &base, &source, data, out)?;
+                Ok(Vec::new())
+            }
+            PromptKind::InlineTemplate { body } => {
+                let base = self
+                    .resolve_prompt_path(spec)
+                    .unwrap_or_else(|| self.config.root.clone());
+                let source = TemplateSource::Inline(body);
+                self.render_template_prompt(&job, &base, &source, data, out)?;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    /// Render each fragment of a sequence prompt in order, then its `repeat_for_args` body.
+    /// Returns the output offsets where entries with a `role` begin.
+    fn render_sequence(
+        &self,
+        job: &RenderJob<'_>,
+        files: &[Utf8PathBuf],
+        out: &mut Emitter<'_>,
+    ) -> Result<Vec<(usize, MessageRole)>> {
+        let RenderJob {
+            reader,
+            name,
+            spec,
+            args,
+            options,
+            report,
+        } = *job;
+        let base = self
+            .resolve_prompt_path(spec)
+            .context("sequence prompt missing prompt_path")?;
+
+        let mut role_marks = Vec::new();
+        let mut arity = 0;
+        for (index, file, full_path) in self.sequence_fragments(&base, files)? {
+            let started = Instant::now();
+            let pin = spec.metadata.pins.get(&index);
+            if pin.is_some() && files[index].as_str() != file {
+                bail!(
+                    "prompt '{name}' pins directory entry '{}'; sha256 applies to a single file",
+                    files[index]
+                );
+            }
+            let content = if options.on_missing != MissingFragmentPolicy::Error
+                && !reader.exists(&full_path)
+            {
+                if pin.is_some() {
+                    bail!("pinned fragment '{file}' for prompt '{name}' is missing");
+                }
+                if options.on_missing == MissingFragmentPolicy::Skip {
+                    reader.warn(format!(
+                        "skipped missing fragment '{file}' for prompt '{name}'"
+                    ));
+                    continue;
+                }
+                String::new()
+            } else if let Some(expected) = pin {
+                let bytes = reader.read_bytes(&full_path).with_context(|| {
+                    format!("failed to read fragment '{file}' for prompt '{name}'")
+                })?;
+                verify_pin(name, &file, expected, &bytes)?;
+                reader.decode(&full_path, bytes)?
+            } else {
+                reader.read(&full_path).with_context(|| {
+                    format!("failed to read fragment '{file}' for prompt '{name}'")
+                })?
+            };
+            let content = expand_transclusions(reader, &content, &base, 0).with_context(|| {
+                format!("failed to expand fragment '{file}' for prompt '{name}'")
+            })?;
+            arity = arity.max(placeholder_arity(&content)?);
+            let read = started.elapsed();
+            let substituted = substitute_placeholders(
+                &content,
+                args,
+                &options.vars,
+                &spec.metadata.vars,
+                report,
+            )?;
+            if options.trace {
+                reader.record_timing(FragmentTiming {
+                    fragment: file,
+                    read,
+                    render: started.elapsed().saturating_sub(read),
+                });
+            }
+            if let Some(role) = spec.metadata.roles.get(&index) {
+                role_marks.push((out.len(), *role));
+            }
+            out.push(&substituted)?;
+            if !options.layout.exact && !out.ends_with_newline() {
+                out.push("\n")?;
+            }
+        }
+
+        if let Some(body) = &spec.metadata.repeat_for_args {
+            render_repeated(job, &base, body, arity, out)?;
+        }
+        Ok(role_marks)
+    }
+
+    /// Render a template file or `template_inline` body against the prompt's data, context,
+    /// and preset.
+    fn render_template_prompt(
+        &self,
+        job: &RenderJob<'_>,
+        base: &Utf8Path,
+        source: &TemplateSource<'_>,
+        data: Option<StructuredData>,
+        out: &mut Emitter<'_>,
+    ) -> Result<()> {
+        let RenderJob {
+            reader,
+            name,
+            spec,
+            args,
+            options,
+            report,
+        } = *job;
+        let data = data.or_else(|| default_data(spec, base));
+        if data.is_none() && options.context.is_empty() && options.preset.is_none() {
+            bail!("prompt '{name}' requires a data file for structured context");
+        }
+
+        let context = self.render_context(name, data.as_ref(), args, options)?;
+        check_required_vars(name, &spec.metadata.vars, &context, report)?;
+        let fragment = match *source {
+            TemplateSource::File(template) => template.to_string(),
+            TemplateSource::Inline(_) => "template_inline".to_owned(),
+        };
+        let settings = &self.config.settings;
+        let started = Instant::now();
+        let autoescape = spec.metadata.autoescape;
+        let rendered = render_template(reader, name, base, source, context, autoescape, settings)?;
+        if options.trace {
+            reader.record_timing(FragmentTiming {
+                fragment,
+                read: Duration::ZERO,
+                render: started.elapsed(),
+            });
+        }
+        out.push(&rendered)
+    }
+
+    /// Up to two declared prompts whose names are close to `name`, closest first, to suggest
+    /// when `name` itself is unknown. Case is ignored, and a name differing only in case is
+    /// always the first suggestion.
+    #[must_use]
+    pub fn suggest_prompts(&self, name: &str) -> Vec<&str> {
+        let wanted = name.to_lowercase();
+        let threshold = (wanted.chars().count() / 3).clamp(1, MAX_SUGGESTION_DISTANCE);
+        let mut candidates: Vec<(usize, &str)> = self
+            .prompt_names()
+            .filter_map(|candidate| {
+                let distance = edit_distance(&wanted, &candidate.to_lowercase());
+                (distance <= threshold).then_some((distance, candidate))
+            })
+            .collect();
+        // Stable, so equally close names keep their declaration order.
+        candidates.sort_by_key(|(distance, _)| *distance);
+        candidates
+            .into_iter()
+            .take(2)
+            .map(|(_, candidate)| candidate)
+            .collect()
+    }
+
+    fn unknown_prompt(&self, name: &str) -> anyhow::Error {
+        anyhow!(
+            "unknown prompt: {name}{}",
+            did_you_mean(&self.suggest_prompts(name))
+        )
+    }
+
+    #[must_use]
+    pub fn available_prompts(&self) -> BTreeMap<String, PromptKind> {
+        self.config
+            .prompts
+            .iter()
+            .map(|(name, spec)| (name.clone(), spec.kind.clone()))
+            .collect()
+    }
+
+    /// Prompt names in the order they were declared, without the sorting `available_prompts`
+    /// applies.
+    pub fn prompt_names(&self) -> impl Iterator<Item = &str> {
+        self.config.prompts.keys().map(String::as_str)
+    }
+
+    #[must_use]
+    pub fn prompt_specs(&self) -> &IndexMap<String, PromptSpec> {
+        &self.config.prompts
+    }
+
+    #[must_use]
     pub fn prompt_spec(&self, name: &str) -> Option<&PromptSpec> {
         self.config.prompts.get(name)
     }
@@ -289,9 +1483,58 @@ impl PromptAssembler {
         !self.config.prompts.is_empty()
     }
 
+    /// Resolve sequence entries to fragment paths, expanding an entry that names a local
+    /// directory into the files it contains. Each path is paired with the index of the entry it
+    /// came from and the label used in errors.
+    fn sequence_fragments(
+        &self,
+        base: &Utf8Path,
+        files: &[Utf8PathBuf],
+    ) -> Result<Vec<(usize, String, Utf8PathBuf)>> {
+        let mut fragments = Vec::new();
+        for (index, file) in files.iter().enumerate() {
+            let full_path = resolve_fragment(base, file);
+            if is_remote(file.as_str()) || !self.source.is_dir(&full_path) {
+                fragments.push((index, file.to_string(), full_path));
+                continue;
+            }
+            for path in self.expand_directory(&full_path)? {
+                let label = path
+                    .strip_prefix(base)
+                    .map_or_else(|_| path.to_string(), ToString::to_string);
+                fragments.push((index, label, path));
+            }
+        }
+        Ok(fragments)
+    }
+
+    /// List the files directly inside `dir`, sorted by name. Hidden files are skipped, and
+    /// only files with an allowed extension are kept when `fragment_extensions` is set.
+    fn expand_directory(&self, dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+        let allowed = self.config.settings.fragment_extensions.as_deref();
+        let mut files = Vec::new();
+        for entry in self.source.list_dir(dir)? {
+            let path = entry.path;
+            if entry.is_dir || path.file_name().is_none_or(|name| name.starts_with('.')) {
+                continue;
+            }
+            if let Some(allowed) = allowed
+                && !path
+                    .extension()
+                    .is_some_and(|extension| allowed.iter().any(|allowed| allowed == extension))
+            {
+                continue;
+            }
+            files.push(path);
+        }
+        files.sort();
+        Ok(files)
+    }
+
     fn resolve_prompt_path(&self, spec: &PromptSpec) -> Option<Utf8PathBuf> {
-        spec.prompt_path_override
+        spec.working_dir
             .clone()
+            .or_else(|| spec.prompt_path_override.clone())
             .or_else(|| self.config.default_prompt_path.clone())
     }
 
@@ -309,7 +1552,7 @@ impl PromptAssembler {
             .config
             .prompts
             .get(name)
-            .ok_or_else(|| anyhow!("unknown prompt: {name}"))?;
+            .ok_or_else(|| self.unknown_prompt(name))?;
 
         let base = self
             .resolve_prompt_path(spec)
@@ -320,9 +1563,8 @@ impl PromptAssembler {
                 let mut parts: Vec<PromptPart> = Vec::new();
                 let mut combined = String::new();
 
-                for file in files {
-                    let full_path = base.join(file);
-                    let raw = read_utf8(full_path.as_ref()).with_context(|| {
+                for (_, file, full_path) in self.sequence_fragments(&base, files)? {
+                    let raw = self.source.read(&full_path).with_context(|| {
                         format!("failed to read fragment '{file}' for prompt '{name}'")
                     })?;
                     combined.push_str(&raw);
@@ -339,7 +1581,7 @@ impl PromptAssembler {
             }
             PromptKind::Template { template } => {
                 let full_path = base.join(template);
-                let raw = read_utf8(full_path.as_ref()).with_context(|| {
+                let raw = self.source.read(&full_path).with_context(|| {
                     format!("failed to read template '{template}' for prompt '{name}'")
                 })?;
 
@@ -350,164 +1592,1002 @@ impl PromptAssembler {
                     },
                 })
             }
+            PromptKind::Inline { body } => Ok(PromptProfile::Inline { body: body.clone() }),
+            PromptKind::InlineTemplate { body } => {
+                Ok(PromptProfile::InlineTemplate { body: body.clone() })
+            }
         }
     }
 
+    /// The directory prompt files resolve against when a prompt sets no `prompt_path` of its
+    /// own.
     #[must_use]
-    pub fn config_warnings(&self) -> &[ConfigIssue] {
-        &self.warnings
+    pub fn default_prompt_path(&self) -> &Utf8Path {
+        self.config
+            .default_prompt_path
+            .as_deref()
+            .unwrap_or(&self.config.root)
     }
 
-    /// Assemble a sequence of raw prompt parts by name without placeholder substitution.
+    /// Resolve the directory a prompt's files are read from: its `prompt_path`, or the
+    /// configuration default.
     ///
     /// # Errors
-    /// Returns an error when a part cannot be located or read.
-    pub fn assemble_parts(&self, working_dir: &Utf8Path, part_names: &[String]) -> Result<String> {
-        if part_names.is_empty() {
-            bail!("no parts provided");
-        }
+    /// Returns an error when the prompt is unknown or has no resolvable `prompt_path`.
+    pub fn prompt_directory(&self, name: &str) -> Result<Utf8PathBuf> {
+        let spec = self
+            .config
+            .prompts
+            .get(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        self.resolve_prompt_path(spec)
+            .context("prompt missing prompt_path")
+    }
 
-        let mut output = String::new();
-        for name in part_names {
-            let resolved = self.resolve_part_path(working_dir, name)?;
-            let contents = read_utf8(resolved.as_path())
-                .with_context(|| format!("failed to read part '{name}' at {resolved}"))?;
-            output.push_str(&contents);
-        }
+    /// Resolve the files a prompt reads from, in render order, without reading them.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or has no resolvable `prompt_path`.
+    pub fn prompt_sources(&self, name: &str) -> Result<Vec<Utf8PathBuf>> {
+        let spec = self
+            .config
+            .prompts
+            .get(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
 
-        Ok(output)
-    }
+        let base = self
+            .resolve_prompt_path(spec)
+            .context("prompt missing prompt_path")?;
 
-    fn resolve_part_path(&self, working_dir: &Utf8Path, raw: &str) -> Result<Utf8PathBuf> {
-        let candidate = Utf8PathBuf::from(raw);
+        Ok(match &spec.kind {
+            PromptKind::Sequence { files } => self
+                .sequence_fragments(&base, files)?
+                .into_iter()
+                .map(|(_, _, path)| path)
+                .chain(
+                    spec.metadata
+                        .repeat_for_args
+                        .as_ref()
+                        .map(|body| resolve_fragment(&base, body)),
+                )
+                .collect(),
+            PromptKind::Template { template } => vec![base.join(template)],
+            PromptKind::Inline { .. } | PromptKind::InlineTemplate { .. } => Vec::new(),
+        })
+    }
 
-        if candidate.is_absolute() {
-            if candidate.exists() {
-                return Ok(candidate);
+    /// The templates template prompt `name` loads through `include`, `extends`, `import`, and
+    /// `from` tags, transitively, along with the `macros` every template imports. Only names
+    /// written as string literals can be followed.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or not a template prompt, or a template
+    /// exists but cannot be read.
+    pub fn template_dependencies(&self, name: &str) -> Result<TemplateDependency> {
+        let spec = self
+            .prompt_spec(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        let mut seen = HashSet::new();
+        let (base, mut root) = match &spec.kind {
+            PromptKind::Template { template } => {
+                let base = self
+                    .resolve_prompt_path(spec)
+                    .context("template prompt missing prompt_path")?;
+                let root = self.template_node(&base, template.as_str(), &mut seen)?;
+                (base, root)
             }
-            bail!("missing part '{raw}'");
+            PromptKind::InlineTemplate { body } => {
+                let base = self
+                    .resolve_prompt_path(spec)
+                    .unwrap_or_else(|| self.config.root.clone());
+                let dependencies = self.template_children(&base, body, &mut seen)?;
+                let root = TemplateDependency {
+                    name: "<inline>".to_owned(),
+                    path: None,
+                    missing: false,
+                    repeated: false,
+                    dependencies,
+                };
+                (base, root)
+            }
+            PromptKind::Sequence { .. } | PromptKind::Inline { .. } => {
+                bail!("prompt '{name}' is not a template prompt")
+            }
+        };
+        for path in macro_paths(self.source.as_ref(), &base, &self.config.settings.macros)? {
+            let relative = path.strip_prefix(&base).unwrap_or(&path).to_string();
+            root.dependencies
+                .push(self.template_node(&base, &relative, &mut seen)?);
         }
+        Ok(root)
+    }
 
-        let cwd_candidate = working_dir.join(&candidate);
-        if cwd_candidate.exists() {
-            return Ok(cwd_candidate);
+    fn template_node(
+        &self,
+        base: &Utf8Path,
+        name: &str,
+        seen: &mut HashSet<Utf8PathBuf>,
+    ) -> Result<TemplateDependency> {
+        let path = base.join(name);
+        let mut node = TemplateDependency {
+            name: name.to_owned(),
+            path: Some(path.clone()),
+            missing: false,
+            repeated: false,
+            dependencies: Vec::new(),
+        };
+        if !seen.insert(path.clone()) {
+            node.repeated = true;
+        } else if !self.source.exists(&path) {
+            node.missing = true;
+        } else {
+            let content = self
+                .source
+                .read(&path)
+                .with_context(|| format!("failed to read template {path}"))?;
+            node.dependencies = self.template_children(base, &content, seen)?;
         }
+        Ok(node)
+    }
 
-        if let Some(base) = &self.config.default_prompt_path {
-            let prompt_candidate = base.join(&candidate);
-            if prompt_candidate.exists() {
-                return Ok(prompt_candidate);
-            }
-        }
+    fn template_children(
+        &self,
+        base: &Utf8Path,
+        content: &str,
+        seen: &mut HashSet<Utf8PathBuf>,
+    ) -> Result<Vec<TemplateDependency>> {
+        orphans::template_references(content)
+            .into_iter()
+            .map(|target| self.template_node(base, target, seen))
+            .collect()
+    }
 
-        bail!("missing part '{raw}'")
+    /// Count the positional arguments a sequence or inline prompt references: the highest `{N}`
+    /// index across its fragments (after transclusion) plus one. Templates return `None`.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or a fragment cannot be read or parsed.
+    pub fn placeholder_arity(&self, name: &str) -> Result<Option<usize>> {
+        let spec = self
+            .config
+            .prompts
+            .get(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        Ok(self.placeholder_usage(name, spec)?.map(|usage| usage.arity))
     }
-}
 
-struct ConfigLoad {
-    config: Config,
-    warnings: Vec<ConfigIssue>,
-}
+    /// The declared vars of a sequence or inline prompt that describe positional arguments,
+    /// with the position each fills, in position order. A var with `position = N` fills `{N}`;
+    /// any other var that no `{name}` placeholder, `default`, or `required_if` uses takes the
+    /// next free position in declaration order. Templates have none.
+    ///
+    /// # Errors
+    /// Returns an error when the prompt is unknown or a fragment cannot be read or parsed.
+    pub fn positional_vars(&self, name: &str) -> Result<Vec<(usize, &PromptVariable)>> {
+        let spec = self
+            .config
+            .prompts
+            .get(name)
+            .ok_or_else(|| self.unknown_prompt(name))?;
+        let Some(usage) = self.placeholder_usage(name, spec)? else {
+            return Ok(Vec::new());
+        };
+        Ok(bind_positions(&spec.metadata.vars, Some(&usage)))
+    }
 
-fn load_config(root: &Utf8Path) -> std::result::Result<ConfigLoad, LoadConfigError> {
-    let mut prompts: IndexMap<String, PromptSpec> = IndexMap::new();
-    let mut default_prompt_path: Option<Utf8PathBuf> = Some(root.to_owned());
-    let mut warnings: Vec<ConfigIssue> = Vec::new();
-    let mut errors: Vec<ConfigIssue> = Vec::new();
+    /// The `{N}` and `{name}` placeholders a sequence or inline prompt uses across its
+    /// fragments, after transclusion. Templates return `None`.
+    fn placeholder_usage(&self, name: &str, spec: &PromptSpec) -> Result<Option<PlaceholderUsage>> {
+        let mut usage = PlaceholderUsage::default();
+        let files = match &spec.kind {
+            PromptKind::Sequence { files } => files,
+            PromptKind::Inline { body } => {
+                usage.add(body)?;
+                return Ok(Some(usage));
+            }
+            PromptKind::Template { .. } | PromptKind::InlineTemplate { .. } => return Ok(None),
+        };
+        let base = self
+            .resolve_prompt_path(spec)
+            .context("sequence prompt missing prompt_path")?;
 
-    let main_config = root.join("config.toml");
-    if main_config.exists() {
-        process_config_file(
-            root,
-            main_config.as_ref(),
-            &mut prompts,
-            &mut default_prompt_path,
-            &mut warnings,
-            &mut errors,
-        )?;
+        let reader = FragmentReader::new(Arc::clone(&self.source), self.config.settings.lossy_utf8);
+        for (_, file, full_path) in self.sequence_fragments(&base, files)? {
+            let content = reader
+                .read(&full_path)
+                .with_context(|| format!("failed to read fragment '{file}' for prompt '{name}'"))?;
+            let content = expand_transclusions(&reader, &content, &base, 0)?;
+            usage
+                .add(&content)
+                .with_context(|| format!("invalid placeholder in fragment '{file}'"))?;
+        }
+        Ok(Some(usage))
     }
 
-    let conf_d = root.join("conf.d");
-    if conf_d.exists() {
-        let mut entries: Vec<Utf8PathBuf> = Vec::new();
-        let read_dir =
-            fs::read_dir(conf_d.as_std_path()).map_err(|source| LoadConfigError::ReadDir {
-                path: conf_d.clone(),
-                source,
-            })?;
+    /// Compile every template prompt, file or `template_inline`, without rendering it and report
+    /// syntax errors, along with `macros` patterns that cannot be expanded or that match two
+    /// files imported under the same alias.
+    ///
+    /// Templates that cannot be read are skipped; rendering reports those separately.
+    #[must_use]
+    pub fn check_templates(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
 
-        for entry in read_dir {
-            let entry = match entry {
-                Ok(entry) => entry,
-                Err(err) => {
-                    errors.push(ConfigIssue::new(
-                        ConfigIssueCode::ParseError,
-                        conf_d.clone(),
-                        None,
-                        format!("failed to read entry in {conf_d}: {err}"),
-                    ));
-                    continue;
-                }
+        for (name, spec) in &self.config.prompts {
+            let Some((template_name, full_path, source)) = self.template_text(name, spec) else {
+                continue;
             };
 
-            let path = entry.path();
-            if path.extension().is_some_and(|ext| ext == "toml") {
-                match Utf8PathBuf::from_path_buf(path) {
-                    Ok(path) => entries.push(path),
-                    Err(_) => errors.push(ConfigIssue::new(
-                        ConfigIssueCode::ParseError,
-                        conf_d.clone(),
+            let base = self
+                .resolve_prompt_path(spec)
+                .unwrap_or_else(|| self.config.root.clone());
+            if let Err(err) =
+                macro_aliases(self.source.as_ref(), &base, &self.config.settings.macros)
+            {
+                issues.push(
+                    ConfigIssue::new(
+                        ConfigIssueCode::InvalidPrompt,
+                        full_path.clone(),
                         None,
-                        "configuration paths must be valid UTF-8",
-                    )),
-                }
+                        format!("macros for prompt '{name}' cannot be imported: {err:#}"),
+                    )
+                    .for_prompt(name),
+                );
+            }
+
+            let env = Environment::new();
+            if let Err(err) = env.template_from_named_str(&template_name, &source) {
+                let line = err.line().and_then(|line| u32::try_from(line).ok());
+                issues.push(
+                    ConfigIssue::new(
+                        ConfigIssueCode::InvalidPrompt,
+                        full_path,
+                        line,
+                        format!("template for prompt '{name}' has a syntax error: {err}"),
+                    )
+                    .for_prompt(name),
+                );
             }
         }
 
-        entries.sort();
+        issues
+    }
 
-        for entry in entries {
-            process_config_file(
-                root,
-                entry.as_ref(),
-                &mut prompts,
-                &mut default_prompt_path,
-                &mut warnings,
-                &mut errors,
-            )?;
+    /// Load every prompt's declared `data` file and report those that are missing or do not
+    /// parse as their format.
+    #[must_use]
+    pub fn check_data_files(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        for (name, spec) in &self.config.prompts {
+            let Some(path) = &spec.metadata.data else {
+                continue;
+            };
+            let base = self
+                .resolve_prompt_path(spec)
+                .unwrap_or_else(|| self.config.root.clone());
+            let Some(data) = default_data(spec, &base) else {
+                continue;
+            };
+            if let Err(err) = load_structured_data(&data) {
+                issues.push(
+                    ConfigIssue::new(
+                        ConfigIssueCode::ParseError,
+                        base.join(path),
+                        None,
+                        format!("data file for prompt '{name}' could not be loaded: {err:#}"),
+                    )
+                    .for_prompt(name),
+                );
+            }
         }
-    }
 
-    if errors.is_empty() {
-        Ok(ConfigLoad {
-            config: Config {
-                root: root.to_owned(),
-                default_prompt_path,
-                prompts,
-            },
-            warnings,
-        })
-    } else {
-        Err(LoadConfigError::Invalid {
-            diagnostics: ConfigDiagnostics { errors, warnings },
-        })
+        issues
     }
-}
 
-fn process_config_file(
-    root: &Utf8Path,
-    path: &Utf8Path,
-    prompts: &mut IndexMap<String, PromptSpec>,
-    default_prompt_path: &mut Option<Utf8PathBuf>,
-    warnings: &mut Vec<ConfigIssue>,
-    errors: &mut Vec<ConfigIssue>,
+    /// Hash every pinned sequence entry and report those whose SHA-256 differs from the pin.
+    /// Entries that cannot be read are reported as [`ConfigIssueCode::ReadError`] issues, since
+    /// rendering would fail on them.
+    #[must_use]
+    pub fn check_pinned_hashes(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        for (name, spec) in &self.config.prompts {
+            if spec.metadata.pins.is_empty() {
+                continue;
+            }
+            let Some(base) = self.resolve_prompt_path(spec) else {
+                continue;
+            };
+            let PromptKind::Sequence { files } = &spec.kind else {
+                continue;
+            };
+            for (&index, expected) in &spec.metadata.pins {
+                let file = &files[index];
+                let path = resolve_fragment(&base, file);
+                let (code, result) = match self.source.read_bytes(&path) {
+                    Ok(content) => (
+                        ConfigIssueCode::HashMismatch,
+                        verify_pin(name, file.as_str(), expected, &content),
+                    ),
+                    Err(err) => (ConfigIssueCode::ReadError, Err(err)),
+                };
+                if let Err(err) = result {
+                    issues.push(
+                        ConfigIssue::new(code, path, None, format!("{err:#}")).for_prompt(name),
+                    );
+                }
+            }
+        }
+
+        issues
+    }
+
+    /// Report sequence and inline prompts whose `{N}` placeholders skip an index, such as `{0}`
+    /// and `{2}` without `{1}`. Indices are collected across all of a prompt's fragments after
+    /// transclusion; fragments that cannot be read or parsed are skipped.
+    #[must_use]
+    pub fn check_placeholder_gaps(&self) -> Vec<ConfigIssue> {
+        let reader = FragmentReader::new(Arc::clone(&self.source), self.config.settings.lossy_utf8);
+        let mut issues = Vec::new();
+
+        for (name, spec) in &self.config.prompts {
+            let contents = match &spec.kind {
+                PromptKind::Inline { body } => vec![body.clone()],
+                PromptKind::Sequence { files } => {
+                    let Some(base) = self.resolve_prompt_path(spec) else {
+                        continue;
+                    };
+                    let Ok(fragments) = self.sequence_fragments(&base, files) else {
+                        continue;
+                    };
+                    fragments
+                        .into_iter()
+                        .filter_map(|(_, _, path)| reader.read(&path).ok())
+                        .filter_map(|content| {
+                            expand_transclusions(&reader, &content, &base, 0).ok()
+                        })
+                        .collect()
+                }
+                PromptKind::Template { .. } | PromptKind::InlineTemplate { .. } => continue,
+            };
+
+            let mut used = BTreeSet::new();
+            for content in &contents {
+                let Ok(segments) = parse_placeholders(content) else {
+                    continue;
+                };
+                used.extend(segments.into_iter().filter_map(|segment| match segment {
+                    Segment::Placeholder(index) => Some(index),
+                    Segment::Literal(_) | Segment::Named(_) => None,
+                }));
+            }
+            let Some(&highest) = used.last() else {
+                continue;
+            };
+            let missing: Vec<String> = (0..highest)
+                .filter(|index| !used.contains(index))
+                .map(|index| format!("{{{index}}}"))
+                .collect();
+            if !missing.is_empty() {
+                issues.push(
+                    ConfigIssue::new(
+                        ConfigIssueCode::PlaceholderGap,
+                        spec.metadata.source.path.clone(),
+                        None,
+                        format!(
+                            "prompt '{name}' uses {{{highest}}} but never {}",
+                            missing.join(", ")
+                        ),
+                    )
+                    .for_prompt(name),
+                );
+            }
+        }
+
+        issues
+    }
+
+    /// Report variables a template references but does not list in its `vars`.
+    ///
+    /// Only prompts that declare vars are checked. References anywhere in the template count,
+    /// including inside `{% if %}` and `{% for %}` blocks, so optional vars used conditionally
+    /// are fine as long as they are declared, and the aliases `macros` are imported under are
+    /// never reported. Templates that fail to compile are skipped;
+    /// [`PromptAssembler::check_templates`] reports those and any `macros` that cannot be
+    /// imported.
+    #[must_use]
+    pub fn check_template_vars(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+
+        for (name, spec) in &self.config.prompts {
+            if spec.metadata.vars.is_empty() {
+                continue;
+            }
+            let Some((template_name, full_path, source)) = self.template_text(name, spec) else {
+                continue;
+            };
+            let base = self
+                .resolve_prompt_path(spec)
+                .unwrap_or_else(|| self.config.root.clone());
+            let aliases: HashSet<String> =
+                macro_aliases(self.source.as_ref(), &base, &self.config.settings.macros)
+                    .map(|aliases| aliases.into_iter().map(|(alias, _)| alias).collect())
+                    .unwrap_or_default();
+
+            let env = Environment::new();
+            let Ok(compiled) = env.template_from_named_str(&template_name, &source) else {
+                continue;
+            };
+            let globals: HashSet<&str> = env.globals().map(|(global, _)| global).collect();
+            let mut undeclared: Vec<String> = compiled
+                .undeclared_variables(false)
+                .into_iter()
+                .filter(|var| {
+                    var != "_args"
+                        && var != "_git"
+                        && !globals.contains(var.as_str())
+                        && !aliases.contains(var)
+                })
+                .filter(|var| {
+                    !spec
+                        .metadata
+                        .vars
+                        .iter()
+                        .any(|declared| &declared.name == var)
+                })
+                .collect();
+            undeclared.sort();
+
+            for var in undeclared {
+                issues.push(
+                    ConfigIssue::new(
+                        ConfigIssueCode::UndeclaredVar,
+                        full_path.clone(),
+                        None,
+                        format!("template for prompt '{name}' uses '{var}' but does not declare it in vars"),
+                    )
+                    .for_prompt(name),
+                );
+            }
+        }
+
+        issues
+    }
+
+    /// The name, diagnostic path, and source of a template prompt: the template file, or the
+    /// `template_inline` body reported against the prompt's config file. `None` for other
+    /// kinds and for template files that cannot be read.
+    fn template_text(
+        &self,
+        name: &str,
+        spec: &PromptSpec,
+    ) -> Option<(String, Utf8PathBuf, String)> {
+        match &spec.kind {
+            PromptKind::Template { template } => {
+                let full_path = self.resolve_prompt_path(spec)?.join(template);
+                let source = self.source.read(&full_path).ok()?;
+                Some((template.to_string(), full_path, source))
+            }
+            PromptKind::InlineTemplate { body } => Some((
+                format!("<prompt.{name}>"),
+                spec.metadata.source.path.clone(),
+                body.clone(),
+            )),
+            PromptKind::Sequence { .. } | PromptKind::Inline { .. } => None,
+        }
+    }
+
+    /// Describe the loaded configuration as stable text for golden-file tests.
+    ///
+    /// Prompts are sorted by name, paths are shown relative to the configuration root, and
+    /// modification times are omitted, so the dump is identical across machines.
+    #[must_use]
+    pub fn debug_dump(&self) -> String {
+        dump::dump_config(&self.config)
+    }
+
+    /// List files under the default `prompt_path` that no prompt reads, directly, through
+    /// transclusion markers and template `include`/`extends`/`import` tags, or as `macros`.
+    /// Hidden files and the configuration's own `config.toml`, `conf.d`, and `cache` entries
+    /// are ignored.
+    ///
+    /// # Errors
+    /// Returns an error when there is no default `prompt_path` or it cannot be walked.
+    pub fn orphaned_files(&self) -> Result<Vec<Utf8PathBuf>> {
+        let library = self
+            .config
+            .default_prompt_path
+            .as_deref()
+            .context("configuration has no prompt_path")?;
+
+        let mut referenced: HashSet<Utf8PathBuf> = HashSet::new();
+        for (name, spec) in &self.config.prompts {
+            let (Some(base), Ok(sources)) =
+                (self.resolve_prompt_path(spec), self.prompt_sources(name))
+            else {
+                continue;
+            };
+            let is_template = matches!(
+                spec.kind,
+                PromptKind::Template { .. } | PromptKind::InlineTemplate { .. }
+            );
+            let mut pending = sources;
+            if is_template {
+                pending.extend(macro_paths(
+                    self.source.as_ref(),
+                    &base,
+                    &self.config.settings.macros,
+                )?);
+            }
+            if let PromptKind::InlineTemplate { body } = &spec.kind {
+                pending.extend(
+                    orphans::template_references(body)
+                        .into_iter()
+                        .map(|target| base.join(target)),
+                );
+            }
+            while let Some(path) = pending.pop() {
+                if is_remote(path.as_str()) || !referenced.insert(path.clone()) {
+                    continue;
+                }
+                let Ok(content) = self.source.read(&path) else {
+                    continue;
+                };
+                if is_template {
+                    pending.extend(
+                        orphans::template_references(&content)
+                            .into_iter()
+                            .map(|target| base.join(target)),
+                    );
+                } else {
+                    pending.extend(
+                        orphans::transclusion_targets(&content)
+                            .into_iter()
+                            .map(|target| resolve_fragment(&base, Utf8Path::new(target))),
+                    );
+                }
+            }
+        }
+
+        let root = &self.config.root;
+        let skip: HashSet<Utf8PathBuf> = ["config.toml", "conf.d", "cache"]
+            .into_iter()
+            .map(|entry| root.join(entry))
+            .collect();
+        let files = orphans::library_files(library, &skip)?;
+        Ok(files
+            .into_iter()
+            .filter(|file| !referenced.contains(file))
+            .collect())
+    }
+
+    #[must_use]
+    pub fn config_warnings(&self) -> &[ConfigIssue] {
+        &self.warnings
+    }
+
+    /// The settings in effect after every configuration file was read.
+    #[must_use]
+    pub fn settings(&self) -> &Settings {
+        &self.config.settings
+    }
+
+    #[must_use]
+    pub fn skipped_prompts(&self) -> &[SkippedPrompt] {
+        &self.skipped
+    }
+
+    /// Assemble a sequence of raw prompt parts by name without placeholder substitution.
+    ///
+    /// # Errors
+    /// Returns an error when a part cannot be located or read.
+    pub fn assemble_parts(&self, working_dir: &Utf8Path, part_names: &[String]) -> Result<String> {
+        let parts = self.read_parts(working_dir, part_names)?;
+        Ok(parts.into_iter().map(|part| part.content).collect())
+    }
+
+    /// Resolve and read each named part, in order, as [`PromptAssembler::assemble_parts`] does.
+    ///
+    /// # Errors
+    /// Returns an error when no parts are given or a part cannot be located or read.
+    pub fn read_parts(
+        &self,
+        working_dir: &Utf8Path,
+        part_names: &[String],
+    ) -> Result<Vec<PromptPart>> {
+        self.read_parts_concurrently(working_dir, part_names, NonZeroUsize::MIN)
+    }
+
+    /// Read parts like [`PromptAssembler::read_parts`], using up to `concurrency` worker
+    /// threads. Parts are returned in the order given; the first failing part, in that order,
+    /// is reported and no new reads start once any read fails.
+    ///
+    /// # Errors
+    /// Returns an error when no parts are given or a part cannot be located or read.
+    pub fn read_parts_concurrently(
+        &self,
+        working_dir: &Utf8Path,
+        part_names: &[String],
+        concurrency: NonZeroUsize,
+    ) -> Result<Vec<PromptPart>> {
+        if part_names.is_empty() {
+            bail!("no parts provided");
+        }
+
+        let workers = concurrency.get().min(part_names.len());
+        if workers == 1 {
+            let mut parts = Vec::new();
+            for name in part_names {
+                parts.extend(self.read_part(working_dir, name)?);
+            }
+            return Ok(parts);
+        }
+
+        // Workers claim indices in ascending order, so every part before a failing one has
+        // been read by the time the failure is reported.
+        let next = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let mut results: Vec<Option<Result<Vec<PromptPart>>>> = std::iter::repeat_with(|| None)
+            .take(part_names.len())
+            .collect();
+        thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        while !failed.load(Ordering::Relaxed) {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(name) = part_names.get(index) else {
+                                break;
+                            };
+                            let result = self.read_part(working_dir, name);
+                            if result.is_err() {
+                                failed.store(true, Ordering::Relaxed);
+                            }
+                            done.push((index, result));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let done = handle
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+                for (index, result) in done {
+                    results[index] = Some(result);
+                }
+            }
+        });
+
+        let parts: Vec<Vec<PromptPart>> = results.into_iter().flatten().collect::<Result<_>>()?;
+        Ok(parts.into_iter().flatten().collect())
+    }
+
+    /// Read one named part, or every file inside it when it names a local directory.
+    fn read_part(&self, working_dir: &Utf8Path, name: &str) -> Result<Vec<PromptPart>> {
+        let path = self.resolve_part_path(working_dir, name)?;
+        let paths = if !is_remote(name) && self.source.is_dir(&path) {
+            self.expand_directory(&path)?
+        } else {
+            vec![path]
+        };
+        paths
+            .into_iter()
+            .map(|path| {
+                let content = self
+                    .source
+                    .read(&path)
+                    .with_context(|| format!("failed to read part '{name}' at {path}"))?;
+                Ok(PromptPart { path, content })
+            })
+            .collect()
+    }
+
+    fn resolve_part_path(&self, working_dir: &Utf8Path, raw: &str) -> Result<Utf8PathBuf> {
+        let candidate = Utf8PathBuf::from(raw);
+
+        if is_remote(raw) {
+            return Ok(candidate);
+        }
+
+        if candidate.is_absolute() {
+            if self.source.exists(&candidate) {
+                return Ok(candidate);
+            }
+            bail!("missing part '{raw}'");
+        }
+
+        let cwd_candidate = working_dir.join(&candidate);
+        if self.source.exists(&cwd_candidate) {
+            return Ok(cwd_candidate);
+        }
+
+        if let Some(base) = &self.config.default_prompt_path {
+            let prompt_candidate = base.join(&candidate);
+            if self.source.exists(&prompt_candidate) {
+                return Ok(prompt_candidate);
+            }
+        }
+
+        bail!("missing part '{raw}'")
+    }
+}
+
+/// The source used for configurations loaded from `dir`: the filesystem, plus cached
+/// `http(s)://` fragments when the `remote` feature is enabled.
+fn default_source(
+    dir: &Utf8Path,
+    settings: &Settings,
+    options: &LoadOptions,
+) -> Arc<dyn FragmentSource> {
+    #[cfg(feature = "archive")]
+    let local = ArchiveSource::new(FileSystemSource);
+    #[cfg(not(feature = "archive"))]
+    let local = FileSystemSource;
+    #[cfg(feature = "remote")]
+    {
+        Arc::new(
+            RemoteSource::new(local, dir.join("cache").join("remote"))
+                .with_ttl(
+                    settings
+                        .remote_cache_ttl
+                        .map(std::time::Duration::from_secs),
+                )
+                .offline(options.offline),
+        )
+    }
+    #[cfg(not(feature = "remote"))]
+    {
+        let _ = (dir, settings, options);
+        Arc::new(local)
+    }
+}
+
+/// Whether a fragment reference names an `http://` or `https://` URL.
+pub(crate) fn is_remote(reference: &str) -> bool {
+    reference.starts_with("https://") || reference.starts_with("http://")
+}
+
+/// Join a fragment reference onto `base`, leaving URLs untouched.
+fn resolve_fragment(base: &Utf8Path, file: &Utf8Path) -> Utf8PathBuf {
+    if is_remote(file.as_str()) {
+        file.to_owned()
+    } else {
+        base.join(file)
+    }
+}
+
+struct ConfigLoad {
+    config: Config,
+    errors: Vec<ConfigIssue>,
+    warnings: Vec<ConfigIssue>,
+    skipped: Vec<SkippedPrompt>,
+    prompt_tags: BTreeMap<String, Vec<String>>,
+}
+
+struct LoadState<'a> {
+    options: &'a LoadOptions,
+    pending: IndexMap<String, PendingPrompt>,
+    /// Prompts skipped by their activation conditions, still available as a `base`.
+    inactive: IndexMap<String, PendingPrompt>,
+    default_prompt_path: Option<Utf8PathBuf>,
+    settings: Settings,
+    warnings: Vec<ConfigIssue>,
+    errors: Vec<ConfigIssue>,
+    skipped: Vec<SkippedPrompt>,
+    prompt_tags: BTreeMap<String, Vec<String>>,
+}
+
+fn load_config(
+    root: &Utf8Path,
+    options: &LoadOptions,
+) -> std::result::Result<ConfigLoad, LoadConfigError> {
+    let load = collect_config(root, options)?;
+    if load.errors.is_empty() {
+        Ok(load)
+    } else {
+        Err(LoadConfigError::Invalid {
+            diagnostics: ConfigDiagnostics {
+                errors: load.errors,
+                warnings: load.warnings,
+                prompt_tags: load.prompt_tags,
+            },
+        })
+    }
+}
+
+/// Read every configuration file under `root`, keeping the prompts that load and recording the
+/// errors of those that do not.
+fn collect_config(
+    root: &Utf8Path,
+    options: &LoadOptions,
+) -> std::result::Result<ConfigLoad, LoadConfigError> {
+    let mut state = LoadState {
+        options,
+        pending: IndexMap::new(),
+        inactive: IndexMap::new(),
+        default_prompt_path: Some(root.to_owned()),
+        settings: Settings::default(),
+        warnings: Vec::new(),
+        errors: Vec::new(),
+        skipped: Vec::new(),
+        prompt_tags: BTreeMap::new(),
+    };
+
+    let main_config = root.join("config.toml");
+    if main_config.exists() {
+        process_config_file(root, main_config.as_ref(), &mut state)?;
+    }
+
+    let conf_d = root.join("conf.d");
+    if conf_d.exists() {
+        let mut entries: Vec<Utf8PathBuf> = Vec::new();
+        let mut ancestors = HashSet::new();
+        collect_conf_d(&conf_d, 0, &mut ancestors, &mut entries, &mut state.errors)?;
+
+        entries.sort();
+        // A file reached through two symlinked directories loads once, at its first path.
+        let mut loaded = HashSet::new();
+        entries.retain(|entry| {
+            fs::canonicalize(entry.as_std_path()).map_or(true, |canonical| loaded.insert(canonical))
+        });
+
+        for entry in entries {
+            process_config_file(root, entry.as_ref(), &mut state)?;
+        }
+    }
+
+    let LoadState {
+        pending,
+        inactive,
+        default_prompt_path,
+        settings,
+        mut warnings,
+        mut errors,
+        skipped,
+        mut prompt_tags,
+        ..
+    } = state;
+
+    let mut prompts = IndexMap::new();
+    for (name, entry) in &pending {
+        if entry.prompt.is_abstract {
+            continue;
+        }
+        let resolved =
+            inherit_prompt(name, &pending, &inactive, &mut Vec::new()).and_then(|prompt| {
+                prompt_tags.insert(name.clone(), prompt.tags.clone());
+                warnings.extend(ignored_keys(name, &prompt, &entry.source));
+                warnings.extend(required_defaults(name, &prompt, &entry.source));
+                build_prompt_spec(root, name, prompt, &entry.source, &entry.settings)
+            });
+        match resolved {
+            Ok(spec) => {
+                prompts.insert(name.clone(), spec);
+            }
+            Err(issue) => errors.push(issue.for_prompt(name)),
+        }
+    }
+
+    Ok(ConfigLoad {
+        config: Config {
+            root: root.to_owned(),
+            default_prompt_path,
+            prompts,
+            settings,
+        },
+        errors,
+        warnings,
+        skipped,
+        prompt_tags,
+    })
+}
+
+/// Gather every `*.toml` file under `dir`, descending into subdirectories. `ancestors` holds
+/// the canonical paths of the directories being descended, so a symlink leading back to one of
+/// them is reported instead of followed, while two symlinks to the same directory elsewhere
+/// are both followed. Nesting deeper than [`MAX_CONF_D_DEPTH`] is reported and skipped.
+fn collect_conf_d(
+    dir: &Utf8Path,
+    depth: usize,
+    ancestors: &mut HashSet<std::path::PathBuf>,
+    entries: &mut Vec<Utf8PathBuf>,
+    errors: &mut Vec<ConfigIssue>,
+) -> std::result::Result<(), LoadConfigError> {
+    if depth > MAX_CONF_D_DEPTH {
+        errors.push(ConfigIssue::new(
+            ConfigIssueCode::ParseError,
+            dir.to_owned(),
+            None,
+            format!("{dir} is nested more than {MAX_CONF_D_DEPTH} directories deep in conf.d"),
+        ));
+        return Ok(());
+    }
+    let canonical =
+        fs::canonicalize(dir.as_std_path()).map_err(|source| LoadConfigError::ReadDir {
+            path: dir.to_owned(),
+            source,
+        })?;
+    if !ancestors.insert(canonical.clone()) {
+        errors.push(ConfigIssue::new(
+            ConfigIssueCode::ParseError,
+            dir.to_owned(),
+            None,
+            format!("symlink loop: {dir} leads back to a directory that contains it"),
+        ));
+        return Ok(());
+    }
+    let collected = collect_conf_d_entries(dir, depth, ancestors, entries, errors);
+    ancestors.remove(&canonical);
+    collected
+}
+
+fn collect_conf_d_entries(
+    dir: &Utf8Path,
+    depth: usize,
+    ancestors: &mut HashSet<std::path::PathBuf>,
+    entries: &mut Vec<Utf8PathBuf>,
+    errors: &mut Vec<ConfigIssue>,
+) -> std::result::Result<(), LoadConfigError> {
+    let read_dir = fs::read_dir(dir.as_std_path()).map_err(|source| LoadConfigError::ReadDir {
+        path: dir.to_owned(),
+        source,
+    })?;
+    for entry in read_dir {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                errors.push(ConfigIssue::new(
+                    ConfigIssueCode::ParseError,
+                    dir.to_owned(),
+                    None,
+                    format!("failed to read entry in {dir}: {err}"),
+                ));
+                continue;
+            }
+        };
+        let Ok(path) = Utf8PathBuf::from_path_buf(entry.path()) else {
+            errors.push(ConfigIssue::new(
+                ConfigIssueCode::ParseError,
+                dir.to_owned(),
+                None,
+                "configuration paths must be valid UTF-8",
+            ));
+            continue;
+        };
+        let is_toml = path.extension() == Some("toml");
+        // Symlinks are followed; a `.toml` one that loops on itself or dangles is reported.
+        match fs::metadata(path.as_std_path()) {
+            Ok(metadata) if metadata.is_dir() => {
+                collect_conf_d(&path, depth + 1, ancestors, entries, errors)?;
+            }
+            Ok(metadata) if metadata.is_file() && is_toml => entries.push(path),
+            Ok(_) => {}
+            Err(_) if !is_toml => {}
+            Err(err) => errors.push(ConfigIssue::new(
+                ConfigIssueCode::ParseError,
+                path.clone(),
+                None,
+                format!("cannot follow {path}: {err}"),
+            )),
+        }
+    }
+    Ok(())
+}
+
+fn process_config_file(
+    root: &Utf8Path,
+    path: &Utf8Path,
+    state: &mut LoadState,
 ) -> std::result::Result<(), LoadConfigError> {
     let content = read_config_file(path)?;
     let raw: RawFile = match toml::from_str(&content) {
         Ok(raw) => raw,
         Err(err) => {
             let line = None;
-            errors.push(ConfigIssue::new(
+            state.errors.push(ConfigIssue::new(
                 ConfigIssueCode::ParseError,
                 path.to_owned(),
                 line,
@@ -517,11 +2597,46 @@ fn process_config_file(
         }
     };
 
+    if let Some(mut settings) = raw.settings {
+        let audit_log = settings.audit_log.take();
+        let context_file = settings.context_file.take();
+        settings.apply(&mut state.settings);
+        if let Some(path_str) = audit_log {
+            match resolve_configured_path(root, &path_str, &state.settings) {
+                Ok(resolved) => state.settings.audit_log = Some(resolved),
+                Err(err) => state.errors.push(ConfigIssue::new(
+                    ConfigIssueCode::InvalidSetting,
+                    path.to_owned(),
+                    None,
+                    format!("invalid audit_log '{path_str}': {err}"),
+                )),
+            }
+        }
+        if let Some(path_str) = context_file {
+            let resolved =
+                resolve_configured_path(root, &path_str, &state.settings).and_then(|resolved| {
+                    match StructuredData::from_path(resolved.clone()) {
+                        Some(_) => Ok(resolved),
+                        None => Err(anyhow!("context_file must be JSON, TOML, or YAML")),
+                    }
+                });
+            match resolved {
+                Ok(resolved) => state.settings.context_file = Some(resolved),
+                Err(err) => state.errors.push(ConfigIssue::new(
+                    ConfigIssueCode::InvalidSetting,
+                    path.to_owned(),
+                    None,
+                    format!("invalid context_file '{path_str}': {err}"),
+                )),
+            }
+        }
+    }
+
     if let Some(path_str) = raw.prompt_path {
-        match resolve_path(root, &path_str) {
-            Ok(resolved) => *default_prompt_path = Some(resolved),
+        match resolve_configured_path(root, &path_str, &state.settings) {
+            Ok(resolved) => state.default_prompt_path = Some(resolved),
             Err(err) => {
-                errors.push(ConfigIssue::new(
+                state.errors.push(ConfigIssue::new(
                     ConfigIssueCode::InvalidPrompt,
                     path.to_owned(),
                     None,
@@ -539,34 +2654,132 @@ fn process_config_file(
             .ok(),
     };
 
-    for (name, prompt) in raw.prompt {
-        match build_prompt_spec(root, &name, prompt, &source) {
-            Ok(spec) => {
-                if let Some(previous) = prompts.insert(name.clone(), spec) {
-                    warnings.push(ConfigIssue::new(
-                        ConfigIssueCode::Override,
-                        source.path.clone(),
-                        None,
-                        format!(
-                            "prompt '{name}' overrides definition from {}",
-                            previous.metadata.source.path
-                        ),
-                    ));
-                }
-            }
-            Err(issue) => errors.push(issue),
-        }
-    }
+    collect_prompts(state, raw.prompt, &source);
 
     Ok(())
 }
 
-fn read_config_file(path: &Utf8Path) -> std::result::Result<String, LoadConfigError> {
-    let mut file = fs::File::open(path.as_std_path()).map_err(|source| LoadConfigError::Io {
-        path: path.to_owned(),
-        source,
-    })?;
-    let mut buf = String::new();
+/// Queue the prompts of one file for building. Prompts whose activation conditions fail are
+/// recorded as skipped and kept aside, where they can still serve as a `base`.
+fn collect_prompts(
+    state: &mut LoadState,
+    prompts: IndexMap<String, RawPrompt>,
+    source: &PromptSource,
+) {
+    for (name, prompt) in prompts {
+        let inactive = inactive_reason(&prompt, &state.options.os);
+        let entry = PendingPrompt {
+            prompt,
+            source: source.clone(),
+            settings: state.settings.clone(),
+        };
+        if let Some(reason) = inactive {
+            state.skipped.push(SkippedPrompt {
+                name: name.clone(),
+                reason,
+                source: source.path.clone(),
+            });
+            state.inactive.insert(name, entry);
+            continue;
+        }
+
+        state
+            .prompt_tags
+            .insert(name.clone(), entry.prompt.tags.clone());
+        if let Some(previous) = state.pending.insert(name.clone(), entry) {
+            state.warnings.push(
+                ConfigIssue::new(
+                    ConfigIssueCode::Override,
+                    source.path.clone(),
+                    None,
+                    format!(
+                        "prompt '{name}' overrides definition from {}",
+                        previous.source.path
+                    ),
+                )
+                .for_prompt(&name),
+            );
+        }
+    }
+}
+
+/// A prompt read from a configuration file, built once every file is loaded so that `base`
+/// can name a prompt defined later. `settings` are those in effect where it was declared.
+struct PendingPrompt {
+    prompt: RawPrompt,
+    source: PromptSource,
+    settings: Settings,
+}
+
+/// Resolve `name`'s `base` chain into a single prompt whose unset metadata comes from its
+/// bases. Tags accumulate base-first, vars are merged by name, and other fields set locally win.
+/// A base skipped by its own activation conditions is still inherited from, since those
+/// conditions are never inherited.
+fn inherit_prompt(
+    name: &str,
+    pending: &IndexMap<String, PendingPrompt>,
+    inactive: &IndexMap<String, PendingPrompt>,
+    chain: &mut Vec<String>,
+) -> std::result::Result<RawPrompt, ConfigIssue> {
+    let entry = pending
+        .get(name)
+        .or_else(|| inactive.get(name))
+        .expect("prompt was collected");
+    let mut prompt = entry.prompt.clone();
+    let Some(base) = prompt.base.clone() else {
+        return Ok(prompt);
+    };
+
+    chain.push(name.to_owned());
+    if chain.contains(&base) {
+        chain.push(base);
+        return Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            entry.source.path.clone(),
+            None,
+            format!("prompt base cycle: {}", chain.join(" -> ")),
+        ));
+    }
+    if !pending.contains_key(&base) && !inactive.contains_key(&base) {
+        return Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            entry.source.path.clone(),
+            None,
+            format!("prompt '{name}' has unknown base '{base}'"),
+        ));
+    }
+
+    let parent = inherit_prompt(&base, pending, inactive, chain)?;
+    prompt.inherit_from(parent);
+    Ok(prompt)
+}
+
+fn inactive_reason(prompt: &RawPrompt, os: &str) -> Option<String> {
+    if let Some(expected) = &prompt.when_os
+        && expected != os
+    {
+        return Some(format!("when_os = \"{expected}\" does not match \"{os}\""));
+    }
+
+    if let Some(predicate) = &prompt.when_env {
+        let matched = match predicate.split_once('=') {
+            Some((key, expected)) => std::env::var(key).is_ok_and(|value| value == expected),
+            None => std::env::var_os(predicate).is_some_and(|value| !value.is_empty()),
+        };
+        if !matched {
+            return Some(format!("when_env = \"{predicate}\" is not satisfied"));
+        }
+    }
+
+    None
+}
+
+fn read_config_file(path: &Utf8Path) -> std::result::Result<String, LoadConfigError> {
+    let mut file = fs::File::open(path.as_std_path()).map_err(|source| LoadConfigError::Io {
+        path: path.to_owned(),
+        source,
+    })?;
+    let mut buf = String::new();
     file.read_to_string(&mut buf)
         .map_err(|source| LoadConfigError::Io {
             path: path.to_owned(),
@@ -575,14 +2788,74 @@ fn read_config_file(path: &Utf8Path) -> std::result::Result<String, LoadConfigEr
     Ok(buf)
 }
 
+/// Warn about keys a prompt's kind does not use but that are harmless there, so a misplaced
+/// one is not silently ignored. Keys whose misuse would change the output, such as `data` on
+/// a sequence or `repeat_for_args` on a template, are rejected by [`build_prompt_spec`] instead.
+fn ignored_keys(name: &str, prompt: &RawPrompt, source: &PromptSource) -> Vec<ConfigIssue> {
+    let untemplated = prompt.template.is_none() && prompt.template_inline.is_none();
+    let kind = if prompt.prompts.is_some() {
+        "sequence"
+    } else {
+        "inline"
+    };
+    // Key, and whether this prompt sets it although its kind never reads it.
+    let ignored = [
+        (
+            "prompt_path",
+            prompt.inline.is_some() && prompt.prompt_path.is_some(),
+        ),
+        (
+            "autoescape",
+            untemplated && prompt.autoescape == Some(false),
+        ),
+    ];
+    ignored
+        .into_iter()
+        .filter(|&(_, set)| set)
+        .map(|(key, _)| {
+            ConfigIssue::new(
+                ConfigIssueCode::IgnoredKey,
+                source.path.clone(),
+                None,
+                format!(
+                    "prompt '{name}' sets {key}, which {kind} prompts do not use; it is ignored"
+                ),
+            )
+            .for_prompt(name)
+        })
+        .collect()
+}
+
+/// Warn about vars that are `required` yet have a `default`, which always satisfies them.
+fn required_defaults(name: &str, prompt: &RawPrompt, source: &PromptSource) -> Vec<ConfigIssue> {
+    prompt
+        .vars
+        .iter()
+        .filter(|var| var.required && var.default.is_some())
+        .map(|var| {
+            ConfigIssue::new(
+                ConfigIssueCode::RequiredWithDefault,
+                source.path.clone(),
+                None,
+                format!(
+                    "var '{}' of prompt '{name}' is required but has a default, so it is never missing",
+                    var.name
+                ),
+            )
+            .for_prompt(name)
+        })
+        .collect()
+}
+
 fn build_prompt_spec(
     root: &Utf8Path,
     prompt_name: &str,
-    prompt: RawPrompt,
+    mut prompt: RawPrompt,
     source: &PromptSource,
+    settings: &Settings,
 ) -> std::result::Result<PromptSpec, ConfigIssue> {
-    let prompt_path_override = match prompt.prompt_path {
-        Some(path) => match resolve_path(root, &path) {
+    let prompt_path_override = match prompt.prompt_path.take() {
+        Some(path) => match resolve_configured_path(root, &path, settings) {
             Ok(resolved) => Some(resolved),
             Err(err) => {
                 return Err(ConfigIssue::new(
@@ -596,64 +2869,349 @@ fn build_prompt_spec(
         None => None,
     };
 
-    let kind = match (prompt.prompts, prompt.template) {
-        (Some(files), None) => {
-            if files.is_empty() {
-                return Err(ConfigIssue::new(
-                    ConfigIssueCode::InvalidPrompt,
-                    source.path.clone(),
-                    None,
-                    "prompt sequence cannot be empty",
-                ));
-            }
-            PromptKind::Sequence {
-                files: files.into_iter().map(Utf8PathBuf::from).collect(),
-            }
+    let (kind, sequence) = match (
+        prompt.prompts.take(),
+        prompt.template.take(),
+        prompt.inline.take(),
+        prompt.template_inline.take(),
+    ) {
+        (Some(files), None, None, None) => {
+            let sequence = parse_sequence_entries(prompt_name, files, source)?;
+            let kind = PromptKind::Sequence {
+                files: sequence.files.clone(),
+            };
+            (kind, sequence)
         }
-        (None, Some(template)) => PromptKind::Template {
-            template: Utf8PathBuf::from(template),
-        },
-        (Some(_), Some(_)) => {
+        (None, Some(template), None, None) => (
+            PromptKind::Template {
+                template: Utf8PathBuf::from(template),
+            },
+            SequenceEntries::default(),
+        ),
+        (None, None, Some(body), None) => (PromptKind::Inline { body }, SequenceEntries::default()),
+        (None, None, None, Some(body)) => (
+            PromptKind::InlineTemplate { body },
+            SequenceEntries::default(),
+        ),
+        (None, None, None, None) => {
             return Err(ConfigIssue::new(
                 ConfigIssueCode::InvalidPrompt,
                 source.path.clone(),
                 None,
-                "prompts and template are exclusive options",
+                "prompt must define 'prompts', 'template', 'inline', or 'template_inline'",
             ));
         }
-        (None, None) => {
+        _ => {
             return Err(ConfigIssue::new(
                 ConfigIssueCode::InvalidPrompt,
                 source.path.clone(),
                 None,
-                "prompt must define either 'prompts' or 'template'",
+                "prompts, template, inline, and template_inline are exclusive options",
             ));
         }
     };
 
-    let vars = parse_prompt_vars(prompt_name, prompt.vars, source)?;
+    let vars = parse_prompt_vars(prompt_name, std::mem::take(&mut prompt.vars), source)?;
+    check_post_process(
+        prompt_name,
+        prompt.post_process.as_deref(),
+        prompt.post_process_timeout,
+        source,
+    )?;
+    let data_format = parse_data_format(prompt_name, prompt.data_format.as_deref(), &kind, source)?;
+    let escape = parse_escape(prompt_name, prompt.escape.as_deref(), source)?;
+    let working_dir = parse_working_dir(
+        root,
+        prompt_name,
+        prompt.working_dir.take(),
+        &kind,
+        source,
+        settings,
+    )?;
+    check_kind_keys(prompt_name, &prompt, &kind, data_format, source)?;
 
     let metadata = PromptMetadata {
         description: prompt.description,
         tags: prompt.tags,
         vars,
         stdin_supported: prompt.stdin_supported,
+        post_process: prompt.post_process,
+        post_process_timeout: prompt.post_process_timeout,
+        escape,
+        repeat_for_args: prompt.repeat_for_args.map(Utf8PathBuf::from),
+        dedent: prompt.dedent.unwrap_or(false),
+        autoescape: prompt.autoescape.unwrap_or(false),
+        replace: prompt.replace,
+        presets: prompt.presets,
+        data: prompt.data.map(Utf8PathBuf::from),
+        data_format,
+        pins: sequence.pins,
+        roles: sequence.roles,
         source: source.clone(),
     };
 
     Ok(PromptSpec {
         prompt_path_override,
+        working_dir,
         kind,
         metadata,
     })
 }
 
+/// A sequence prompt's entries split into fragment paths and the pins and roles set on them,
+/// keyed by entry index.
+#[derive(Default)]
+struct SequenceEntries {
+    files: Vec<Utf8PathBuf>,
+    pins: BTreeMap<usize, String>,
+    roles: BTreeMap<usize, MessageRole>,
+}
+
+fn parse_sequence_entries(
+    prompt_name: &str,
+    entries: Vec<RawSequenceEntry>,
+    source: &PromptSource,
+) -> std::result::Result<SequenceEntries, ConfigIssue> {
+    if entries.is_empty() {
+        return Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            "prompt sequence cannot be empty",
+        ));
+    }
+    let mut parsed = SequenceEntries::default();
+    for (index, entry) in entries.into_iter().enumerate() {
+        let RawSequenceTable { file, sha256, role } = match entry {
+            RawSequenceEntry::Path(file) => RawSequenceTable {
+                file,
+                sha256: None,
+                role: None,
+            },
+            RawSequenceEntry::Table(table) => table,
+        };
+        if let Some(role) = role {
+            let Some(role) = MessageRole::parse(&role) else {
+                return Err(ConfigIssue::new(
+                    ConfigIssueCode::InvalidPrompt,
+                    source.path.clone(),
+                    None,
+                    format!(
+                        "prompt '{prompt_name}' gives '{file}' unknown role '{role}'; expected system, user, or assistant"
+                    ),
+                ));
+            };
+            parsed.roles.insert(index, role);
+        }
+        if let Some(sha256) = sha256 {
+            if file.ends_with('/') {
+                return Err(ConfigIssue::new(
+                    ConfigIssueCode::InvalidPrompt,
+                    source.path.clone(),
+                    None,
+                    format!(
+                        "prompt '{prompt_name}' pins directory entry '{file}'; sha256 applies to a single file"
+                    ),
+                ));
+            }
+            let digest = sha256.to_ascii_lowercase();
+            if digest.len() != 64 || !digest.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+                return Err(ConfigIssue::new(
+                    ConfigIssueCode::InvalidPrompt,
+                    source.path.clone(),
+                    None,
+                    format!(
+                        "prompt '{prompt_name}' pins '{file}' to '{sha256}', which is not a SHA-256 hex digest"
+                    ),
+                ));
+            }
+            parsed.pins.insert(index, digest);
+        }
+        parsed.files.push(Utf8PathBuf::from(file));
+    }
+    Ok(parsed)
+}
+
+fn check_post_process(
+    prompt_name: &str,
+    command: Option<&str>,
+    timeout: Option<u64>,
+    source: &PromptSource,
+) -> std::result::Result<(), ConfigIssue> {
+    if command.is_some_and(|command| command.trim().is_empty()) {
+        return Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            "post_process must name a command",
+        ));
+    }
+
+    match timeout {
+        Some(0) => Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            "post_process_timeout must be at least 1 second",
+        )),
+        Some(_) if command.is_none() => Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            format!("prompt '{prompt_name}' sets post_process_timeout without post_process"),
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn parse_data_format(
+    prompt_name: &str,
+    raw: Option<&str>,
+    kind: &PromptKind,
+    source: &PromptSource,
+) -> std::result::Result<Option<DataFormat>, ConfigIssue> {
+    match raw {
+        Some(_)
+            if !matches!(
+                kind,
+                PromptKind::Template { .. } | PromptKind::InlineTemplate { .. }
+            ) =>
+        {
+            Err(ConfigIssue::new(
+                ConfigIssueCode::InvalidPrompt,
+                source.path.clone(),
+                None,
+                format!(
+                    "prompt '{prompt_name}' sets data_format, which only template prompts support"
+                ),
+            ))
+        }
+        Some(raw) => DataFormat::parse(raw).map(Some).ok_or_else(|| {
+            ConfigIssue::new(
+                ConfigIssueCode::InvalidPrompt,
+                source.path.clone(),
+                None,
+                format!(
+                    "unknown data_format '{raw}' for prompt '{prompt_name}'; expected json, toml, or yaml"
+                ),
+            )
+        }),
+        None => Ok(None),
+    }
+}
+
+fn parse_escape(
+    prompt_name: &str,
+    raw: Option<&str>,
+    source: &PromptSource,
+) -> std::result::Result<Option<OutputEscape>, ConfigIssue> {
+    raw.map(|raw| {
+        OutputEscape::parse(raw).ok_or_else(|| {
+            ConfigIssue::new(
+                ConfigIssueCode::InvalidPrompt,
+                source.path.clone(),
+                None,
+                format!("unknown escape '{raw}' for prompt '{prompt_name}'; expected none, json, or shell"),
+            )
+        })
+    })
+    .transpose()
+}
+
+fn parse_working_dir(
+    root: &Utf8Path,
+    prompt_name: &str,
+    raw: Option<String>,
+    kind: &PromptKind,
+    source: &PromptSource,
+    settings: &Settings,
+) -> std::result::Result<Option<Utf8PathBuf>, ConfigIssue> {
+    match raw {
+        Some(_) if !matches!(kind, PromptKind::Sequence { .. }) => Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            format!("prompt '{prompt_name}' sets working_dir, which only sequence prompts support"),
+        )),
+        Some(path) => resolve_configured_path(root, &path, settings)
+            .map(Some)
+            .map_err(|err| {
+                ConfigIssue::new(
+                    ConfigIssueCode::InvalidPrompt,
+                    source.path.clone(),
+                    None,
+                    format!("prompt '{prompt_name}' has invalid working_dir '{path}': {err}"),
+                )
+            }),
+        None => Ok(None),
+    }
+}
+
+/// Reject keys that the prompt's kind does not support: `data`, `presets`, and
+/// `autoescape = true` outside template prompts, and `repeat_for_args` outside sequences.
+/// Also rejects an empty `replace` key and a `data` file whose format cannot be inferred.
+fn check_kind_keys(
+    prompt_name: &str,
+    prompt: &RawPrompt,
+    kind: &PromptKind,
+    data_format: Option<DataFormat>,
+    source: &PromptSource,
+) -> std::result::Result<(), ConfigIssue> {
+    let untemplated = matches!(
+        kind,
+        PromptKind::Sequence { .. } | PromptKind::Inline { .. }
+    );
+    let invalid = |message: String| {
+        Err(ConfigIssue::new(
+            ConfigIssueCode::InvalidPrompt,
+            source.path.clone(),
+            None,
+            message,
+        ))
+    };
+
+    if let Some(data) = &prompt.data {
+        if untemplated {
+            return invalid(format!(
+                "prompt '{prompt_name}' sets data, which only template prompts support"
+            ));
+        }
+        if data_format.is_none() && StructuredData::from_path(Utf8PathBuf::from(data)).is_none() {
+            return invalid(format!(
+                "data file '{data}' for prompt '{prompt_name}' must be JSON, TOML, or YAML"
+            ));
+        }
+    }
+    if !prompt.presets.is_empty() && untemplated {
+        return invalid(format!(
+            "prompt '{prompt_name}' sets presets, which only template prompts support"
+        ));
+    }
+    if prompt.replace.contains_key("") {
+        return invalid(format!(
+            "prompt '{prompt_name}' has an empty key in replace"
+        ));
+    }
+    if prompt.autoescape == Some(true) && untemplated {
+        return invalid(format!(
+            "prompt '{prompt_name}' sets autoescape, which only template prompts support"
+        ));
+    }
+    if prompt.repeat_for_args.is_some() && !matches!(kind, PromptKind::Sequence { .. }) {
+        return invalid(format!(
+            "prompt '{prompt_name}' sets repeat_for_args, which only sequence prompts support"
+        ));
+    }
+    Ok(())
+}
+
 fn parse_prompt_vars(
     prompt_name: &str,
     vars: Vec<RawPromptVar>,
     source: &PromptSource,
 ) -> std::result::Result<Vec<PromptVariable>, ConfigIssue> {
     let mut seen: HashSet<String> = HashSet::new();
+    let mut positions: BTreeMap<usize, String> = BTreeMap::new();
     let mut parsed: Vec<PromptVariable> = Vec::with_capacity(vars.len());
 
     for raw in vars {
@@ -666,185 +3224,1033 @@ fn parse_prompt_vars(
             ));
         }
 
-        let raw_kind = raw.kind.unwrap_or_else(|| "string".to_owned());
-        let kind = parse_var_kind(&raw_kind).ok_or_else(|| {
-            ConfigIssue::new(
-                ConfigIssueCode::InvalidPrompt,
-                source.path.clone(),
-                None,
-                format!("unknown var type '{raw_kind}' for prompt '{prompt_name}'"),
-            )
-        })?;
+        let raw_kind = raw.kind.unwrap_or_else(|| "string".to_owned());
+        let kind = parse_var_kind(&raw_kind).ok_or_else(|| {
+            ConfigIssue::new(
+                ConfigIssueCode::InvalidPrompt,
+                source.path.clone(),
+                None,
+                format!("unknown var type '{raw_kind}' for prompt '{prompt_name}'"),
+            )
+        })?;
+
+        if let Some(position) = raw.position {
+            let invalid = |message: String| {
+                ConfigIssue::new(
+                    ConfigIssueCode::InvalidPrompt,
+                    source.path.clone(),
+                    None,
+                    message,
+                )
+            };
+            if raw.default.is_some() {
+                return Err(invalid(format!(
+                    "var '{}' of prompt '{prompt_name}' sets both position and default; \
+                     positional arguments have no default",
+                    raw.name
+                )));
+            }
+            if let Some(other) = positions.insert(position, raw.name.clone()) {
+                return Err(invalid(format!(
+                    "vars '{other}' and '{}' of prompt '{prompt_name}' both describe position {position}",
+                    raw.name
+                )));
+            }
+        }
+
+        parsed.push(PromptVariable {
+            name: raw.name,
+            required: raw.required,
+            kind,
+            description: raw.description,
+            required_if: raw.required_if,
+            default: raw.default,
+            position: raw.position,
+        });
+    }
+
+    for var in &parsed {
+        if let Some(condition) = &var.required_if
+            && !seen.contains(condition)
+        {
+            return Err(ConfigIssue::new(
+                ConfigIssueCode::InvalidPrompt,
+                source.path.clone(),
+                None,
+                format!(
+                    "var '{}' of prompt '{prompt_name}' is required_if undeclared var '{condition}'",
+                    var.name
+                ),
+            ));
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn parse_var_kind(raw: &str) -> Option<PromptVariableKind> {
+    match raw {
+        "string" => Some(PromptVariableKind::String),
+        "path" => Some(PromptVariableKind::Path),
+        "number" => Some(PromptVariableKind::Number),
+        "boolean" => Some(PromptVariableKind::Boolean),
+        _ => None,
+    }
+}
+
+fn resolve_configured_path(
+    root: &Utf8Path,
+    path: &str,
+    settings: &Settings,
+) -> Result<Utf8PathBuf> {
+    if settings.require_absolute_paths
+        && !path.starts_with("~/")
+        && !Utf8Path::new(path).is_absolute()
+    {
+        bail!("relative paths are not allowed when require_absolute_paths is enabled");
+    }
+    resolve_path(root, path)
+}
+
+fn resolve_path(root: &Utf8Path, path: &str) -> Result<Utf8PathBuf> {
+    if let Some(stripped) = path.strip_prefix("~/") {
+        let base_dirs =
+            BaseDirs::new().ok_or_else(|| anyhow!("cannot resolve '~' without home directory"))?;
+        let mut buf = Utf8PathBuf::from_path_buf(base_dirs.home_dir().to_path_buf())
+            .map_err(|_| anyhow!("home directory is not valid UTF-8"))?;
+        buf.push(stripped);
+        Ok(buf)
+    } else {
+        let candidate = Utf8PathBuf::from(path);
+        if candidate.is_absolute() {
+            Ok(candidate)
+        } else {
+            Ok(root.join(candidate))
+        }
+    }
+}
+
+/// Inline `{{> path }}` markers with the referenced file, resolved against `base`.
+///
+/// `{{{>` escapes a marker: it is rewritten to `{{>`, which placeholder substitution then
+/// renders as a literal `{>`. A single trailing newline is dropped from included content so a marker on its own
+/// line does not introduce a blank line.
+fn expand_transclusions(
+    source: &FragmentReader,
+    content: &str,
+    base: &Utf8Path,
+    depth: usize,
+) -> Result<String> {
+    let mut output = String::with_capacity(content.len());
+    let mut rest = content;
+
+    while let Some(start) = rest.find("{{>") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 3..];
+        if rest[..start].ends_with('{') {
+            output.push_str("{>");
+            rest = after;
+            continue;
+        }
+        let end = after
+            .find("}}")
+            .ok_or_else(|| anyhow!("unterminated transclusion marker"))?;
+        let target = after[..end].trim();
+        if target.is_empty() {
+            bail!("transclusion marker is missing a path");
+        }
+        if depth >= MAX_TRANSCLUSION_DEPTH {
+            bail!(
+                "transclusion of '{target}' exceeds the maximum depth of {MAX_TRANSCLUSION_DEPTH}"
+            );
+        }
+
+        let included = source
+            .read(&resolve_fragment(base, Utf8Path::new(target)))
+            .with_context(|| format!("failed to transclude '{target}'"))?;
+        let expanded = expand_transclusions(source, &included, base, depth + 1)?;
+        output.push_str(expanded.strip_suffix('\n').unwrap_or(&expanded));
+        rest = &after[end + 2..];
+    }
+
+    output.push_str(rest);
+    Ok(output)
+}
+
+/// A run of literal text or a positional `{N}` reference within a fragment.
+enum Segment {
+    Literal(String),
+    Placeholder(usize),
+    Named(String),
+}
+
+fn parse_placeholders(template: &str) -> Result<Vec<Segment>> {
+    let mut segments = Vec::new();
+    let mut literal = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '{' => match chars.peek() {
+                Some('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                Some(first) if first.is_ascii_alphabetic() || *first == '_' => {
+                    let mut name = String::new();
+                    while let Some(peek) = chars.peek() {
+                        if peek.is_ascii_alphanumeric() || *peek == '_' {
+                            name.push(*peek);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+
+                    match chars.next() {
+                        Some('}') => {}
+                        _ => bail!("unterminated placeholder '{{{name}'"),
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Named(name));
+                }
+                Some(_) => {
+                    let mut digits = String::new();
+                    while let Some(peek) = chars.peek() {
+                        if peek.is_ascii_digit() {
+                            digits.push(*peek);
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+
+                    if digits.is_empty() {
+                        bail!("empty placeholder braces are not allowed");
+                    }
+
+                    let index = digits
+                        .parse::<usize>()
+                        .map_err(|_| anyhow!("invalid placeholder index '{digits}'"))?;
+
+                    match chars.next() {
+                        Some('}') => {}
+                        _ => bail!("unterminated placeholder '{{{digits}'"),
+                    }
+
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(Segment::Placeholder(index));
+                }
+                None => bail!("unterminated placeholder at end of template"),
+            },
+            '}' => match chars.peek() {
+                Some('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                _ => bail!("unmatched closing brace '}}'"),
+            },
+            other => literal.push(other),
+        }
+    }
+
+    if !literal.is_empty() {
+        segments.push(Segment::Literal(literal));
+    }
+    Ok(segments)
+}
+
+/// Fill the `{N}` placeholders in `template` from `args` with the same rules as a sequence
+/// render: `{{` and `}}` produce literal braces, and a placeholder without a value is an
+/// error.
+///
+/// ```
+/// let filled = prompt_assembler::substitute("Review {0} for {{bugs}}", &["main.rs".to_owned()])?;
+/// assert_eq!(filled, "Review main.rs for {bugs}");
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+///
+/// # Errors
+/// Returns an error when the template is malformed or names a placeholder with no value.
+pub fn substitute(template: &str, args: &[String]) -> Result<String> {
+    substitute_named(template, args, &BTreeMap::new())
+}
+
+/// Like [`substitute`], also filling `{name}` placeholders from `named`.
+///
+/// # Errors
+/// Returns an error when the template is malformed or names a placeholder with no value.
+pub fn substitute_named(
+    template: &str,
+    args: &[String],
+    named: &BTreeMap<String, String>,
+) -> Result<String> {
+    substitute_placeholders(template, args, named, &[], &|message| Err(anyhow!(message)))
+}
+
+/// Fill `{N}` from `args` and `{name}` from `named`. A declared var that is not supplied
+/// renders empty unless it is `required`.
+fn substitute_placeholders(
+    template: &str,
+    args: &[String],
+    named: &BTreeMap<String, String>,
+    declared: &[PromptVariable],
+    report: &dyn Fn(String) -> Result<()>,
+) -> Result<String> {
+    let mut output = String::with_capacity(template.len());
+    for segment in parse_placeholders(template)? {
+        match segment {
+            Segment::Literal(text) => output.push_str(&text),
+            Segment::Placeholder(index) => match args.get(index) {
+                Some(value) => output.push_str(value),
+                None => report(format!("missing argument for placeholder {{{index}}}"))?,
+            },
+            Segment::Named(name) => {
+                if let Some(value) = named.get(&name).or_else(|| {
+                    declared
+                        .iter()
+                        .find(|var| var.name == name)
+                        .and_then(|var| var.default.as_ref())
+                }) {
+                    output.push_str(value);
+                    continue;
+                }
+                let condition_met = |condition: &str| {
+                    named
+                        .get(condition)
+                        .is_some_and(|value| is_truthy_text(value))
+                };
+                match declared.iter().find(|var| var.name == name) {
+                    Some(var) if var.required => report(format!(
+                        "required var '{name}' was not supplied; pass --set {name}=VALUE"
+                    ))?,
+                    Some(PromptVariable {
+                        required_if: Some(condition),
+                        ..
+                    }) if condition_met(condition) => report(format!(
+                        "var '{name}' is required when '{condition}' is set; \
+                         pass --set {name}=VALUE"
+                    ))?,
+                    Some(_) => {}
+                    None => report(format!(
+                        "unknown placeholder '{{{name}}}'; declare it in vars or pass --set {name}=VALUE"
+                    ))?,
+                }
+            }
+        }
+    }
+    Ok(output)
+}
+
+/// Pair each var that describes a positional argument with its position, in position order.
+/// Vars with an explicit `position` keep it. Without `usage`, no other var is bound; with it,
+/// each var that no `{name}` placeholder, `default`, or `required_if` refers to takes the next
+/// position not claimed explicitly, in declaration order.
+fn bind_positions<'a>(
+    vars: &'a [PromptVariable],
+    usage: Option<&PlaceholderUsage>,
+) -> Vec<(usize, &'a PromptVariable)> {
+    let claimed: BTreeSet<usize> = vars.iter().filter_map(|var| var.position).collect();
+    let mut free = (0..).filter(|position| !claimed.contains(position));
+    let mut bound: Vec<(usize, &PromptVariable)> = vars
+        .iter()
+        .filter_map(|var| {
+            if let Some(position) = var.position {
+                return Some((position, var));
+            }
+            let usage = usage?;
+            let named = usage.names.contains(&var.name)
+                || var.default.is_some()
+                || vars
+                    .iter()
+                    .any(|other| other.required_if.as_deref() == Some(var.name.as_str()));
+            if named {
+                return None;
+            }
+            free.next().map(|position| (position, var))
+        })
+        .collect();
+    bound.sort_by_key(|(position, _)| *position);
+    bound
+}
+
+/// Check positional arguments against the vars `bound` to positions: there must be an
+/// argument for every bound position, and each must read as its var's kind. `arity`, absent
+/// for prompts with `repeat_for_args`, counts the placeholders used so surplus arguments are
+/// reported too.
+fn check_var_kinds(
+    prompt: &str,
+    bound: &[(usize, &PromptVariable)],
+    args: &[String],
+    arity: Option<usize>,
+    report: &dyn Fn(String) -> Result<()>,
+) -> Result<()> {
+    let Some(expected) = bound.last().map(|(position, _)| position + 1) else {
+        return Ok(());
+    };
+    if args.len() < expected {
+        report(format!(
+            "prompt '{prompt}' expects {expected} arguments but got {}",
+            args.len()
+        ))?;
+    } else if let Some(arity) = arity
+        && args.len() > expected
+    {
+        let limit = arity.max(expected);
+        if args.len() > limit {
+            report(format!(
+                "prompt '{prompt}' takes {limit} arguments but got {}",
+                args.len()
+            ))?;
+        }
+    }
+
+    for (position, var) in bound {
+        let Some(value) = args.get(*position) else {
+            continue;
+        };
+        if !value_matches_kind(&var.kind, value) {
+            report(format!(
+                "var '{}' ({}) expected but got '{value}'",
+                var.name,
+                var.kind.as_str()
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether `value` reads as `kind`: a finite number, a `true`/`false`-like word, or a
+/// non-empty path without NUL bytes.
+fn value_matches_kind(kind: &PromptVariableKind, value: &str) -> bool {
+    match kind {
+        PromptVariableKind::String => true,
+        PromptVariableKind::Path => !value.is_empty() && !value.contains('\0'),
+        PromptVariableKind::Number => value.trim().parse::<f64>().is_ok_and(f64::is_finite),
+        PromptVariableKind::Boolean => matches!(
+            value.trim().to_ascii_lowercase().as_str(),
+            "true" | "false" | "yes" | "no" | "1" | "0"
+        ),
+    }
+}
+
+/// Whether a `--set` value counts as set for `required_if`: anything but empty, `false`,
+/// `0`, or `no`.
+fn is_truthy_text(value: &str) -> bool {
+    !matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "" | "false" | "0" | "no"
+    )
+}
+
+/// Jinja truthiness of a JSON value, as `required_if` applies it to template data.
+#[must_use]
+pub fn is_truthy_json(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::Bool(flag) => *flag,
+        serde_json::Value::Number(number) => number.as_f64().is_some_and(|n| n != 0.0),
+        serde_json::Value::String(text) => !text.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        serde_json::Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// Fail when a var that is `required`, or whose `required_if` var is truthy, is missing.
+fn check_required_vars(
+    prompt_name: &str,
+    vars: &[PromptVariable],
+    context: &serde_json::Map<String, serde_json::Value>,
+    report: &dyn Fn(String) -> Result<()>,
+) -> Result<()> {
+    for var in vars {
+        if context.contains_key(&var.name) {
+            continue;
+        }
+        if var.required {
+            report(format!(
+                "prompt '{prompt_name}' requires var '{}'",
+                var.name
+            ))?;
+        } else if let Some(condition) = &var.required_if
+            && context.get(condition).is_some_and(is_truthy_json)
+        {
+            report(format!(
+                "prompt '{prompt_name}' requires var '{}' because '{condition}' is set",
+                var.name
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// Build the template context from the data file, exposing `args` as `_args`.
+fn template_context(
+    prompt_name: &str,
+    data: Option<&StructuredData>,
+    args: &[String],
+) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let mut map = match data {
+        Some(data) => {
+            let data_path = data.path();
+            let data_value = load_structured_data(data).with_context(|| {
+                format!("failed to load data file {data_path} for prompt '{prompt_name}'")
+            })?;
+            match data_value {
+                serde_json::Value::Object(obj) => obj,
+                other => {
+                    let mut obj = serde_json::Map::new();
+                    obj.insert("value".into(), other);
+                    obj
+                }
+            }
+        }
+        None => serde_json::Map::new(),
+    };
+
+    if !args.is_empty() {
+        let positional = serde_json::Value::Array(
+            args.iter()
+                .cloned()
+                .map(serde_json::Value::String)
+                .collect(),
+        );
+        map.insert("_args".into(), positional);
+    }
+    Ok(map)
+}
+
+/// Levenshtein distance between `a` and `b`, counted in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// A hint listing `suggestions` for an unknown name, or nothing when there are none.
+fn did_you_mean(suggestions: &[&str]) -> String {
+    match suggestions {
+        [] => String::new(),
+        [only] => format!(" (did you mean '{only}'?)"),
+        many => {
+            let quoted: Vec<String> = many.iter().map(|name| format!("'{name}'")).collect();
+            format!(" (did you mean: {}?)", quoted.join(", "))
+        }
+    }
+}
 
-        parsed.push(PromptVariable {
-            name: raw.name,
-            required: raw.required,
-            kind,
-            description: raw.description,
-        });
+/// Load the `context_file` setting, which must hold a table of context entries.
+fn shared_context(path: &Utf8Path) -> Result<serde_json::Map<String, serde_json::Value>> {
+    let data = StructuredData::from_path(path.to_owned())
+        .ok_or_else(|| anyhow!("context_file {path} must be JSON, TOML, or YAML"))?;
+    let value = load_structured_data(&data)
+        .with_context(|| format!("failed to load context_file {path}"))?;
+    match value {
+        serde_json::Value::Object(map) => Ok(map),
+        _ => bail!("context_file {path} must hold a table of context entries"),
     }
+}
 
-    Ok(parsed)
+/// Where a render writes its output, tracking what has been written so fragments can be
+/// joined without reading the output back.
+struct Emitter<'w> {
+    out: &'w mut dyn Write,
+    len: usize,
+    ends_with_newline: bool,
 }
 
-fn parse_var_kind(raw: &str) -> Option<PromptVariableKind> {
-    match raw {
-        "string" => Some(PromptVariableKind::String),
-        "path" => Some(PromptVariableKind::Path),
-        "number" => Some(PromptVariableKind::Number),
-        "boolean" => Some(PromptVariableKind::Boolean),
-        _ => None,
+impl<'w> Emitter<'w> {
+    fn new(out: &'w mut dyn Write) -> Self {
+        Self {
+            out,
+            len: 0,
+            ends_with_newline: false,
+        }
     }
-}
 
-fn resolve_path(root: &Utf8Path, path: &str) -> Result<Utf8PathBuf> {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        let base_dirs =
-            BaseDirs::new().ok_or_else(|| anyhow!("cannot resolve '~' without home directory"))?;
-        let mut buf = Utf8PathBuf::from_path_buf(base_dirs.home_dir().to_path_buf())
-            .map_err(|_| anyhow!("home directory is not valid UTF-8"))?;
-        buf.push(stripped);
-        Ok(buf)
-    } else {
-        let candidate = Utf8PathBuf::from(path);
-        if candidate.is_absolute() {
-            Ok(candidate)
-        } else {
-            Ok(root.join(candidate))
+    fn push(&mut self, text: &str) -> Result<()> {
+        if text.is_empty() {
+            return Ok(());
         }
+        self.out
+            .write_all(text.as_bytes())
+            .context("failed to write rendered output")?;
+        self.len += text.len();
+        self.ends_with_newline = text.ends_with('\n');
+        Ok(())
+    }
+
+    /// Bytes written so far.
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn ends_with_newline(&self) -> bool {
+        self.ends_with_newline
     }
 }
 
-fn read_utf8(path: &Utf8Path) -> Result<String> {
-    let mut file =
-        fs::File::open(path.as_std_path()).with_context(|| format!("failed to open {path}"))?;
-    let mut buf = String::new();
-    file.read_to_string(&mut buf)
-        .with_context(|| format!("failed to read {path}"))?;
-    Ok(buf)
+/// Cut `output` into messages at each `(offset, role)` mark. Text before the first mark is a
+/// `user` message, consecutive marks with the same role share a message, and empty messages
+/// are dropped.
+fn split_messages<'a>(
+    output: &'a str,
+    marks: &[(usize, MessageRole)],
+) -> Vec<(MessageRole, &'a str)> {
+    let mut spans: Vec<(MessageRole, usize, usize)> = Vec::new();
+    let mut boundaries = vec![(0, MessageRole::User)];
+    boundaries.extend_from_slice(marks);
+    for (index, &(start, role)) in boundaries.iter().enumerate() {
+        let end = boundaries
+            .get(index + 1)
+            .map_or(output.len(), |(end, _)| *end);
+        match spans.last_mut() {
+            Some((last_role, _, last_end)) if *last_role == role => *last_end = end,
+            _ if start == end => {}
+            _ => spans.push((role, start, end)),
+        }
+    }
+    spans
+        .into_iter()
+        .map(|(role, start, end)| (role, &output[start..end]))
+        .collect()
 }
 
-fn substitute_placeholders(template: &str, args: &[String]) -> Result<String> {
-    let mut output = String::with_capacity(template.len());
-    let mut chars = template.chars().peekable();
+/// What a prompt's placeholders refer to: one more than the highest `{N}` index, and the
+/// name of every `{name}`.
+#[derive(Default)]
+struct PlaceholderUsage {
+    arity: usize,
+    names: BTreeSet<String>,
+}
 
-    while let Some(ch) = chars.next() {
-        match ch {
-            '{' => match chars.peek() {
-                Some('{') => {
-                    chars.next();
-                    output.push('{');
+impl PlaceholderUsage {
+    fn add(&mut self, template: &str) -> Result<()> {
+        for segment in parse_placeholders(template)? {
+            match segment {
+                Segment::Placeholder(index) => self.arity = self.arity.max(index + 1),
+                Segment::Named(name) => {
+                    self.names.insert(name);
                 }
-                Some(_) => {
-                    let mut digits = String::new();
-                    while let Some(peek) = chars.peek() {
-                        if peek.is_ascii_digit() {
-                            digits.push(*peek);
-                            chars.next();
-                        } else {
-                            break;
-                        }
-                    }
+                Segment::Literal(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
 
-                    if digits.is_empty() {
-                        bail!("empty placeholder braces are not allowed");
-                    }
+/// One more than the highest `{N}` index in `template`, or zero without placeholders.
+fn placeholder_arity(template: &str) -> Result<usize> {
+    Ok(parse_placeholders(template)?
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Placeholder(index) => Some(index + 1),
+            Segment::Literal(_) | Segment::Named(_) => None,
+        })
+        .max()
+        .unwrap_or(0))
+}
 
-                    let index = digits
-                        .parse::<usize>()
-                        .map_err(|_| anyhow!("invalid placeholder index '{digits}'"))?;
+/// What every part of a single render needs: the prompt being rendered, its arguments, and
+/// where diagnostics go. `report` fails the render unless `--force` turned it into a warning.
+#[derive(Clone, Copy)]
+struct RenderJob<'a> {
+    reader: &'a FragmentReader,
+    name: &'a str,
+    spec: &'a PromptSpec,
+    args: &'a [String],
+    options: &'a RenderOptions,
+    report: &'a dyn Fn(String) -> Result<()>,
+}
 
-                    match chars.next() {
-                        Some('}') => {}
-                        _ => bail!("unterminated placeholder '{{{digits}'"),
-                    }
+/// Render an inline prompt's body with its placeholders filled.
+fn render_inline(job: &RenderJob<'_>, body: &str, out: &mut Emitter<'_>) -> Result<()> {
+    let started = Instant::now();
+    let mut rendered = substitute_placeholders(
+        body,
+        job.args,
+        &job.options.vars,
+        &job.spec.metadata.vars,
+        job.report,
+    )?;
+    if !job.options.layout.exact && !rendered.ends_with('\n') {
+        rendered.push('\n');
+    }
+    if job.options.trace {
+        job.reader.record_timing(FragmentTiming {
+            fragment: "inline".to_owned(),
+            read: Duration::ZERO,
+            render: started.elapsed(),
+        });
+    }
+    out.push(&rendered)
+}
 
-                    if index > 9 {
-                        bail!("positional placeholders support up to 9 arguments");
-                    }
-                    let value = args
-                        .get(index)
-                        .ok_or_else(|| anyhow!("missing argument for placeholder {{{index}}}"))?;
-                    output.push_str(value);
-                }
-                None => bail!("unterminated placeholder at end of template"),
-            },
-            '}' => match chars.peek() {
-                Some('}') => {
-                    chars.next();
-                    output.push('}');
-                }
-                _ => bail!("unmatched closing brace '}}'"),
-            },
-            other => output.push(other),
+/// Render a sequence prompt's `repeat_for_args` body once per argument beyond the first
+/// `arity`, with `{item}` bound to that argument.
+fn render_repeated(
+    job: &RenderJob<'_>,
+    base: &Utf8Path,
+    body: &Utf8Path,
+    arity: usize,
+    out: &mut Emitter<'_>,
+) -> Result<()> {
+    let RenderJob {
+        reader,
+        name,
+        spec,
+        args,
+        options,
+        report,
+    } = *job;
+    let started = Instant::now();
+    let content = reader
+        .read(&resolve_fragment(base, body))
+        .with_context(|| format!("failed to read repeat_for_args '{body}' for prompt '{name}'"))?;
+    let content = expand_transclusions(reader, &content, base, 0)?;
+    let read = started.elapsed();
+    let mut named = options.vars.clone();
+    for item in args.iter().skip(arity) {
+        named.insert("item".to_owned(), item.clone());
+        let substituted =
+            substitute_placeholders(&content, args, &named, &spec.metadata.vars, report)?;
+        out.push(&substituted)?;
+        if !options.layout.exact && !out.ends_with_newline() {
+            out.push("\n")?;
         }
     }
+    if options.trace {
+        reader.record_timing(FragmentTiming {
+            fragment: body.to_string(),
+            read,
+            render: started.elapsed().saturating_sub(read),
+        });
+    }
+    Ok(())
+}
 
-    Ok(output)
+/// Where a template prompt's main template comes from.
+enum TemplateSource<'a> {
+    File(&'a Utf8Path),
+    Inline(&'a str),
 }
 
 fn render_template(
+    source: &FragmentReader,
     prompt_name: &str,
     base: &Utf8Path,
-    template: &Utf8Path,
-    data: &StructuredData,
-    args: &[String],
+    template: &TemplateSource<'_>,
+    map: serde_json::Map<String, serde_json::Value>,
+    autoescape: bool,
+    settings: &Settings,
 ) -> Result<String> {
     let mut env = Environment::new();
     env.set_keep_trailing_newline(true);
-    env.set_loader(minijinja::path_loader(base.as_std_path()));
-
-    let template_name = template.as_str();
-    let template_ref = env
-        .get_template(template_name)
-        .with_context(|| format!("prompt '{prompt_name}' template '{template}' not found"))?;
+    // minijinja would otherwise pick escaping from the template name, e.g. `.html`.
+    env.set_auto_escape_callback(move |_| {
+        if autoescape {
+            AutoEscape::Html
+        } else {
+            AutoEscape::None
+        }
+    });
+    env.set_loader(fragment_loader(source.clone(), base.to_owned()));
 
-    let data_path = data.path();
-    let data_value = load_structured_data(data).with_context(|| {
-        format!("failed to load data file {data_path} for prompt '{prompt_name}'")
-    })?;
-    let mut map = match data_value {
-        serde_json::Value::Object(obj) => obj,
-        other => {
-            let mut obj = serde_json::Map::new();
-            obj.insert("value".into(), other);
-            obj
+    let imports = macro_imports(source.source(), base, &settings.macros)?;
+    let inline_name = format!("<prompt.{prompt_name}>");
+    let (template_name, body) = match *template {
+        TemplateSource::File(path) if imports.is_empty() => (path.as_str(), None),
+        TemplateSource::File(path) => {
+            let full_path = base.join(path);
+            if !source.exists(&full_path) {
+                bail!("prompt '{prompt_name}' template '{path}' not found");
+            }
+            (path.as_str(), Some(source.read(&full_path)?))
+        }
+        TemplateSource::Inline(body) => (inline_name.as_str(), Some(body.to_owned())),
+    };
+    let body = body.map(|body| format!("{imports}{body}"));
+    let loaded = match &body {
+        Some(body) => env.template_from_named_str(template_name, body),
+        None => env.get_template(template_name),
+    };
+    let template_ref = match loaded {
+        Ok(template_ref) => template_ref,
+        Err(err) if err.kind() == minijinja::ErrorKind::TemplateNotFound => {
+            return Err(anyhow::Error::new(err).context(format!(
+                "prompt '{prompt_name}' template '{template_name}' not found"
+            )));
+        }
+        Err(err) => {
+            return Err(
+                anyhow::Error::new(RenderError::from_minijinja(&env, template_name, &err)).context(
+                    format!("compiling template '{template_name}' for prompt '{prompt_name}'"),
+                ),
+            );
         }
     };
 
-    if !args.is_empty() {
-        let positional = serde_json::Value::Array(
-            args.iter()
-                .cloned()
-                .map(serde_json::Value::String)
-                .collect(),
-        );
-        map.insert("_args".into(), positional);
-    }
+    // Only shell out to git when the template actually asks for it.
+    #[cfg(feature = "git")]
+    let map = {
+        let mut map = map;
+        if settings.git_context
+            && template_ref.undeclared_variables(false).contains("_git")
+            && let Some(git) = git::git_context()
+        {
+            map.insert("_git".into(), git);
+        }
+        map
+    };
+    #[cfg(not(feature = "git"))]
+    let _ = settings.git_context;
 
     let context_value = serde_json::Value::Object(map);
     let rendered = template_ref
         .render(minijinja::value::Value::from_serialize(&context_value))
+        .map_err(|err| RenderError::from_minijinja(&env, template_name, &err))
         .with_context(|| {
             format!("rendering template '{template_name}' for prompt '{prompt_name}'")
         })?;
     Ok(rendered)
 }
 
-fn load_structured_data(data: &StructuredData) -> Result<serde_json::Value> {
-    match data {
-        StructuredData::Json(path) => {
-            let content = read_utf8(path)?;
-            Ok(serde_json::from_str(&content)
-                .with_context(|| format!("failed to parse JSON data from {path}"))?)
+/// Fail unless `content` hashes to the pinned `expected` digest.
+fn verify_pin(prompt: &str, file: &str, expected: &str, content: &[u8]) -> Result<()> {
+    use sha2::{Digest, Sha256};
+    use std::fmt::Write as _;
+
+    let actual = Sha256::digest(content)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        });
+    if actual != expected {
+        bail!(
+            "fragment '{file}' for prompt '{prompt}' does not match its pinned sha256: expected {expected}, found {actual}"
+        );
+    }
+    Ok(())
+}
+
+/// The prompt's declared `data` file, resolved against `base` and read as its `data_format`
+/// when one is set.
+fn default_data(spec: &PromptSpec, base: &Utf8Path) -> Option<StructuredData> {
+    let path = base.join(spec.metadata.data.as_ref()?);
+    match spec.metadata.data_format {
+        Some(format) => Some(StructuredData::with_format(path, format)),
+        None => StructuredData::from_path(path),
+    }
+}
+
+/// Remove the leading whitespace every non-blank line of `text` shares, like Python's
+/// `textwrap.dedent`. Blank lines do not count toward the margin and lose their whitespace.
+fn dedent(text: &str) -> String {
+    let margin = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .reduce(|margin, indent| {
+            let common = margin
+                .char_indices()
+                .zip(indent.chars())
+                .find(|((_, left), right)| left != right)
+                .map_or(margin.len().min(indent.len()), |((index, _), _)| index);
+            &margin[..common]
+        })
+        .unwrap_or_default();
+
+    text.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                &line[line.trim_end_matches(['\n', '\r']).len()..]
+            } else {
+                line.strip_prefix(margin).unwrap_or(line)
+            }
+        })
+        .collect()
+}
+
+/// Macro templates matching `patterns` under `base`, listed through `source` and sorted within
+/// each pattern. `*` and `?` stay within one path segment; `**` spans directories.
+fn macro_paths(
+    source: &dyn FragmentSource,
+    base: &Utf8Path,
+    patterns: &[String],
+) -> Result<Vec<Utf8PathBuf>> {
+    let options = glob::MatchOptions {
+        require_literal_separator: true,
+        ..glob::MatchOptions::new()
+    };
+    let mut paths = Vec::new();
+    for pattern in patterns {
+        let compiled = glob::Pattern::new(pattern)
+            .with_context(|| format!("invalid macros pattern '{pattern}'"))?;
+        let segments: Vec<&str> = pattern.split('/').collect();
+        let literal = segments
+            .iter()
+            .take_while(|segment| !segment.contains(['*', '?', '[']))
+            .count();
+        if literal == segments.len() {
+            let path = base.join(pattern);
+            if source.exists(&path) {
+                paths.push(path);
+            }
+            continue;
+        }
+
+        // Walk from the deepest directory the pattern names literally, no deeper than the
+        // pattern reaches unless it contains `**`.
+        let recursive = segments.contains(&"**");
+        let mut matches = Vec::new();
+        let mut pending = vec![(base.join(segments[..literal].join("/")), literal)];
+        while let Some((dir, depth)) = pending.pop() {
+            if !source.is_dir(&dir) {
+                continue;
+            }
+            let entries = source
+                .list_dir(&dir)
+                .with_context(|| format!("failed to expand macros pattern '{pattern}'"))?;
+            for entry in entries {
+                if entry.is_dir {
+                    if recursive || depth + 1 < segments.len() {
+                        pending.push((entry.path, depth + 1));
+                    }
+                    continue;
+                }
+                let Ok(relative) = entry.path.strip_prefix(base) else {
+                    continue;
+                };
+                if compiled.matches_path_with(relative.as_std_path(), options) {
+                    matches.push(entry.path);
+                }
+            }
+        }
+        matches.sort();
+        paths.extend(matches);
+    }
+    Ok(paths)
+}
+
+/// The macro templates matching `patterns` under `base`, each paired with the alias it is
+/// imported under: its file stem with characters other than ASCII letters and digits replaced
+/// by `_`.
+///
+/// # Errors
+/// Returns an error when a pattern cannot be expanded or two macro files share an alias.
+fn macro_aliases(
+    source: &dyn FragmentSource,
+    base: &Utf8Path,
+    patterns: &[String],
+) -> Result<Vec<(String, Utf8PathBuf)>> {
+    let mut aliases: Vec<(String, Utf8PathBuf)> = Vec::new();
+    for path in macro_paths(source, base, patterns)? {
+        let Ok(name) = path.strip_prefix(base) else {
+            continue;
+        };
+        let alias: String = name
+            .file_stem()
+            .unwrap_or_default()
+            .chars()
+            .map(|ch| if ch.is_ascii_alphanumeric() { ch } else { '_' })
+            .collect();
+        if let Some((_, other)) = aliases.iter().find(|(existing, _)| *existing == alias) {
+            bail!("macros '{other}' and '{name}' would both be imported as '{alias}'");
+        }
+        aliases.push((alias, name.to_owned()));
+    }
+    Ok(aliases)
+}
+
+/// Build `{% import %}` tags for the macro templates matching `patterns` under `base`, each
+/// bound to its alias. The tags share one line so template line numbers are unchanged.
+fn macro_imports(
+    source: &dyn FragmentSource,
+    base: &Utf8Path,
+    patterns: &[String],
+) -> Result<String> {
+    use std::fmt::Write as _;
+
+    let mut imports = String::new();
+    for (alias, name) in macro_aliases(source, base, patterns)? {
+        let _ = write!(imports, "{{% import {:?} as {alias} %}}", name.as_str());
+    }
+    Ok(imports)
+}
+
+/// Resolve template names against `base` through `source`, rejecting `..` segments the same
+/// way minijinja's `path_loader` does.
+fn fragment_loader(
+    source: FragmentReader,
+    base: Utf8PathBuf,
+) -> impl Fn(&str) -> std::result::Result<Option<String>, minijinja::Error> + Send + Sync + 'static
+{
+    move |name| {
+        if name
+            .split('/')
+            .any(|segment| segment == ".." || segment.contains('\\'))
+        {
+            return Ok(None);
+        }
+        let path = base.join(name);
+        if !source.exists(&path) {
+            return Ok(None);
+        }
+        source.read(&path).map(Some).map_err(|err| {
+            minijinja::Error::new(
+                minijinja::ErrorKind::InvalidOperation,
+                format!("could not read template {path}: {err:#}"),
+            )
+        })
+    }
+}
+
+/// Read and parse `data` in its format, converting TOML and YAML to JSON with every table's
+/// keys sorted.
+///
+/// # Errors
+/// Returns an error when the file cannot be read or does not parse as its format.
+pub fn load_structured_data(data: &StructuredData) -> Result<serde_json::Value> {
+    let (format, path) = match data {
+        StructuredData::Json(path) => (DataFormat::Json, path),
+        StructuredData::Toml(path) => (DataFormat::Toml, path),
+        StructuredData::Yaml(path) => (DataFormat::Yaml, path),
+    };
+    format.parse_content(&read_utf8(path)?, path.as_str())
+}
+
+/// Order every table in `value` by key, so templates iterate TOML and YAML data the same way however
+/// `serde_json` happens to be configured in the final build.
+fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<_> = map.into_iter().collect();
+            entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, sort_keys(value)))
+                    .collect(),
+            )
         }
-        StructuredData::Toml(path) => {
-            let content = read_utf8(path)?;
-            let toml_value: toml::Value = toml::from_str(&content)
-                .with_context(|| format!("failed to parse TOML data from {path}"))?;
-            serde_json::to_value(toml_value)
-                .map_err(|err| anyhow!("failed to convert TOML to JSON: {err}"))
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(sort_keys).collect())
         }
+        other => other,
     }
 }
 
@@ -854,19 +4260,93 @@ struct RawFile {
     #[serde(default)]
     prompt_path: Option<String>,
     #[serde(default)]
+    settings: Option<RawSettings>,
+    #[serde(default)]
     prompt: IndexMap<String, RawPrompt>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
+struct RawSettings {
+    #[serde(default)]
+    require_absolute_paths: Option<bool>,
+    #[serde(default)]
+    lossy_utf8: Option<bool>,
+    #[serde(default)]
+    remote_cache_ttl: Option<u64>,
+    #[serde(default)]
+    git_context: Option<bool>,
+    #[serde(default)]
+    fragment_extensions: Option<Vec<String>>,
+    #[serde(default)]
+    macros: Option<Vec<String>>,
+    #[serde(default)]
+    default_prompt: Option<String>,
+    #[serde(default)]
+    audit_log: Option<String>,
+    #[serde(default)]
+    context_file: Option<String>,
+    #[serde(default)]
+    global_prepend: Option<String>,
+    #[serde(default)]
+    global_append: Option<String>,
+}
+
+impl RawSettings {
+    fn apply(self, settings: &mut Settings) {
+        if let Some(value) = self.require_absolute_paths {
+            settings.require_absolute_paths = value;
+        }
+        if let Some(value) = self.lossy_utf8 {
+            settings.lossy_utf8 = value;
+        }
+        if let Some(value) = self.remote_cache_ttl {
+            settings.remote_cache_ttl = Some(value);
+        }
+        if let Some(value) = self.git_context {
+            settings.git_context = value;
+        }
+        if let Some(extensions) = self.fragment_extensions {
+            settings.fragment_extensions = Some(
+                extensions
+                    .into_iter()
+                    .map(|extension| extension.trim_start_matches('.').to_owned())
+                    .collect(),
+            );
+        }
+        if let Some(patterns) = self.macros {
+            settings.macros = patterns;
+        }
+        if let Some(name) = self.default_prompt {
+            settings.default_prompt = Some(name);
+        }
+        if let Some(file) = self.global_prepend {
+            settings.global_prepend = Some(Utf8PathBuf::from(file));
+        }
+        if let Some(file) = self.global_append {
+            settings.global_append = Some(Utf8PathBuf::from(file));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct RawPrompt {
+    #[serde(default)]
+    base: Option<String>,
+    #[serde(default, rename = "abstract")]
+    is_abstract: bool,
     #[serde(default)]
     prompt_path: Option<String>,
     #[serde(default)]
-    prompts: Option<Vec<String>>,
+    prompts: Option<Vec<RawSequenceEntry>>,
     #[serde(default)]
     template: Option<String>,
     #[serde(default)]
+    inline: Option<String>,
+    #[serde(default)]
+    template_inline: Option<String>,
+    #[serde(default)]
     description: Option<String>,
     #[serde(default)]
     tags: Vec<String>,
@@ -875,9 +4355,98 @@ struct RawPrompt {
     #[serde(default)]
     #[serde(rename = "stdin")]
     stdin_supported: Option<bool>,
+    #[serde(default)]
+    post_process: Option<String>,
+    #[serde(default)]
+    post_process_timeout: Option<u64>,
+    #[serde(default)]
+    escape: Option<String>,
+    #[serde(default)]
+    repeat_for_args: Option<String>,
+    #[serde(default)]
+    dedent: Option<bool>,
+    #[serde(default)]
+    autoescape: Option<bool>,
+    #[serde(default)]
+    replace: IndexMap<String, String>,
+    #[serde(default)]
+    presets: IndexMap<String, serde_json::Map<String, serde_json::Value>>,
+    #[serde(default)]
+    working_dir: Option<String>,
+    #[serde(default)]
+    data: Option<String>,
+    #[serde(default)]
+    data_format: Option<String>,
+    #[serde(default)]
+    when_os: Option<String>,
+    #[serde(default)]
+    when_env: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
+impl RawPrompt {
+    /// Fill metadata this prompt leaves unset from `base`. The kind (`prompts`, `template`,
+    /// `inline`, or `template_inline`), `prompt_path`, `working_dir`, and activation conditions
+    /// are never inherited.
+    fn inherit_from(&mut self, base: RawPrompt) {
+        let mut tags = base.tags;
+        for tag in std::mem::take(&mut self.tags) {
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        self.tags = tags;
+
+        let mut vars = base.vars;
+        for var in std::mem::take(&mut self.vars) {
+            match vars.iter_mut().find(|existing| existing.name == var.name) {
+                Some(existing) => *existing = var,
+                None => vars.push(var),
+            }
+        }
+        self.vars = vars;
+
+        self.description = self.description.take().or(base.description);
+        self.stdin_supported = self.stdin_supported.or(base.stdin_supported);
+        self.post_process = self.post_process.take().or(base.post_process);
+        self.post_process_timeout = self.post_process_timeout.or(base.post_process_timeout);
+        self.escape = self.escape.take().or(base.escape);
+        self.dedent = self.dedent.or(base.dedent);
+        self.autoescape = self.autoescape.or(base.autoescape);
+
+        let mut replace = base.replace;
+        replace.extend(std::mem::take(&mut self.replace));
+        self.replace = replace;
+        let mut presets = base.presets;
+        presets.extend(std::mem::take(&mut self.presets));
+        self.presets = presets;
+        self.data = self.data.take().or(base.data);
+        self.data_format = self.data_format.take().or(base.data_format);
+    }
+}
+
+/// A sequence entry: a bare path, or a table that may pin the file to a SHA-256 digest and
+/// tag it with a chat role.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "a fragment path or a table with `file` and optional `sha256` and `role` keys"
+)]
+enum RawSequenceEntry {
+    Path(String),
+    Table(RawSequenceTable),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSequenceTable {
+    file: String,
+    #[serde(default)]
+    sha256: Option<String>,
+    #[serde(default)]
+    role: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
 #[serde(deny_unknown_fields)]
 struct RawPromptVar {
     name: String,
@@ -888,4 +4457,10 @@ struct RawPromptVar {
     kind: Option<String>,
     #[serde(default)]
     description: Option<String>,
+    #[serde(default)]
+    required_if: Option<String>,
+    #[serde(default)]
+    default: Option<String>,
+    #[serde(default)]
+    position: Option<usize>,
 }
@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use camino::{Utf8Path, Utf8PathBuf};
+
+/// Files under `dir`, recursively and sorted, skipping hidden entries and anything in `skip`.
+pub(crate) fn library_files(
+    dir: &Utf8Path,
+    skip: &HashSet<Utf8PathBuf>,
+) -> Result<Vec<Utf8PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_owned()];
+    while let Some(current) = pending.pop() {
+        let entries = current
+            .read_dir_utf8()
+            .with_context(|| format!("failed to read directory {current}"))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read entry in {current}"))?;
+            let path = entry.path();
+            if entry.file_name().starts_with('.') || skip.contains(path) {
+                continue;
+            }
+            if path.is_dir() {
+                pending.push(path.to_owned());
+            } else if path.is_file() {
+                files.push(path.to_owned());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Paths named by `{{> path }}` markers in a sequence fragment. Escaped `{{{>` markers and
+/// malformed ones are ignored; rendering reports the latter.
+pub(crate) fn transclusion_targets(content: &str) -> Vec<&str> {
+    let mut targets = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("{{>") {
+        let after = &rest[start + 3..];
+        if rest[..start].ends_with('{') {
+            rest = after;
+            continue;
+        }
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        let target = after[..end].trim();
+        if !target.is_empty() {
+            targets.push(target);
+        }
+        rest = &after[end + 2..];
+    }
+    targets
+}
+
+/// Template names loaded by `include`, `extends`, `import`, and `from` tags with a literal
+/// string argument. Names computed at render time cannot be seen and are not reported.
+pub(crate) fn template_references(content: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find("{%") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("%}") else {
+            break;
+        };
+        let tag = after[..end].trim_start_matches(['-', '+']).trim_start();
+        let loads = ["include", "extends", "import", "from"]
+            .iter()
+            .any(|keyword| {
+                tag.strip_prefix(keyword)
+                    .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+            });
+        if loads {
+            names.extend(quoted_strings(tag));
+        }
+        rest = &after[end + 2..];
+    }
+    names
+}
+
+/// Single- or double-quoted literals in `tag`, so `include ["a.j2", "b.j2"]` lists both.
+fn quoted_strings(tag: &str) -> Vec<&str> {
+    let mut strings = Vec::new();
+    let mut rest = tag;
+    while let Some(start) = rest.find(['"', '\'']) {
+        let quote = rest[start..].chars().next().unwrap_or('"');
+        let after = &rest[start + 1..];
+        let Some(end) = after.find(quote) else {
+            break;
+        };
+        strings.push(&after[..end]);
+        rest = &after[end + 1..];
+    }
+    strings
+}
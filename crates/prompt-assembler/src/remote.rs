@@ -0,0 +1,228 @@
+use std::fmt;
+use std::fs;
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, anyhow};
+use camino::{Utf8Path, Utf8PathBuf};
+use reqwest::StatusCode;
+use reqwest::blocking::Client;
+use reqwest::header::{ETAG, IF_NONE_MATCH};
+
+use crate::{FragmentSource, Result, SourceEntry, is_remote};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Serves `http(s)://` fragment references over the network and delegates everything else.
+///
+/// Responses are cached under `cache_dir` together with their `ETag`. Cached copies younger
+/// than the TTL are used as-is; older ones are revalidated, and the cache is used whenever the
+/// network is unavailable or the server answers with an error status.
+pub struct RemoteSource<S> {
+    inner: S,
+    cache_dir: Utf8PathBuf,
+    ttl: Option<Duration>,
+    offline: bool,
+    client: OnceLock<Option<Client>>,
+}
+
+impl<S> RemoteSource<S> {
+    /// Wrap `inner`, caching remote fragments in `cache_dir`.
+    ///
+    /// The HTTP client is only created once a remote fragment is first read.
+    pub fn new(inner: S, cache_dir: impl Into<Utf8PathBuf>) -> Self {
+        Self {
+            inner,
+            cache_dir: cache_dir.into(),
+            ttl: None,
+            offline: false,
+            client: OnceLock::new(),
+        }
+    }
+
+    /// Serve cached copies younger than `ttl` without contacting the server.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Never touch the network; reads of uncached URLs fail.
+    #[must_use]
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    fn is_fresh(&self, body_path: &Utf8Path) -> bool {
+        let Some(ttl) = self.ttl else {
+            return false;
+        };
+        fs::metadata(body_path.as_std_path())
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < ttl)
+    }
+
+    fn client(&self) -> Result<&Client> {
+        self.client
+            .get_or_init(|| {
+                Client::builder()
+                    .timeout(REQUEST_TIMEOUT)
+                    .user_agent(concat!("prompt-assembler/", env!("CARGO_PKG_VERSION")))
+                    .build()
+                    .ok()
+            })
+            .as_ref()
+            .ok_or_else(|| anyhow!("failed to initialize HTTP client"))
+    }
+
+    fn cache_paths(&self, url: &str) -> (Utf8PathBuf, Utf8PathBuf) {
+        let key = format!("{:016x}", fnv1a(url.as_bytes()));
+        (
+            self.cache_dir.join(format!("{key}.body")),
+            self.cache_dir.join(format!("{key}.etag")),
+        )
+    }
+
+    fn fetch(&self, url: &str) -> Result<String> {
+        let (body_path, etag_path) = self.cache_paths(url);
+        let cached = fs::read_to_string(body_path.as_std_path()).ok();
+        if self.offline {
+            return cached.ok_or_else(|| {
+                anyhow!("{url} is not cached and network access is disabled (offline)")
+            });
+        }
+        if let Some(cached) = &cached
+            && self.is_fresh(&body_path)
+        {
+            return Ok(cached.clone());
+        }
+        let etag = fs::read_to_string(etag_path.as_std_path()).ok();
+
+        let mut request = self.client()?.get(url);
+        if let (Some(_), Some(etag)) = (&cached, &etag) {
+            request = request.header(IF_NONE_MATCH, etag.trim());
+        }
+
+        let response = match request.send() {
+            Ok(response) => response,
+            Err(err) => {
+                return cached.ok_or_else(|| {
+                    anyhow!("failed to fetch {url} and no cached copy exists: {err}")
+                });
+            }
+        };
+
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED
+            && let Some(cached) = cached
+        {
+            // Restart the TTL clock; failing to do so only costs an extra revalidation.
+            let _ = fs::File::options()
+                .write(true)
+                .open(body_path.as_std_path())
+                .and_then(|file| file.set_modified(SystemTime::now()));
+            return Ok(cached);
+        }
+        if !status.is_success() {
+            return cached.ok_or_else(|| anyhow!("failed to fetch {url}: HTTP {status}"));
+        }
+
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let body = response
+            .text()
+            .with_context(|| format!("failed to read response body from {url}"))?;
+
+        self.store(&body_path, &etag_path, &body, etag.as_deref())
+            .with_context(|| format!("failed to cache {url}"))?;
+        Ok(body)
+    }
+
+    /// Whether `url` is cached, or else whether the server answers a `HEAD` request for it.
+    fn is_available(&self, url: &str) -> bool {
+        let (body_path, _) = self.cache_paths(url);
+        if body_path.exists() {
+            return true;
+        }
+        if self.offline {
+            return false;
+        }
+        self.client()
+            .ok()
+            .and_then(|client| client.head(url).send().ok())
+            .is_some_and(|response| response.status().is_success())
+    }
+
+    fn store(
+        &self,
+        body_path: &Utf8Path,
+        etag_path: &Utf8Path,
+        body: &str,
+        etag: Option<&str>,
+    ) -> Result<()> {
+        fs::create_dir_all(self.cache_dir.as_std_path())?;
+        fs::write(body_path.as_std_path(), body)?;
+        match etag {
+            Some(etag) => fs::write(etag_path.as_std_path(), etag)?,
+            None if etag_path.exists() => fs::remove_file(etag_path.as_std_path())?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for RemoteSource<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RemoteSource")
+            .field("inner", &self.inner)
+            .field("cache_dir", &self.cache_dir)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S: FragmentSource> FragmentSource for RemoteSource<S> {
+    fn read(&self, path: &Utf8Path) -> Result<String> {
+        if is_remote(path.as_str()) {
+            self.fetch(path.as_str())
+        } else {
+            self.inner.read(path)
+        }
+    }
+
+    fn read_bytes(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        if is_remote(path.as_str()) {
+            self.fetch(path.as_str()).map(String::into_bytes)
+        } else {
+            self.inner.read_bytes(path)
+        }
+    }
+
+    fn exists(&self, path: &Utf8Path) -> bool {
+        if is_remote(path.as_str()) {
+            self.is_available(path.as_str())
+        } else {
+            self.inner.exists(path)
+        }
+    }
+
+    fn is_dir(&self, path: &Utf8Path) -> bool {
+        !is_remote(path.as_str()) && self.inner.is_dir(path)
+    }
+
+    fn list_dir(&self, dir: &Utf8Path) -> Result<Vec<SourceEntry>> {
+        self.inner.list_dir(dir)
+    }
+}
+
+/// Stable 64-bit FNV-1a hash used to name cache entries.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail};
+
+use crate::{RenderOptions, Result};
+
+/// A `KEY=service/account` request to fill the template context entry `KEY` from a secret
+/// store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretRef {
+    pub key: String,
+    pub service: String,
+    pub account: String,
+}
+
+impl FromStr for SecretRef {
+    type Err = anyhow::Error;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        let invalid = || anyhow!("invalid secret '{raw}': expected KEY=service/account");
+        let (key, entry) = raw.split_once('=').ok_or_else(invalid)?;
+        let (service, account) = entry.rsplit_once('/').ok_or_else(invalid)?;
+        if key.is_empty() || service.is_empty() || account.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            key: key.to_owned(),
+            service: service.to_owned(),
+            account: account.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for SecretRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}/{}", self.key, self.service, self.account)
+    }
+}
+
+/// Where secret values are looked up, keyed by service and account.
+pub trait SecretStore {
+    /// The secret stored for `service` and `account`, or `None` when there is no such entry.
+    ///
+    /// # Errors
+    /// Returns an error when the store itself cannot be queried.
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>>;
+}
+
+/// Reads secrets from the platform keyring: Keychain on macOS, Credential Manager on Windows,
+/// and the kernel keyring on Linux.
+#[cfg(feature = "keyring")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyringStore;
+
+#[cfg(feature = "keyring")]
+impl SecretStore for KeyringStore {
+    fn get(&self, service: &str, account: &str) -> Result<Option<String>> {
+        match keyring::Entry::new(service, account)?.get_password() {
+            Ok(secret) => Ok(Some(secret)),
+            Err(keyring::Error::NoEntry) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl RenderOptions {
+    /// Look up `secret` in `store` and add it to the template context, marking the entry
+    /// redacted so [`crate::PromptAssembler::dump_context`] never shows its value.
+    ///
+    /// # Errors
+    /// Returns an error when the store has no such entry or cannot be queried.
+    pub fn insert_secret(&mut self, secret: &SecretRef, store: &dyn SecretStore) -> Result<()> {
+        let Some(value) = store.get(&secret.service, &secret.account)? else {
+            bail!(
+                "secret '{}' not found: no entry for service '{}' and account '{}'",
+                secret.key,
+                secret.service,
+                secret.account
+            );
+        };
+        self.context
+            .insert(secret.key.clone(), serde_json::Value::String(value));
+        self.redact.insert(secret.key.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use anyhow::Context;
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::{FragmentTiming, Result};
+
+/// Where prompt fragments, templates, and parts are read from.
+///
+/// Paths are already resolved against the prompt's `prompt_path`, so an implementation only
+/// needs to map a full path to its contents.
+pub trait FragmentSource: fmt::Debug + Send + Sync {
+    /// Read the UTF-8 contents stored at `path`.
+    ///
+    /// # Errors
+    /// Returns an error when the path does not exist or cannot be read as UTF-8.
+    fn read(&self, path: &Utf8Path) -> Result<String>;
+
+    /// Read the raw bytes stored at `path`, used when decoding lossily.
+    /// The default delegates to [`FragmentSource::read`].
+    ///
+    /// # Errors
+    /// Returns an error when the path does not exist or cannot be read.
+    fn read_bytes(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        self.read(path).map(String::into_bytes)
+    }
+
+    /// Report whether `path` can be read. The default attempts a full read.
+    fn exists(&self, path: &Utf8Path) -> bool {
+        self.read(path).is_ok()
+    }
+
+    /// Report whether `path` names a directory that [`FragmentSource::list_dir`] can list.
+    /// The default reports none, so every sequence entry is read as a file.
+    fn is_dir(&self, path: &Utf8Path) -> bool {
+        let _ = path;
+        false
+    }
+
+    /// List the entries directly inside the directory `dir`, in any order. The default lists
+    /// nothing.
+    ///
+    /// # Errors
+    /// Returns an error when the directory cannot be read.
+    fn list_dir(&self, dir: &Utf8Path) -> Result<Vec<SourceEntry>> {
+        let _ = dir;
+        Ok(Vec::new())
+    }
+}
+
+/// One entry returned by [`FragmentSource::list_dir`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceEntry {
+    /// Full path of the entry, `dir` joined with its name.
+    pub path: Utf8PathBuf,
+    /// Whether the entry is itself a directory.
+    pub is_dir: bool,
+}
+
+/// Reads fragments from the local filesystem. This is the default source.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystemSource;
+
+impl FragmentSource for FileSystemSource {
+    fn read(&self, path: &Utf8Path) -> Result<String> {
+        read_utf8(path)
+    }
+
+    fn read_bytes(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        fs::read(path.as_std_path()).with_context(|| format!("failed to read {path}"))
+    }
+
+    fn exists(&self, path: &Utf8Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Utf8Path) -> bool {
+        path.is_dir()
+    }
+
+    fn list_dir(&self, dir: &Utf8Path) -> Result<Vec<SourceEntry>> {
+        let mut listed = Vec::new();
+        let entries = dir
+            .read_dir_utf8()
+            .with_context(|| format!("failed to read directory {dir}"))?;
+        for entry in entries {
+            let entry = entry.with_context(|| format!("failed to read entry in {dir}"))?;
+            let path = entry.path();
+            let is_dir = path.is_dir();
+            if is_dir || path.is_file() {
+                listed.push(SourceEntry {
+                    path: path.to_owned(),
+                    is_dir,
+                });
+            }
+        }
+        Ok(listed)
+    }
+}
+
+/// Reads fragments for a single render, collecting warnings raised along the way.
+///
+/// Clones share the warning list so the template loader can report into the same render.
+#[derive(Debug, Clone)]
+pub(crate) struct FragmentReader {
+    source: Arc<dyn FragmentSource>,
+    lossy: bool,
+    warnings: Arc<Mutex<Vec<String>>>,
+    timings: Arc<Mutex<Vec<FragmentTiming>>>,
+}
+
+impl FragmentReader {
+    pub(crate) fn new(source: Arc<dyn FragmentSource>, lossy: bool) -> Self {
+        Self {
+            source,
+            lossy,
+            warnings: Arc::default(),
+            timings: Arc::default(),
+        }
+    }
+
+    pub(crate) fn read(&self, path: &Utf8Path) -> Result<String> {
+        if !self.lossy {
+            return self.source.read(path);
+        }
+
+        let bytes = self.source.read_bytes(path)?;
+        self.decode(path, bytes)
+    }
+
+    pub(crate) fn read_bytes(&self, path: &Utf8Path) -> Result<Vec<u8>> {
+        self.source.read_bytes(path)
+    }
+
+    /// Decode bytes read from `path`, replacing invalid UTF-8 with a warning when lossy.
+    pub(crate) fn decode(&self, path: &Utf8Path, bytes: Vec<u8>) -> Result<String> {
+        match String::from_utf8(bytes) {
+            Ok(content) => Ok(content),
+            Err(err) if self.lossy => {
+                self.warn(format!(
+                    "{path} is not valid UTF-8; invalid bytes were replaced"
+                ));
+                Ok(String::from_utf8_lossy(err.as_bytes()).into_owned())
+            }
+            Err(err) => Err(err).with_context(|| format!("{path} is not valid UTF-8")),
+        }
+    }
+
+    pub(crate) fn source(&self) -> &dyn FragmentSource {
+        self.source.as_ref()
+    }
+
+    pub(crate) fn exists(&self, path: &Utf8Path) -> bool {
+        self.source.exists(path)
+    }
+
+    pub(crate) fn warn(&self, message: String) {
+        self.warnings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(message);
+    }
+
+    pub(crate) fn take_warnings(&self) -> Vec<String> {
+        std::mem::take(&mut *self.warnings.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+
+    pub(crate) fn record_timing(&self, timing: FragmentTiming) {
+        self.timings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(timing);
+    }
+
+    pub(crate) fn take_timings(&self) -> Vec<FragmentTiming> {
+        std::mem::take(&mut *self.timings.lock().unwrap_or_else(PoisonError::into_inner))
+    }
+}
+
+pub(crate) fn read_utf8(path: &Utf8Path) -> Result<String> {
+    let mut file =
+        fs::File::open(path.as_std_path()).with_context(|| format!("failed to open {path}"))?;
+    let mut buf = String::new();
+    file.read_to_string(&mut buf)
+        .with_context(|| format!("failed to read {path}"))?;
+    Ok(buf)
+}
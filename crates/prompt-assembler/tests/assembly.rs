@@ -1,8 +1,15 @@
+use std::collections::HashMap;
 use std::fs;
 use std::io::Write;
-
-use camino::Utf8Path;
-use prompt_assembler::{LoadConfigError, PromptAssembler, StructuredData};
+use std::num::NonZeroUsize;
+
+use anyhow::anyhow;
+use camino::{Utf8Path, Utf8PathBuf};
+use prompt_assembler::{
+    Config, ConfigIssueCode, FragmentSource, LayoutOptions, LoadConfigError, LoadOptions,
+    MissingFragmentPolicy, PromptAssembler, PromptKind, PromptSpec, RenderError, RenderOptions,
+    SecretRef, SecretStore, StructuredData, substitute, substitute_named,
+};
 use tempfile::TempDir;
 
 fn utf8_path(path: &std::path::Path) -> &Utf8Path {
@@ -155,6 +162,65 @@ fn renders_template_prompt_with_toml_data() {
     assert_eq!(rendered, "Role: admin\n");
 }
 
+#[test]
+fn renders_template_prompt_with_yaml_data() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.system]
+        template = "system.j2"
+
+        [prompt.declared]
+        template = "system.j2"
+        data = "role.txt"
+        data_format = "yaml"
+        "#,
+    );
+    write_file(
+        root,
+        "system.j2",
+        "Role: {{ role }}{% for tool in tools %} {{ tool }}{% endfor %}\n",
+    );
+    write_file(
+        root,
+        "data.yml",
+        "role: admin\ntools:\n  - git\n  - cargo\n",
+    );
+    write_file(root, "role.txt", "role: viewer\ntools: []\n");
+    write_file(root, "broken.yaml", "role: [unclosed\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+
+    let rendered = assembler
+        .render_prompt(
+            "system",
+            &[],
+            Some(StructuredData::Yaml(root.join("data.yml"))),
+        )
+        .expect("render template");
+    assert_eq!(rendered, "Role: admin git cargo\n");
+
+    let rendered = assembler
+        .render_prompt("declared", &[], None)
+        .expect("render declared YAML data");
+    assert_eq!(rendered, "Role: viewer\n");
+
+    let err = assembler
+        .render_prompt(
+            "system",
+            &[],
+            Some(StructuredData::Yaml(root.join("broken.yaml"))),
+        )
+        .expect_err("invalid YAML should fail");
+    assert!(
+        format!("{err:#}").contains("failed to parse YAML data"),
+        "{err:#}"
+    );
+}
+
 #[test]
 fn fails_when_arguments_missing() {
     let temp = TempDir::new().unwrap();
@@ -240,6 +306,34 @@ fn loads_without_prompt_definitions() {
     assert_eq!(assembled_parts, "standalone content\n");
 }
 
+#[test]
+fn concurrent_part_reads_keep_order_and_report_first_failure() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+
+    let names: Vec<String> = (0..40).map(|i| format!("part{i:02}.md")).collect();
+    for (i, name) in names.iter().enumerate() {
+        write_file(root, name, &format!("{i}\n"));
+    }
+    let concurrency = NonZeroUsize::new(8).unwrap();
+
+    let parts = assembler
+        .read_parts_concurrently(root, &names, concurrency)
+        .expect("read parts");
+    let contents: Vec<_> = parts.iter().map(|part| part.content.as_str()).collect();
+    let expected: Vec<_> = (0..40).map(|i| format!("{i}\n")).collect();
+    assert_eq!(contents, expected);
+
+    let mut with_gaps = names.clone();
+    with_gaps.insert(5, "missing-a.md".into());
+    with_gaps.push("missing-b.md".into());
+    let err = assembler
+        .read_parts_concurrently(root, &with_gaps, concurrency)
+        .unwrap_err();
+    assert!(err.to_string().contains("missing-a.md"), "{err:#}");
+}
+
 #[test]
 fn default_prompt_path_is_config_directory() {
     let temp = TempDir::new().unwrap();
@@ -397,6 +491,108 @@ fn later_conf_d_entries_override_base_definition() {
     assert_eq!(rendered, "Override yes\n");
 }
 
+#[test]
+fn render_prompt_to_writer_streams_fragments() {
+    /// Records each write separately, failing once `fail_after` writes have succeeded.
+    struct Chunks {
+        writes: Vec<String>,
+        fail_after: Option<usize>,
+    }
+
+    impl Write for Chunks {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self
+                .fail_after
+                .is_some_and(|limit| self.writes.len() >= limit)
+            {
+                return Err(std::io::Error::other("sink closed"));
+            }
+            self.writes.push(String::from_utf8_lossy(buf).into_owned());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.steps]
+        prompts = ["one.md", "two.md"]
+
+        [prompt.shouty]
+        prompts = ["one.md", "two.md"]
+        replace = { "first" = "FIRST" }
+        "#,
+    );
+    write_file(root, "one.md", "first {0}");
+    write_file(root, "two.md", "second {0}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let args = ["x".to_owned()];
+
+    let mut chunks = Chunks {
+        writes: Vec::new(),
+        fail_after: None,
+    };
+    assembler
+        .render_prompt_to_writer("steps", &args, None, &mut chunks)
+        .expect("stream prompt");
+    assert_eq!(chunks.writes, ["first x", "\n", "second x\n"]);
+    assert_eq!(
+        chunks.writes.concat(),
+        assembler.render_prompt("steps", &args, None).unwrap()
+    );
+
+    let mut buffer = Vec::new();
+    assembler
+        .render_prompt_to_writer("shouty", &args, None, &mut buffer)
+        .expect("render prompt with replace");
+    assert_eq!(String::from_utf8(buffer).unwrap(), "FIRST x\nsecond x\n");
+
+    let mut failing = Chunks {
+        writes: Vec::new(),
+        fail_after: Some(1),
+    };
+    let err = assembler
+        .render_prompt_to_writer("steps", &args, None, &mut failing)
+        .expect_err("write failure should surface");
+    assert!(err.to_string().contains("failed to write rendered output"));
+    assert_eq!(failing.writes, ["first x"]);
+}
+
+#[test]
+fn reload_picks_up_config_changes_and_keeps_state_on_error() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(root, "[prompt.hello]\nprompts = [\"hello.md\"]\n");
+    write_file(root, "hello.md", "Hello {0}\n");
+    write_file(root, "bye.md", "Bye {0}\n");
+
+    let mut assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let options = LoadOptions::default();
+
+    write_config(
+        root,
+        "[prompt.hello]\nprompts = [\"hello.md\"]\n\n[prompt.bye]\nprompts = [\"bye.md\"]\n",
+    );
+    assembler.reload(root, &options).expect("reload");
+    let rendered = assembler
+        .render_prompt("bye", &["Ada".into()], None)
+        .expect("render reloaded prompt");
+    assert_eq!(rendered, "Bye Ada\n");
+
+    write_config(root, "[prompt.broken]\n");
+    assert!(assembler.reload(root, &options).is_err());
+    assert!(assembler.prompt_spec("bye").is_some());
+}
+
 #[test]
 fn config_errors_on_unknown_prompt_key() {
     let temp = TempDir::new().unwrap();
@@ -459,7 +655,7 @@ fn errors_on_non_sequential_placeholder_index() {
 }
 
 #[test]
-fn errors_on_placeholder_index_above_nine() {
+fn placeholder_indexes_above_nine_use_later_arguments() {
     let temp = TempDir::new().unwrap();
     let root = utf8_path(temp.path());
     let library_dir = root.join("library");
@@ -477,15 +673,339 @@ fn errors_on_placeholder_index_above_nine() {
         )
         .as_str(),
     );
-    write_file(&library_dir, "ten.md", "Value {10}\n");
+    write_file(&library_dir, "ten.md", "Value {10}, {12}\n");
 
     let assembler = PromptAssembler::from_directory(root).expect("load assembler");
 
     let err = assembler
         .render_prompt("ten", &["one".into()], None)
-        .expect_err("placeholder above nine should fail");
+        .expect_err("placeholder beyond the arguments should fail");
+    assert!(
+        format!("{err}").contains("missing argument for placeholder {10}"),
+        "{err}"
+    );
+
+    let args: Vec<String> = (0..13).map(|index| format!("a{index}")).collect();
+    let rendered = assembler
+        .render_prompt("ten", &args, None)
+        .expect("render with thirteen arguments");
+    assert_eq!(rendered, "Value a10, a12\n");
+}
+
+#[test]
+fn named_placeholders_enforce_required_vars() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.issue]
+        prompts = ["issue.md"]
+        vars = [
+          { name = "issue", required = true },
+          { name = "note" },
+        ]
+        "#,
+    );
+    write_file(root, "issue.md", "Fix {issue} for {0}.{note}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let args = ["alice".to_owned()];
+
+    let err = assembler
+        .render_prompt_with_options("issue", &args, None, &RenderOptions::default())
+        .expect_err("missing required var should fail");
+    assert!(
+        err.to_string()
+            .contains("required var 'issue' was not supplied"),
+        "{err:#}"
+    );
+
+    let options = RenderOptions {
+        vars: [("issue".to_owned(), "#42".to_owned())].into(),
+        ..RenderOptions::default()
+    };
+    let rendered = assembler
+        .render_prompt_with_options("issue", &args, None, &options)
+        .expect("render with required var");
+    assert_eq!(rendered, "Fix #42 for alice.\n");
+}
+
+#[test]
+fn named_placeholders_mix_with_positional_args() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.ticket]
+        prompts = ["ticket.md"]
+        vars = [{ name = "ticket_id" }]
+        "#,
+    );
+    write_file(root, "ticket.md", "{0}: close {ticket_id} ({reason})\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let args = ["alice".to_owned()];
+
+    let options = RenderOptions {
+        vars: [
+            ("ticket_id".to_owned(), "T-7".to_owned()),
+            ("reason".to_owned(), "done".to_owned()),
+        ]
+        .into(),
+        ..RenderOptions::default()
+    };
+    let rendered = assembler
+        .render_prompt_with_options("ticket", &args, None, &options)
+        .expect("render named placeholders");
+    assert_eq!(rendered, "alice: close T-7 (done)\n");
+
+    let options = RenderOptions {
+        vars: [("ticket_id".to_owned(), "T-7".to_owned())].into(),
+        ..RenderOptions::default()
+    };
+    let err = assembler
+        .render_prompt_with_options("ticket", &args, None, &options)
+        .expect_err("undeclared, unsupplied placeholder should fail");
+    assert!(
+        err.to_string().contains("unknown placeholder '{reason}'"),
+        "{err}"
+    );
+}
+
+#[test]
+fn declared_var_defaults_fill_missing_values() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.letter]
+        prompts = ["letter.md"]
+        vars = [{ name = "tone", default = "formal" }]
+
+        [prompt.note]
+        template = "note.j2"
+        data = "note.json"
+        vars = [
+          { name = "tone", default = "formal" },
+          { name = "signoff", required = true, default = "Thanks" },
+        ]
+        "#,
+    );
+    write_file(root, "letter.md", "Write a {tone} letter\n");
+    write_file(root, "note.j2", "{{ tone }} / {{ signoff }}\n");
+    write_file(root, "note.json", r#"{"signoff": "Cheers"}"#);
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+
+    let rendered = assembler
+        .render_prompt("letter", &[], None)
+        .expect("default fills the placeholder");
+    assert_eq!(rendered, "Write a formal letter\n");
+
+    let options = RenderOptions {
+        vars: [("tone".to_owned(), "casual".to_owned())].into(),
+        ..RenderOptions::default()
+    };
+    let rendered = assembler
+        .render_prompt_with_options("letter", &[], None, &options)
+        .expect("supplied value wins");
+    assert_eq!(rendered, "Write a casual letter\n");
+
+    let rendered = assembler
+        .render_prompt("note", &[], None)
+        .expect("default fills the template context");
+    assert_eq!(rendered, "formal / Cheers\n");
+
+    let warnings = assembler.config_warnings();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert_eq!(warnings[0].code, ConfigIssueCode::RequiredWithDefault);
+    assert_eq!(
+        warnings[0].message,
+        "var 'signoff' of prompt 'note' is required but has a default, so it is never missing"
+    );
+}
+
+#[test]
+fn positional_args_are_checked_against_declared_var_kinds() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.seeded]
+        prompts = ["seeded.md"]
+        vars = [
+          { name = "seed", type = "number", position = 0 },
+          { name = "verbose", type = "boolean", position = 1 },
+        ]
+
+        [prompt.plain]
+        prompts = ["seeded.md"]
+        "#,
+    );
+    write_file(root, "seeded.md", "Seed {0}, verbose {1}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let args = |values: &[&str]| values.iter().map(|&v| v.to_owned()).collect::<Vec<_>>();
+
+    let err = assembler
+        .render_prompt("seeded", &args(&["abc", "yes"]), None)
+        .expect_err("non-numeric seed should fail");
+    assert!(
+        err.to_string()
+            .contains("var 'seed' (number) expected but got 'abc'"),
+        "{err}"
+    );
+
+    let err = assembler
+        .render_prompt("seeded", &args(&["42", "maybe"]), None)
+        .expect_err("non-boolean flag should fail");
+    assert!(
+        err.to_string()
+            .contains("var 'verbose' (boolean) expected but got 'maybe'"),
+        "{err}"
+    );
+
+    let rendered = assembler
+        .render_prompt("seeded", &args(&["42", "yes"]), None)
+        .expect("valid args render");
+    assert_eq!(rendered, "Seed 42, verbose yes\n");
+
+    let rendered = assembler
+        .render_prompt("plain", &args(&["abc", "maybe"]), None)
+        .expect("prompts without vars are unchecked");
+    assert_eq!(rendered, "Seed abc, verbose maybe\n");
+
+    let err = assembler
+        .render_prompt("seeded", &args(&["42"]), None)
+        .expect_err("missing positional arg should fail");
+    assert!(
+        err.to_string()
+            .contains("prompt 'seeded' expects 2 arguments but got 1"),
+        "{err}"
+    );
+
+    let err = assembler
+        .render_prompt("seeded", &args(&["42", "yes", "extra"]), None)
+        .expect_err("surplus positional arg should fail");
+    assert!(
+        err.to_string()
+            .contains("prompt 'seeded' takes 2 arguments but got 3"),
+        "{err}"
+    );
+}
+
+#[test]
+fn named_vars_with_defaults_are_not_checked_as_positional_args() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.fix]
+        prompts = ["fix.md"]
+        vars = [{ name = "ticket", type = "number", default = "7" }]
+        "#,
+    );
+    write_file(root, "fix.md", "Fix {0} for ticket {ticket}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("fix", &["main.rs".to_owned()], None)
+        .expect("named var does not describe {0}");
+    assert_eq!(rendered, "Fix main.rs for ticket 7\n");
+}
+
+#[test]
+fn vars_without_position_bind_to_arguments_in_declaration_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.seeded]
+        prompts = ["seeded.md"]
+        vars = [
+          { name = "seed", type = "number" },
+          { name = "ticket", type = "number" },
+          { name = "verbose", type = "boolean" },
+          { name = "label", position = 0 },
+        ]
+        "#,
+    );
+    write_file(
+        root,
+        "seeded.md",
+        "{0}: seed {1}, verbose {2}, ticket {ticket}\n",
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let bound: Vec<(usize, &str)> = assembler
+        .positional_vars("seeded")
+        .unwrap()
+        .into_iter()
+        .map(|(position, var)| (position, var.name.as_str()))
+        .collect();
+    assert_eq!(bound, [(0, "label"), (1, "seed"), (2, "verbose")]);
+
+    let args = |values: &[&str]| values.iter().map(|&v| v.to_owned()).collect::<Vec<_>>();
+    let err = assembler
+        .render_prompt("seeded", &args(&["run", "abc", "yes"]), None)
+        .expect_err("non-numeric seed should fail");
+    assert!(
+        err.to_string()
+            .contains("var 'seed' (number) expected but got 'abc'"),
+        "{err}"
+    );
+}
 
-    assert!(format!("{err}").contains("up to 9"));
+#[test]
+fn required_if_depends_on_another_var() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.call]
+        template = "call.j2"
+        vars = [
+          { name = "use_auth", type = "boolean" },
+          { name = "api_key", required_if = "use_auth" },
+        ]
+        "#,
+    );
+    write_file(
+        root,
+        "call.j2",
+        "{% if use_auth %}key={{ api_key }}{% else %}anonymous{% endif %}\n",
+    );
+    write_file(root, "off.json", r#"{"use_auth": false}"#);
+    write_file(root, "on.json", r#"{"use_auth": true}"#);
+    write_file(root, "keyed.json", r#"{"use_auth": true, "api_key": "k"}"#);
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let render = |file: &str| {
+        assembler.render_prompt("call", &[], Some(StructuredData::Json(root.join(file))))
+    };
+
+    assert_eq!(render("off.json").unwrap(), "anonymous\n");
+    let err = render("on.json").expect_err("api_key is required when use_auth is true");
+    assert!(
+        err.to_string()
+            .contains("requires var 'api_key' because 'use_auth' is set"),
+        "{err:#}"
+    );
+    assert_eq!(render("keyed.json").unwrap(), "key=k\n");
 }
 
 #[test]
@@ -612,3 +1132,2131 @@ fn errors_when_template_prompt_missing_data() {
 
     assert!(format!("{err}").contains("data file"));
 }
+
+#[test]
+fn expands_nested_transclusion_markers() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.modular]
+            prompts = ["main.md"]
+            "#
+        )
+        .as_str(),
+    );
+    write_file(
+        &library_dir,
+        "main.md",
+        "Start {0}\n{{> shared/note.md }}\nEnd\n",
+    );
+    write_file(
+        &library_dir,
+        "shared/note.md",
+        "Note [{{> shared/inner.md}}]\n",
+    );
+    write_file(&library_dir, "shared/inner.md", "inner {0}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("modular", &["arg".into()], None)
+        .expect("render with transclusions");
+
+    assert_eq!(rendered, "Start arg\nNote [inner arg]\nEnd\n");
+}
+
+#[test]
+fn escaped_transclusion_marker_renders_literally() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.modular]
+            prompts = ["main.md"]
+            "#
+        )
+        .as_str(),
+    );
+    write_file(
+        &library_dir,
+        "main.md",
+        "Literal {{{> shared/note.md }}, real {{> shared/note.md }}\n",
+    );
+    write_file(&library_dir, "shared/note.md", "note\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("modular", &[], None)
+        .expect("render escaped marker");
+
+    assert_eq!(rendered, "Literal {> shared/note.md }, real note\n");
+}
+
+#[test]
+fn errors_when_transclusion_target_missing() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.broken]
+            prompts = ["main.md"]
+            "#
+        )
+        .as_str(),
+    );
+    write_file(&library_dir, "main.md", "{{> missing/note.md }}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let err = assembler
+        .render_prompt("broken", &[], None)
+        .expect_err("missing transclusion target should error");
+
+    assert!(format!("{err:#}").contains("missing/note.md"));
+}
+
+#[test]
+fn errors_when_transclusion_recurses_too_deeply() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.loop]
+            prompts = ["loop.md"]
+            "#
+        )
+        .as_str(),
+    );
+    write_file(&library_dir, "loop.md", "{{> loop.md }}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let err = assembler
+        .render_prompt("loop", &[], None)
+        .expect_err("self-transclusion should error");
+
+    assert!(format!("{err:#}").contains("maximum depth"));
+}
+
+#[test]
+fn require_absolute_paths_rejects_relative_prompt_path() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        prompt_path = "library"
+
+        [settings]
+        require_absolute_paths = true
+
+        [prompt.alpha]
+        prompts = ["alpha.md"]
+        "#,
+    );
+
+    let err = PromptAssembler::from_directory(root).expect_err("relative path should fail");
+    let load_err = err.downcast::<LoadConfigError>().expect("load error");
+    match load_err {
+        LoadConfigError::Invalid { diagnostics } => {
+            assert!(
+                diagnostics
+                    .errors
+                    .iter()
+                    .any(|issue| issue.message.contains("require_absolute_paths"))
+            );
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn require_absolute_paths_accepts_absolute_and_rejects_relative_prompt_override() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [settings]
+            require_absolute_paths = true
+
+            [prompt.alpha]
+            prompts = ["alpha.md"]
+
+            [prompt.beta]
+            prompt_path = "relative"
+            prompts = ["beta.md"]
+            "#
+        )
+        .as_str(),
+    );
+
+    let err = PromptAssembler::from_directory(root).expect_err("relative override should fail");
+    let load_err = err.downcast::<LoadConfigError>().expect("load error");
+    match load_err {
+        LoadConfigError::Invalid { diagnostics } => {
+            assert_eq!(diagnostics.errors.len(), 1);
+            assert!(diagnostics.errors[0].message.contains("prompt 'beta'"));
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn settings_apply_to_their_whole_file_and_later_files_only() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.early]
+        prompt_path = "relative"
+        prompts = ["early.md"]
+        "#,
+    );
+    let conf_d = root.join("conf.d");
+    fs::create_dir_all(conf_d.as_std_path()).unwrap();
+    fs::write(
+        conf_d.join("10-strict.toml").as_std_path(),
+        "[prompt.mid]\nprompt_path = \"relative\"\nprompts = [\"mid.md\"]\n\n\
+         [settings]\nrequire_absolute_paths = true\n",
+    )
+    .unwrap();
+    fs::write(
+        conf_d.join("20-late.toml").as_std_path(),
+        "[prompt.late]\nprompt_path = \"relative\"\nprompts = [\"late.md\"]\n",
+    )
+    .unwrap();
+
+    let (assembler, diagnostics) =
+        PromptAssembler::load_valid_prompts(root, &LoadOptions::default())
+            .expect("load valid prompts");
+    assert!(assembler.settings().require_absolute_paths);
+    assert_eq!(assembler.prompt_names().collect::<Vec<_>>(), ["early"]);
+    let failed: Vec<_> = diagnostics
+        .errors
+        .iter()
+        .map(|issue| issue.prompt.as_deref().unwrap_or_default())
+        .collect();
+    assert_eq!(failed, ["mid", "late"]);
+}
+
+#[test]
+fn relative_prompt_path_allowed_by_default() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        prompt_path = "library"
+
+        [prompt.alpha]
+        prompts = ["alpha.md"]
+        "#,
+    );
+    write_file(&root.join("library"), "alpha.md", "Alpha\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler.render_prompt("alpha", &[], None).unwrap();
+    assert_eq!(rendered, "Alpha\n");
+}
+
+#[test]
+fn exact_rendering_preserves_missing_trailing_newline() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.exact]
+        prompts = ["one.md", "two.md"]
+        "#,
+    );
+    write_file(root, "one.md", "One {0}");
+    write_file(root, "two.md", "Two");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let options = RenderOptions {
+        layout: LayoutOptions {
+            exact: true,
+            ..LayoutOptions::default()
+        },
+        ..RenderOptions::default()
+    };
+    let rendered = assembler
+        .render_prompt_with_options("exact", &["x".into()], None, &options)
+        .expect("render exact");
+
+    assert_eq!(rendered, "One xTwo");
+}
+
+#[test]
+fn when_os_skips_prompts_for_other_platforms() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.everywhere]
+        prompts = ["all.md"]
+
+        [prompt.linux-only]
+        when_os = "linux"
+        prompts = ["linux.md"]
+        "#,
+    );
+
+    let options = LoadOptions {
+        os: "macos".to_owned(),
+        ..LoadOptions::default()
+    };
+    let assembler = PromptAssembler::load_with_options(root, &options).expect("load assembler");
+    let names: Vec<_> = assembler.available_prompts().keys().cloned().collect();
+    assert_eq!(names, vec!["everywhere"]);
+
+    let skipped = assembler.skipped_prompts();
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].name, "linux-only");
+    assert!(skipped[0].reason.contains("when_os"));
+
+    let options = LoadOptions {
+        os: "linux".to_owned(),
+        ..LoadOptions::default()
+    };
+    let assembler = PromptAssembler::load_with_options(root, &options).expect("load assembler");
+    assert!(assembler.prompt_spec("linux-only").is_some());
+    assert!(assembler.skipped_prompts().is_empty());
+}
+
+#[test]
+fn prompts_inherit_from_a_base_skipped_by_its_conditions() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.linux-base]
+        when_os = "linux"
+        description = "Shared by every platform"
+        tags = ["ops"]
+        prompts = ["base.md"]
+
+        [prompt.child]
+        base = "linux-base"
+        prompts = ["child.md"]
+        "#,
+    );
+    write_file(root, "child.md", "Child\n");
+
+    let options = LoadOptions {
+        os: "macos".to_owned(),
+        ..LoadOptions::default()
+    };
+    let assembler = PromptAssembler::load_with_options(root, &options).expect("load assembler");
+    assert!(assembler.prompt_spec("linux-base").is_none());
+    assert_eq!(assembler.skipped_prompts()[0].name, "linux-base");
+
+    let child = assembler.prompt_spec("child").expect("child loads");
+    assert_eq!(
+        child.metadata.description.as_deref(),
+        Some("Shared by every platform")
+    );
+    assert_eq!(child.metadata.tags, ["ops"]);
+    assert_eq!(
+        assembler.render_prompt("child", &[], None).unwrap(),
+        "Child\n"
+    );
+}
+
+#[test]
+fn skipped_conditional_prompt_does_not_override_base_definition() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.note]
+        prompts = ["base.md"]
+        "#,
+    );
+    let conf_d = root.join("conf.d");
+    fs::create_dir_all(conf_d.as_std_path()).unwrap();
+    fs::write(
+        conf_d.join("10-windows.toml").as_std_path(),
+        "[prompt.note]\nwhen_os = \"windows\"\nprompts = [\"windows.md\"]\n",
+    )
+    .unwrap();
+    write_file(root, "base.md", "Base\n");
+
+    let options = LoadOptions {
+        os: "linux".to_owned(),
+        ..LoadOptions::default()
+    };
+    let assembler = PromptAssembler::load_with_options(root, &options).expect("load assembler");
+
+    assert!(assembler.config_warnings().is_empty());
+    assert_eq!(
+        assembler.render_prompt("note", &[], None).unwrap(),
+        "Base\n"
+    );
+}
+
+#[test]
+fn renders_from_config_built_in_code() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_file(root, "greet.j2", "Hello {{ name }} from {{ _args[0] }}!\n");
+    write_file(root, "intro.md", "Intro {0}\n");
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"name": "World"}"#).unwrap();
+
+    let mut config = Config::new(root);
+    config.prompts.insert(
+        "greet".to_owned(),
+        PromptSpec::new(PromptKind::Template {
+            template: "greet.j2".into(),
+        }),
+    );
+    config.prompts.insert(
+        "intro".to_owned(),
+        PromptSpec::new(PromptKind::Sequence {
+            files: vec!["intro.md".into()],
+        }),
+    );
+
+    let assembler = PromptAssembler::from_config(config);
+    assert!(assembler.config_warnings().is_empty());
+
+    let rendered = assembler
+        .render_prompt(
+            "greet",
+            &["code".into()],
+            Some(StructuredData::Json(data_path)),
+        )
+        .expect("render template");
+    assert_eq!(rendered, "Hello World from code!\n");
+
+    let rendered = assembler
+        .render_prompt("intro", &["x".into()], None)
+        .expect("render sequence");
+    assert_eq!(rendered, "Intro x\n");
+}
+
+#[derive(Debug, Default)]
+struct MapSource(HashMap<Utf8PathBuf, String>);
+
+impl FragmentSource for MapSource {
+    fn read(&self, path: &Utf8Path) -> prompt_assembler::Result<String> {
+        self.0
+            .get(path)
+            .cloned()
+            .ok_or_else(|| anyhow!("no fragment at {path}"))
+    }
+}
+
+#[test]
+fn reads_fragments_from_custom_source() {
+    let temp = TempDir::new().unwrap();
+    let data_path = utf8_path(temp.path()).join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"name": "World"}"#).unwrap();
+
+    let root = Utf8PathBuf::from("/virtual");
+    let mut source = MapSource::default();
+    source.0.insert(
+        root.join("intro.md"),
+        "Intro {0}\n{{> footer.md }}\n".into(),
+    );
+    source
+        .0
+        .insert(root.join("footer.md"), "-- footer\n".into());
+    source.0.insert(
+        root.join("greet.j2"),
+        "{% include 'header.j2' %}Hello {{ name }}!\n".into(),
+    );
+    source.0.insert(root.join("header.j2"), "# Header\n".into());
+
+    let mut config = Config::new(root.clone());
+    config.prompts.insert(
+        "intro".to_owned(),
+        PromptSpec::new(PromptKind::Sequence {
+            files: vec!["intro.md".into()],
+        }),
+    );
+    config.prompts.insert(
+        "greet".to_owned(),
+        PromptSpec::new(PromptKind::Template {
+            template: "greet.j2".into(),
+        }),
+    );
+    config.prompts.insert(
+        "missing".to_owned(),
+        PromptSpec::new(PromptKind::Sequence {
+            files: vec!["absent.md".into()],
+        }),
+    );
+
+    let assembler = PromptAssembler::from_config_with_source(config, source);
+
+    let rendered = assembler
+        .render_prompt("intro", &["x".into()], None)
+        .expect("render sequence");
+    assert_eq!(rendered, "Intro x\n-- footer\n");
+
+    let rendered = assembler
+        .render_prompt("greet", &[], Some(StructuredData::Json(data_path)))
+        .expect("render template");
+    assert_eq!(rendered, "# Header\nHello World!\n");
+
+    let parts = assembler
+        .assemble_parts(Utf8Path::new("/nowhere"), &["footer.md".into()])
+        .expect("assemble parts");
+    assert_eq!(parts, "-- footer\n");
+
+    let err = assembler
+        .render_prompt("missing", &[], None)
+        .expect_err("missing fragment");
+    assert!(format!("{err:#}").contains("no fragment at /virtual/absent.md"));
+}
+
+#[test]
+fn lossy_setting_replaces_invalid_utf8_with_warning() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[settings]
+lossy_utf8 = true
+
+[prompt.legacy]
+prompts = ["legacy.md"]
+"#,
+    );
+    fs::write(root.join("legacy.md").as_std_path(), b"caf\xe9 {0}\n").unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt_output("legacy", &["ok".into()], None, &RenderOptions::default())
+        .expect("render lossy");
+
+    assert_eq!(rendered.output, "caf\u{fffd} ok\n");
+    assert_eq!(rendered.warnings.len(), 1);
+    assert!(rendered.warnings[0].contains("legacy.md is not valid UTF-8"));
+}
+
+#[test]
+fn pins_hash_raw_bytes_of_lossy_fragments() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[settings]
+lossy_utf8 = true
+
+[prompt.legacy]
+prompts = [{ file = "legacy.md", sha256 = "6e91b61a65983c212eaa9c8db09f378db71ae1a8a96cd90646c9cc593ee276ce" }]
+"#,
+    );
+    fs::write(root.join("legacy.md").as_std_path(), b"A\xff\n").unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt_output("legacy", &[], None, &RenderOptions::default())
+        .expect("raw bytes match the pin");
+    assert_eq!(rendered.output, "A\u{fffd}\n");
+    assert!(assembler.check_pinned_hashes().is_empty());
+}
+
+#[test]
+fn debug_dump_is_stable_and_relative() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[prompt.zeta]
+template = "zeta.j2"
+description = "Last alphabetically"
+vars = [{ name = "topic", required = true }]
+
+[prompt.alpha]
+prompt_path = "snippets"
+prompts = ["one.md", "two.md"]
+tags = ["core"]
+"#,
+    );
+
+    let first = PromptAssembler::from_directory(root)
+        .expect("first load")
+        .debug_dump();
+    let second = PromptAssembler::from_directory(root)
+        .expect("second load")
+        .debug_dump();
+
+    assert_eq!(first, second);
+    assert!(!first.contains(root.as_str()));
+    assert_eq!(
+        first,
+        r#"prompt_path = .
+settings.require_absolute_paths = false
+settings.lossy_utf8 = false
+settings.remote_cache_ttl = -
+settings.git_context = true
+settings.fragment_extensions = -
+settings.macros = []
+settings.default_prompt = -
+settings.audit_log = -
+settings.context_file = -
+settings.global_prepend = -
+settings.global_append = -
+
+[prompt.alpha]
+kind = sequence
+files = [one.md, two.md]
+prompt_path = snippets
+source = config.toml
+tags = [core]
+
+[prompt.zeta]
+kind = template
+template = zeta.j2
+prompt_path = -
+source = config.toml
+description = "Last alphabetically"
+var topic: string required
+"#
+    );
+}
+
+#[test]
+fn placeholder_arity_counts_highest_index() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[prompt.gappy]
+prompts = ["first.md", "second.md"]
+
+[prompt.report]
+template = "report.j2"
+"#,
+    );
+    write_file(root, "first.md", "Use {0} with {{literal}}\n");
+    write_file(root, "second.md", "Then {2}\n");
+    write_file(root, "report.j2", "{{ value }}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    assert_eq!(assembler.placeholder_arity("gappy").unwrap(), Some(3));
+    assert_eq!(assembler.placeholder_arity("report").unwrap(), None);
+    assert!(assembler.placeholder_arity("missing").is_err());
+}
+
+#[test]
+fn conditionally_used_vars_count_as_declared_references() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[prompt.triage]
+template = "triage.j2"
+vars = [
+  { name = "title", required = true },
+  { name = "issue", required = false },
+  { name = "labels", required = false },
+]
+
+[prompt.sloppy]
+template = "sloppy.j2"
+vars = [{ name = "title" }]
+"#,
+    );
+    write_file(
+        root,
+        "triage.j2",
+        "{{ title }}\n{% if issue %}Issue: {{ issue }}\n{% endif %}\
+{% for label in labels %}- {{ label }}\n{% endfor %}{{ _args[0] }}\n",
+    );
+    write_file(
+        root,
+        "sloppy.j2",
+        "{{ title }}{% if extra %}{{ extra }}{% endif %}\n",
+    );
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"title": "Crash"}"#).unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    assert!(assembler.check_templates().is_empty());
+
+    let issues = assembler.check_template_vars();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].prompt.as_deref(), Some("sloppy"));
+    assert!(issues[0].message.contains("'extra'"));
+
+    let rendered = assembler
+        .render_prompt(
+            "triage",
+            &["done".into()],
+            Some(StructuredData::Json(data_path)),
+        )
+        .expect("optional vars may be absent");
+    assert_eq!(rendered, "Crash\ndone\n");
+}
+
+/// Serve `body` with an `ETag` to each connection, answering revalidations with 304.
+/// Returns the base URL and a handle yielding every request line received.
+#[cfg(feature = "remote")]
+fn serve_fragment(
+    body: &'static str,
+    connections: usize,
+) -> (String, std::thread::JoinHandle<Vec<String>>) {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut seen = Vec::new();
+        for stream in listener.incoming().take(connections) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut revalidating = false;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if line
+                    .to_ascii_lowercase()
+                    .starts_with("if-none-match: \"v1\"")
+                {
+                    revalidating = true;
+                }
+                seen.push(line.trim().to_owned());
+            }
+            let response = if revalidating {
+                "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nContent-Length: 0\r\n\r\n".to_owned()
+            } else {
+                format!(
+                    "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: {}\r\n\r\n{body}",
+                    body.len()
+                )
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        seen
+    });
+    (url, handle)
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn fetches_remote_fragments_with_etag_cache() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (url, server) = serve_fragment("Remote {0}\n", 2);
+    write_config(
+        root,
+        &format!("[prompt.shared]\nprompts = [\"local.md\", \"{url}/shared.md\"]\n"),
+    );
+    write_file(root, "local.md", "Local\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    for _ in 0..2 {
+        let rendered = assembler
+            .render_prompt("shared", &["x".into()], None)
+            .expect("render remote");
+        assert_eq!(rendered, "Local\nRemote x\n");
+    }
+
+    let requests = server.join().unwrap();
+    assert!(
+        requests
+            .iter()
+            .any(|line| line == "GET /shared.md HTTP/1.1")
+    );
+    assert!(
+        requests
+            .iter()
+            .any(|line| line.eq_ignore_ascii_case("if-none-match: \"v1\""))
+    );
+
+    // The server is gone; the cached copy still renders.
+    let rendered = assembler
+        .render_prompt("shared", &["y".into()], None)
+        .expect("render from cache");
+    assert_eq!(rendered, "Local\nRemote y\n");
+    assert!(root.join("cache/remote").exists());
+}
+
+/// Answer each connection with the next of `responses`, returning the request line of each.
+#[cfg(feature = "remote")]
+fn serve_responses(responses: Vec<&'static str>) -> (String, std::thread::JoinHandle<Vec<String>>) {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let handle = std::thread::spawn(move || {
+        let mut seen = Vec::new();
+        // Responses come first so the listener stops once they run out.
+        for (response, stream) in responses.into_iter().zip(listener.incoming()) {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            reader.read_line(&mut request).unwrap();
+            seen.push(request.trim().to_owned());
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        seen
+    });
+    (url, handle)
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn remote_error_statuses_fall_back_to_cache_and_exists_sends_head() {
+    use prompt_assembler::{FileSystemSource, RemoteSource};
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let (url, server) = serve_responses(vec![
+        "HTTP/1.1 200 OK\r\nContent-Length: 7\r\n\r\nRemote\n",
+        "HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+    ]);
+    let source = RemoteSource::new(FileSystemSource, root.join("cache"));
+    let shared = Utf8PathBuf::from(format!("{url}/shared.md"));
+
+    assert_eq!(source.read(&shared).unwrap(), "Remote\n");
+    assert_eq!(
+        source.read(&shared).expect("HTTP 500 falls back to cache"),
+        "Remote\n"
+    );
+    assert!(
+        source.exists(&shared),
+        "cached URLs exist without a request"
+    );
+    assert!(source.exists(&Utf8PathBuf::from(format!("{url}/other.md"))));
+    assert!(!source.exists(&Utf8PathBuf::from(format!("{url}/missing.md"))));
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests,
+        [
+            "GET /shared.md HTTP/1.1",
+            "GET /shared.md HTTP/1.1",
+            "HEAD /other.md HTTP/1.1",
+            "HEAD /missing.md HTTP/1.1",
+        ]
+    );
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn offline_mode_requires_cached_remote_fragments() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    let (url, server) = serve_fragment("Remote\n", 1);
+    write_config(
+        root,
+        &format!("[prompt.shared]\nprompts = [\"{url}/shared.md\"]\n"),
+    );
+    let offline = LoadOptions {
+        offline: true,
+        ..LoadOptions::default()
+    };
+
+    let cold = PromptAssembler::load_with_options(root, &offline).expect("load assembler");
+    let err = cold.render_prompt("shared", &[], None).unwrap_err();
+    assert!(format!("{err:#}").contains("is not cached"), "{err:#}");
+
+    let online = PromptAssembler::from_directory(root).expect("load assembler");
+    assert_eq!(
+        online.render_prompt("shared", &[], None).unwrap(),
+        "Remote\n"
+    );
+    server.join().unwrap();
+
+    let warm = PromptAssembler::load_with_options(root, &offline).expect("load assembler");
+    assert_eq!(warm.render_prompt("shared", &[], None).unwrap(), "Remote\n");
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn remote_cache_ttl_skips_revalidation() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    let (url, server) = serve_fragment("Remote\n", 1);
+    write_config(
+        root,
+        &format!(
+            "[settings]\nremote_cache_ttl = 3600\n\n[prompt.shared]\nprompts = [\"{url}/shared.md\"]\n"
+        ),
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    assert!(
+        assembler
+            .debug_dump()
+            .contains("settings.remote_cache_ttl = 3600")
+    );
+    for _ in 0..3 {
+        assert_eq!(
+            assembler.render_prompt("shared", &[], None).unwrap(),
+            "Remote\n"
+        );
+    }
+
+    let requests = server.join().unwrap();
+    assert!(
+        !requests
+            .iter()
+            .any(|line| line.to_ascii_lowercase().starts_with("if-none-match"))
+    );
+}
+
+#[test]
+fn prompt_names_preserve_config_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.zeta]
+            prompts = ["z.md"]
+
+            [prompt.alpha]
+            prompts = ["a.md"]
+
+            [prompt.mid]
+            prompts = ["m.md"]
+            "#
+        ),
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let names: Vec<_> = assembler.prompt_names().collect();
+
+    assert_eq!(names, vec!["zeta", "alpha", "mid"]);
+}
+
+#[test]
+fn reporting_diagnostics_returns_errors_and_warnings_together() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.alpha]
+            prompts = ["a.md"]
+            "#
+        ),
+    );
+    write_file(
+        root,
+        "conf.d/10-more.toml",
+        "[prompt.alpha]\nprompts = [\"b.md\"]\n\n[prompt.empty]\nprompts = []\n",
+    );
+
+    let (assembler, diagnostics) =
+        PromptAssembler::load_reporting_diagnostics(root, &LoadOptions::default());
+
+    assert!(assembler.is_none());
+    assert!(
+        diagnostics
+            .errors
+            .iter()
+            .any(|issue| issue.message.contains("prompt sequence cannot be empty"))
+    );
+    assert!(
+        diagnostics
+            .warnings
+            .iter()
+            .any(|issue| issue.code == ConfigIssueCode::Override)
+    );
+}
+
+#[test]
+fn directory_entries_expand_to_allowed_extensions() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [settings]
+            fragment_extensions = ["md"]
+
+            [prompt.bundle]
+            prompts = ["intro.md", "sections"]
+            "#
+        ),
+    );
+    write_file(&library_dir, "intro.md", "Intro\n");
+    write_file(&library_dir, "sections/02-second.md", "Second\n");
+    write_file(&library_dir, "sections/01-first.md", "First\n");
+    write_file(&library_dir, "sections/diagram.png", "not text\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("bundle", &[], None)
+        .expect("render bundle");
+    assert_eq!(rendered, "Intro\nFirst\nSecond\n");
+
+    let parts = assembler
+        .read_parts(root, &["library/sections".to_owned()])
+        .expect("read directory part");
+    let names: Vec<_> = parts
+        .iter()
+        .map(|part| part.path.file_name().unwrap().to_owned())
+        .collect();
+    assert_eq!(names, vec!["01-first.md", "02-second.md"]);
+}
+
+#[test]
+fn repeat_for_args_renders_body_once_per_trailing_argument() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.review]
+            prompts = ["header.md"]
+            repeat_for_args = "body.md"
+            "#
+        ),
+    );
+    write_file(&library_dir, "header.md", "Review for {0}:\n");
+    write_file(&library_dir, "body.md", "- check {item}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let args = vec!["alice".to_owned(), "a.rs".to_owned(), "b.rs".to_owned()];
+    let rendered = assembler
+        .render_prompt("review", &args, None)
+        .expect("render review");
+
+    assert_eq!(rendered, "Review for alice:\n- check a.rs\n- check b.rs\n");
+}
+
+#[test]
+fn template_render_errors_report_line_and_column() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.broken]
+            template = "broken.j2"
+            "#
+        ),
+    );
+    write_file(
+        &library_dir,
+        "broken.j2",
+        "Title\n\nValue: {{ missing.attr }}\n",
+    );
+    let data_path = library_dir.join("data.json");
+    fs::write(data_path.as_std_path(), "{}").unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let err = assembler
+        .render_prompt("broken", &[], Some(StructuredData::Json(data_path)))
+        .expect_err("render should fail");
+
+    let render_err = err.downcast_ref::<RenderError>().expect("render error");
+    assert_eq!(render_err.template, "broken.j2");
+    assert_eq!(render_err.line, Some(3));
+    assert!(format!("{err:#}").contains("template broken.j2:3:"));
+}
+
+#[test]
+fn prompts_inherit_metadata_from_base() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.review]
+            base = "common"
+            prompts = ["review.md"]
+            tags = ["review"]
+            vars = [{{ name = "lang", required = true }}]
+
+            [prompt.common]
+            abstract = true
+            description = "Shared defaults"
+            tags = ["team"]
+            stdin = false
+            vars = [{{ name = "lang" }}, {{ name = "tone" }}]
+            "#
+        ),
+    );
+    write_file(&library_dir, "review.md", "Review\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let names: Vec<_> = assembler.prompt_names().collect();
+    assert_eq!(names, vec!["review"]);
+
+    let metadata = &assembler.prompt_spec("review").unwrap().metadata;
+    assert_eq!(metadata.tags, vec!["team", "review"]);
+    assert_eq!(metadata.description.as_deref(), Some("Shared defaults"));
+    assert_eq!(metadata.stdin_supported, Some(false));
+    let vars: Vec<_> = metadata
+        .vars
+        .iter()
+        .map(|var| (var.name.as_str(), var.required))
+        .collect();
+    assert_eq!(vars, vec![("lang", true), ("tone", false)]);
+}
+
+#[test]
+fn config_errors_on_base_cycle() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.first]
+        base = "second"
+        prompts = ["a.md"]
+
+        [prompt.second]
+        base = "first"
+        prompts = ["b.md"]
+        "#,
+    );
+
+    let err = PromptAssembler::load_with_diagnostics(root).expect_err("cycle should fail");
+    let LoadConfigError::Invalid { diagnostics } = err else {
+        panic!("unexpected error: {err}");
+    };
+    assert!(
+        diagnostics
+            .errors
+            .iter()
+            .any(|issue| issue.message.contains("first -> second -> first"))
+    );
+}
+
+#[test]
+fn toml_tables_iterate_in_sorted_key_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.table]
+            template = "table.j2"
+            "#
+        ),
+    );
+    write_file(
+        &library_dir,
+        "table.j2",
+        "{% for key, value in items | items %}{{ key }}={{ value.n }} {% endfor %}\n",
+    );
+    let data_path = root.join("data.toml");
+    fs::write(
+        data_path.as_std_path(),
+        "[items.zeta]\nn = 1\n\n[items.alpha]\nn = 2\n\n[items.mid]\nn = 3\n",
+    )
+    .unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    for _ in 0..3 {
+        let rendered = assembler
+            .render_prompt("table", &[], Some(StructuredData::Toml(data_path.clone())))
+            .expect("render table");
+        assert_eq!(rendered, "alpha=2 mid=3 zeta=1 \n");
+    }
+}
+
+#[test]
+fn inline_prompt_renders_without_files() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.hi]
+        inline = "Hello {0}!"
+        "#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("hi", &["world".to_string()], None)
+        .expect("render inline prompt");
+    assert_eq!(rendered, "Hello world!\n");
+}
+
+#[test]
+fn inline_template_renders_with_json_data() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.greet]
+        template_inline = "Hello {{ name }}"
+        "#,
+    );
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"name": "Ada"}"#).unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("greet", &[], Some(StructuredData::Json(data_path)))
+        .expect("render inline template");
+    assert_eq!(rendered, "Hello Ada");
+}
+
+#[test]
+fn templates_auto_import_macros_from_glob() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [settings]
+            macros = ["macros/*.j2"]
+
+            [prompt.greet]
+            template = "greet.j2"
+            vars = [{{ name = "name" }}]
+            "#
+        ),
+    );
+    write_file(
+        &library_dir,
+        "macros/text.j2",
+        "{% macro shout(value) %}{{ value | upper }}!{% endmacro %}",
+    );
+    write_file(&library_dir, "greet.j2", "Hello {{ text.shout(name) }}\n");
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"name": "ada"}"#).unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("greet", &[], Some(StructuredData::Json(data_path)))
+        .expect("render greet");
+    assert_eq!(rendered, "Hello ADA!\n");
+    assert!(assembler.check_template_vars().is_empty());
+    assert!(assembler.check_templates().is_empty());
+}
+
+#[test]
+fn macros_sharing_a_stem_are_reported() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+        [settings]
+        macros = ["macros/**/*.j2"]
+
+        [prompt.greet]
+        template_inline = "Hi\n"
+        "#,
+    );
+    write_file(root, "macros/text.j2", "{% macro a() %}a{% endmacro %}");
+    write_file(
+        root,
+        "macros/extra/text.j2",
+        "{% macro b() %}b{% endmacro %}",
+    );
+
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), "{}").unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let err = assembler
+        .render_prompt("greet", &[], Some(StructuredData::Json(data_path)))
+        .expect_err("colliding aliases fail");
+    assert!(
+        format!("{err:#}").contains(
+            "macros 'macros/extra/text.j2' and 'macros/text.j2' would both be imported as 'text'"
+        ),
+        "{err:#}"
+    );
+    let issues = assembler.check_templates();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].prompt.as_deref(), Some("greet"));
+}
+
+#[test]
+fn dedent_strips_common_indentation() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.steps]
+            prompts = ["steps.md"]
+            dedent = true
+            "#
+        ),
+    );
+    write_file(
+        &library_dir,
+        "steps.md",
+        "    Steps:\n\n      1. {0}\n      2. verify\n    Done\n",
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("steps", &["build".to_string()], None)
+        .expect("render steps");
+    assert_eq!(rendered, "Steps:\n\n  1. build\n  2. verify\nDone\n");
+}
+
+#[test]
+fn render_all_yields_each_prompt_result() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.hi]
+        inline = "Hi {0}"
+
+        [prompt.report]
+        template_inline = "{{ title }}"
+        "#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let mut seen = Vec::new();
+    let results: Vec<_> = assembler
+        .render_all(|name| {
+            seen.push(name.to_owned());
+            (vec!["there".to_string()], None)
+        })
+        .collect();
+
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].0, "hi");
+    assert_eq!(results[0].1.as_ref().unwrap(), "Hi there\n");
+    assert_eq!(results[1].0, "report");
+    let err = results[1].1.as_ref().unwrap_err();
+    assert!(err.to_string().contains("requires a data file"));
+    assert_eq!(seen, ["hi", "report"]);
+}
+
+#[test]
+fn working_dir_sets_fragment_root_for_sequence() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    let project_dir = root.join("project");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+    fs::create_dir_all(project_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.notes]
+            prompts = ["notes.md"]
+            working_dir = "{project_dir}"
+            "#
+        ),
+    );
+    write_file(&library_dir, "notes.md", "library notes\n");
+    write_file(&project_dir, "notes.md", "project notes for {0}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("notes", &["today".to_string()], None)
+        .expect("render notes");
+    assert_eq!(rendered, "project notes for today\n");
+}
+
+#[test]
+fn pinned_fragments_fail_on_hash_mismatch() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.policy]
+            prompts = [
+              {{ file = "policy.md", sha256 = "CA678DBBD745CA79D3D20C284C53ADD9918744CE287808CA5B2B1AE57BDE0F3F" }},
+              "ask.md",
+            ]
+            "#
+        ),
+    );
+    write_file(&library_dir, "policy.md", "Policy v1\n");
+    write_file(&library_dir, "ask.md", "Ask {0}\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("policy", &["away".to_string()], None)
+        .expect("render pinned prompt");
+    assert_eq!(rendered, "Policy v1\nAsk away\n");
+    assert!(assembler.check_pinned_hashes().is_empty());
+
+    write_file(&library_dir, "policy.md", "Policy v2\n");
+    let err = assembler
+        .render_prompt("policy", &["away".to_string()], None)
+        .expect_err("edited fragment should fail");
+    let message = format!("{err:#}");
+    assert!(message.contains(
+        "expected ca678dbbd745ca79d3d20c284c53add9918744ce287808ca5b2b1ae57bde0f3f, found "
+    ));
+    let issues = assembler.check_pinned_hashes();
+    assert_eq!(issues.len(), 1);
+    assert_eq!(issues[0].code, ConfigIssueCode::HashMismatch);
+}
+
+#[test]
+fn skipped_missing_fragments_are_reported_as_warnings() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+        [prompt.draft]
+        prompts = ["one.md", "absent.md", "two.md"]
+        "#,
+    );
+    write_file(root, "one.md", "One\n");
+    write_file(root, "two.md", "Two\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let render = |on_missing| {
+        let options = RenderOptions {
+            on_missing,
+            ..RenderOptions::default()
+        };
+        assembler.render_prompt_output("draft", &[], None, &options)
+    };
+
+    let skipped = render(MissingFragmentPolicy::Skip).expect("skip missing fragment");
+    assert_eq!(skipped.output, "One\nTwo\n");
+    assert_eq!(
+        skipped.warnings,
+        ["skipped missing fragment 'absent.md' for prompt 'draft'"]
+    );
+
+    let emptied = render(MissingFragmentPolicy::Empty).expect("empty missing fragment");
+    assert_eq!(emptied.output, "One\nTwo\n");
+    assert!(emptied.warnings.is_empty());
+
+    render(MissingFragmentPolicy::Error).expect_err("missing fragment should fail");
+}
+
+#[test]
+fn missing_and_directory_pins_are_reported_as_such() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let pin = "ca678dbbd745ca79d3d20c284c53add9918744ce287808ca5b2b1ae57bde0f3f";
+    write_config(
+        root,
+        &format!(
+            r#"
+            [prompt.policy]
+            prompts = [{{ file = "policy.md", sha256 = "{pin}" }}, "ask.md"]
+
+            [prompt.bundle]
+            prompts = [{{ file = "notes", sha256 = "{pin}" }}]
+            "#
+        ),
+    );
+    write_file(root, "ask.md", "Ask\n");
+    write_file(root, "notes/a.md", "A\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let options = RenderOptions {
+        on_missing: MissingFragmentPolicy::Empty,
+        ..RenderOptions::default()
+    };
+    let err = assembler
+        .render_prompt_with_options("policy", &[], None, &options)
+        .expect_err("missing pinned fragment should fail");
+    assert!(
+        err.to_string()
+            .contains("pinned fragment 'policy.md' for prompt 'policy' is missing"),
+        "{err:#}"
+    );
+
+    let issues = assembler.check_pinned_hashes();
+    assert!(
+        issues
+            .iter()
+            .any(|issue| issue.code == ConfigIssueCode::ReadError
+                && issue.prompt.as_deref() == Some("policy"))
+    );
+
+    let err = assembler
+        .render_prompt("bundle", &[], None)
+        .expect_err("a pinned directory cannot be verified");
+    assert!(
+        err.to_string()
+            .contains("prompt 'bundle' pins directory entry 'notes'"),
+        "{err:#}"
+    );
+
+    write_config(
+        root,
+        &format!("[prompt.bundle]\nprompts = [{{ file = \"notes/\", sha256 = \"{pin}\" }}]\n"),
+    );
+    let err = PromptAssembler::from_directory(root).expect_err("directory pins are rejected");
+    match err.downcast::<LoadConfigError>().expect("load error") {
+        LoadConfigError::Invalid { diagnostics } => {
+            assert!(
+                diagnostics
+                    .errors
+                    .iter()
+                    .any(|issue| issue.message.contains("pins directory entry 'notes/'"))
+            );
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn misspelled_sequence_entry_keys_are_rejected() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_file(root, "a.md", "A\n");
+    write_config(
+        root,
+        "[prompt.pinned]\nprompts = [{ file = \"a.md\", sha265 = \"00\" }]\n",
+    );
+
+    let err = PromptAssembler::from_directory(root).expect_err("unknown entry key is rejected");
+    match err.downcast::<LoadConfigError>().expect("load error") {
+        LoadConfigError::Invalid { diagnostics } => {
+            assert!(!diagnostics.errors.is_empty());
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn templates_escape_html_only_when_autoescape_is_set() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    let library_dir = root.join("library");
+    fs::create_dir_all(library_dir.as_std_path()).unwrap();
+
+    write_config(
+        root,
+        &format!(
+            r#"
+            prompt_path = "{library_dir}"
+
+            [prompt.plain]
+            template = "page.html"
+
+            [prompt.escaped]
+            template = "page.html"
+            autoescape = true
+            "#
+        ),
+    );
+    write_file(&library_dir, "page.html", "{{ \"a & b\" }}\n");
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), "{}").unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let data = || Some(StructuredData::Json(data_path.clone()));
+    let plain = assembler
+        .render_prompt("plain", &[], data())
+        .expect("render plain");
+    assert_eq!(plain, "a & b\n");
+    let escaped = assembler
+        .render_prompt("escaped", &[], data())
+        .expect("render escaped");
+    assert_eq!(escaped, "a &amp; b\n");
+}
+
+#[test]
+fn replace_map_rewrites_output_in_declaration_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.status]
+        inline = "TODO: {0}"
+        replace = { "TODO" = "DONE", "DONE: " = "done - " }
+        "#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("status", &["ship it".to_string()], None)
+        .expect("render status");
+    assert_eq!(rendered, "done - ship it\n");
+}
+
+struct MockKeyring(HashMap<(String, String), String>);
+
+impl SecretStore for MockKeyring {
+    fn get(&self, service: &str, account: &str) -> prompt_assembler::Result<Option<String>> {
+        Ok(self
+            .0
+            .get(&(service.to_owned(), account.to_owned()))
+            .cloned())
+    }
+}
+
+#[test]
+fn secrets_reach_templates_but_not_context_dumps() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [prompt.call]
+        template_inline = "token={{ token }} user={{ user }}"
+        "#,
+    );
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"user": "ada"}"#).unwrap();
+
+    let keyring = MockKeyring(HashMap::from([(
+        ("api".to_owned(), "ada".to_owned()),
+        "s3cret".to_owned(),
+    )]));
+    let mut options = RenderOptions::default();
+    let secret: SecretRef = "token=api/ada".parse().expect("parse secret");
+    options
+        .insert_secret(&secret, &keyring)
+        .expect("insert secret");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let data = || Some(StructuredData::Json(data_path.clone()));
+    let rendered = assembler
+        .render_prompt_with_options("call", &[], data(), &options)
+        .expect("render with secret");
+    assert_eq!(rendered, "token=s3cret user=ada");
+
+    let context = assembler
+        .dump_context("call", &[], data(), &options)
+        .expect("dump context");
+    assert_eq!(context["token"], serde_json::Value::from("<redacted>"));
+    assert_eq!(context["user"], serde_json::Value::from("ada"));
+
+    let missing: SecretRef = "other=api/grace".parse().expect("parse secret");
+    let err = options.insert_secret(&missing, &keyring).unwrap_err();
+    assert!(err.to_string().contains("secret 'other' not found"));
+}
+
+#[cfg(unix)]
+#[test]
+fn conf_d_symlink_cycles_are_reported_not_followed() {
+    use std::os::unix::fs::symlink;
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(root, "[prompt.alpha]\ninline = \"a\"\n");
+    write_file(root, "conf.d/10-ok.toml", "[prompt.beta]\ninline = \"b\"\n");
+    let conf_d = root.join("conf.d");
+    symlink(conf_d.join("b.toml"), conf_d.join("a.toml")).unwrap();
+    symlink(conf_d.join("a.toml"), conf_d.join("b.toml")).unwrap();
+    symlink(&conf_d, conf_d.join("loop.toml")).unwrap();
+    symlink(&conf_d, conf_d.join("nested")).unwrap();
+
+    let (assembler, diagnostics) =
+        PromptAssembler::load_reporting_diagnostics(root, &LoadOptions::default());
+
+    assert!(assembler.is_none());
+    let cycles: Vec<_> = diagnostics
+        .errors
+        .iter()
+        .filter(|issue| issue.code == ConfigIssueCode::ParseError)
+        .collect();
+    assert_eq!(cycles.len(), 4, "{:?}", diagnostics.errors);
+    let messages: Vec<&str> = cycles.iter().map(|issue| issue.message.as_str()).collect();
+    assert_eq!(
+        messages
+            .iter()
+            .filter(|message| message.starts_with("cannot follow"))
+            .count(),
+        2
+    );
+    assert_eq!(
+        messages
+            .iter()
+            .filter(|message| message.starts_with("symlink loop"))
+            .count(),
+        2
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn conf_d_directory_reached_twice_is_not_a_loop() {
+    use std::os::unix::fs::symlink;
+
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(root, "[prompt.alpha]\ninline = \"a\"\n");
+    write_file(root, "shared/team.toml", "[prompt.team]\ninline = \"t\"\n");
+    write_file(root, "conf.d/.keep", "");
+    let conf_d = root.join("conf.d");
+    symlink(root.join("shared"), conf_d.join("first")).unwrap();
+    symlink(root.join("shared"), conf_d.join("second")).unwrap();
+
+    let (assembler, diagnostics) =
+        PromptAssembler::load_reporting_diagnostics(root, &LoadOptions::default());
+
+    assert!(diagnostics.errors.is_empty(), "{:?}", diagnostics.errors);
+    assert!(
+        diagnostics.warnings.is_empty(),
+        "{:?}",
+        diagnostics.warnings
+    );
+    let assembler = assembler.expect("diamond loads");
+    assert_eq!(
+        assembler.prompt_spec("team").unwrap().metadata.source.path,
+        conf_d.join("first/team.toml")
+    );
+}
+
+#[test]
+fn conf_d_subdirectories_load_in_path_order() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(root, "[prompt.base]\ninline = \"base\"\n");
+    write_file(
+        root,
+        "conf.d/team-b/x.toml",
+        "[prompt.shared]\ninline = \"b\"\n",
+    );
+    write_file(
+        root,
+        "conf.d/team-a/x.toml",
+        "[prompt.shared]\ninline = \"a\"\n",
+    );
+    write_file(
+        root,
+        "conf.d/sub/deeper/x.toml",
+        "[prompt.nested]\ninline = \"nested\"\n",
+    );
+    write_file(root, "conf.d/sub/notes.txt", "not config");
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    assert_eq!(
+        assembler.prompt_names().collect::<Vec<_>>(),
+        ["base", "nested", "shared"]
+    );
+    let rendered = assembler
+        .render_prompt("shared", &[], None)
+        .expect("render shared");
+    assert_eq!(rendered, "b\n");
+}
+
+#[test]
+fn public_substitute_matches_sequence_semantics() {
+    let args = vec!["Ada".to_string(), "Grace".to_string()];
+    assert_eq!(
+        substitute("{1} and {0} {{braces}}", &args).unwrap(),
+        "Grace and Ada {braces}"
+    );
+    let err = substitute("{2}", &args).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("missing argument for placeholder {2}")
+    );
+
+    let named = std::collections::BTreeMap::from([("team".to_string(), "core".to_string())]);
+    assert_eq!(
+        substitute_named("{0} joins {team}", &args, &named).unwrap(),
+        "Ada joins core"
+    );
+    assert!(substitute("{team}", &args).is_err());
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn renders_sequence_from_zip_prompt_path() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    let archive = fs::File::create(root.join("library.zip").as_std_path()).unwrap();
+    let mut zip = zip::ZipWriter::new(archive);
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("intro.md", options).unwrap();
+    zip.write_all(b"Hello {0}\n").unwrap();
+    zip.start_file("shared/outro.md", options).unwrap();
+    zip.write_all(b"Bye\n").unwrap();
+    zip.finish().unwrap();
+
+    write_config(
+        root,
+        r#"
+        prompt_path = "library.zip"
+
+        [prompt.greet]
+        prompts = ["intro.md", "shared/outro.md"]
+
+        [prompt.broken]
+        prompts = ["missing.md"]
+        "#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("greet", &["Ada".to_string()], None)
+        .expect("render from zip");
+    assert_eq!(rendered, "Hello Ada\nBye\n");
+
+    let err = assembler.render_prompt("broken", &[], None).unwrap_err();
+    assert!(format!("{err:#}").contains("missing.md"));
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn directory_entries_expand_inside_zip_prompt_path() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    let archive = fs::File::create(root.join("library.zip").as_std_path()).unwrap();
+    let mut zip = zip::ZipWriter::new(archive);
+    let options = zip::write::SimpleFileOptions::default();
+    for (name, body) in [
+        ("notes/b.md", "B\n"),
+        ("notes/a.md", "A\n"),
+        ("notes/.hidden", "H\n"),
+        ("notes/deep/c.md", "C\n"),
+    ] {
+        zip.start_file(name, options).unwrap();
+        zip.write_all(body.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    write_config(
+        root,
+        r#"
+        prompt_path = "library.zip"
+
+        [prompt.notes]
+        prompts = ["notes"]
+        "#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("notes", &[], None)
+        .expect("render directory from zip");
+    assert_eq!(rendered, "A\nB\n");
+}
+
+#[cfg(feature = "archive")]
+#[test]
+fn macros_are_imported_from_zip_prompt_path() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    let archive = fs::File::create(root.join("library.zip").as_std_path()).unwrap();
+    let mut zip = zip::ZipWriter::new(archive);
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("macros/text.j2", options).unwrap();
+    zip.write_all(b"{% macro shout(value) %}{{ value | upper }}!{% endmacro %}")
+        .unwrap();
+    zip.start_file("greet.j2", options).unwrap();
+    zip.write_all(b"Hello {{ text.shout(name) }}\n").unwrap();
+    zip.finish().unwrap();
+
+    write_config(
+        root,
+        r#"
+        prompt_path = "library.zip"
+
+        [settings]
+        macros = ["macros/*.j2"]
+
+        [prompt.greet]
+        template = "greet.j2"
+        "#,
+    );
+
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"name": "ada"}"#).unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("greet", &[], Some(StructuredData::Json(data_path)))
+        .expect("render macros from zip");
+    assert_eq!(rendered, "Hello ADA!\n");
+}
+
+#[test]
+fn context_file_is_the_lowest_precedence_template_context() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+
+    write_config(
+        root,
+        r#"
+        [settings]
+        context_file = "common.toml"
+
+        [prompt.sign]
+        template_inline = "{{ author }} at {{ org }}"
+        "#,
+    );
+    write_file(root, "common.toml", "org = \"Acme\"\nauthor = \"Team\"\n");
+    let data_path = root.join("data.json");
+    fs::write(data_path.as_std_path(), r#"{"author": "Ada"}"#).unwrap();
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let rendered = assembler
+        .render_prompt("sign", &[], Some(StructuredData::Json(data_path)))
+        .expect("render with shared context");
+    assert_eq!(rendered, "Ada at Acme");
+}
+
+#[test]
+fn missing_inputs_lists_unsupplied_vars_and_their_requirement() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"[prompt.ship]
+template_inline = "{{ service }} {{ notes }}"
+vars = [
+  { name = "service", required = true },
+  { name = "urgent", type = "boolean" },
+  { name = "notes", required_if = "urgent" },
+]
+"#,
+    );
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+
+    let mut options = RenderOptions::default();
+    options.context.insert("urgent".into(), true.into());
+    let missing = assembler
+        .missing_inputs("ship", &[], None, &options)
+        .expect("missing inputs");
+    let missing: Vec<(&str, bool)> = missing
+        .iter()
+        .map(|input| (input.var.name.as_str(), input.required))
+        .collect();
+    assert_eq!(missing, [("service", true), ("notes", true)]);
+
+    assert_eq!(assembler.input_requirements("ship").unwrap().len(), 3);
+    assert!(assembler.input_requirements("nope").is_err());
+}
+
+#[test]
+fn check_templates_compiles_inline_templates() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[prompt.broken]
+template_inline = "{% for item in items %}{{ item }}"
+
+[prompt.fine]
+template_inline = "{{ value }}"
+"#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let issues = assembler.check_templates();
+    assert_eq!(issues.len(), 1, "{issues:?}");
+    assert_eq!(issues[0].code, ConfigIssueCode::InvalidPrompt);
+    assert_eq!(issues[0].prompt.as_deref(), Some("broken"));
+    assert_eq!(issues[0].path, root.join("config.toml"));
+}
+
+#[test]
+fn check_template_vars_covers_inline_templates() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        r#"
+[prompt.note]
+template_inline = "{{ title }} {{ author }}"
+vars = [{ name = "title" }]
+"#,
+    );
+
+    let assembler = PromptAssembler::from_directory(root).expect("load assembler");
+    let issues = assembler.check_template_vars();
+    assert_eq!(issues.len(), 1, "{issues:?}");
+    assert_eq!(issues[0].code, ConfigIssueCode::UndeclaredVar);
+    assert_eq!(issues[0].path, root.join("config.toml"));
+    assert!(issues[0].message.contains("'author'"), "{issues:?}");
+}
+
+#[test]
+fn kind_specific_keys_warn_when_harmless_and_fail_otherwise() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        "[prompt.plain]\nprompts = [\"a.md\"]\nautoescape = false\n",
+    );
+    write_file(root, "a.md", "a & b\n");
+
+    let assembler = PromptAssembler::from_directory(root).expect("ignored key does not fail");
+    let warnings = assembler.config_warnings();
+    assert_eq!(warnings.len(), 1, "{warnings:?}");
+    assert_eq!(warnings[0].code, ConfigIssueCode::IgnoredKey);
+    assert_eq!(
+        warnings[0].message,
+        "prompt 'plain' sets autoescape, which sequence prompts do not use; it is ignored"
+    );
+
+    write_config(
+        root,
+        "[prompt.plain]\nprompts = [\"a.md\"]\ndata = \"data.json\"\n",
+    );
+    let err = PromptAssembler::from_directory(root).expect_err("data on a sequence fails");
+    match err.downcast::<LoadConfigError>().expect("load error") {
+        LoadConfigError::Invalid { diagnostics } => {
+            assert!(
+                diagnostics
+                    .errors
+                    .iter()
+                    .any(|issue| issue.message.contains("only template prompts support"))
+            );
+        }
+        other => panic!("unexpected error: {other}"),
+    }
+}
+
+#[test]
+fn suggest_prompts_returns_close_names_only() {
+    let temp = TempDir::new().unwrap();
+    let root = utf8_path(temp.path());
+    write_config(
+        root,
+        "[prompt.review]\nprompts = [\"a.md\"]\n\n[prompt.preview]\nprompts = [\"a.md\"]\n\n[prompt.Troubleshoot]\nprompts = [\"a.md\"]\n",
+    );
+    write_file(root, "a.md", "a\n");
+
+    let assembler = PromptAssembler::from_directory(root).unwrap();
+    assert_eq!(assembler.suggest_prompts("reviews"), ["review", "preview"]);
+    assert_eq!(assembler.suggest_prompts("troubleshoot"), ["Troubleshoot"]);
+    assert!(assembler.suggest_prompts("deploy").is_empty());
+}